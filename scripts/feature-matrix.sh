@@ -0,0 +1,21 @@
+#!/bin/sh
+# Checks that every feature combination compiles without warnings and that its tests pass.
+set -eu
+
+cd "$(dirname "$0")/.."
+
+for features in \
+    "--no-default-features" \
+    "--no-default-features --features serde" \
+    "--no-default-features --features csv" \
+    "--no-default-features --features testutil" \
+    "--no-default-features --features testkit" \
+    "" \
+    "--features postgres"
+do
+    echo "==> cargo clippy/test ${features:-(default features)}"
+    # shellcheck disable=SC2086
+    cargo clippy --all-targets $features -- -D warnings
+    # shellcheck disable=SC2086
+    cargo test $features
+done
@@ -1,348 +1,3024 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
+use chrono::{DateTime, NaiveDate, Utc};
 use indexmap::IndexMap;
-use rust_decimal::Decimal;
+use rust_decimal::{Decimal, RoundingStrategy};
 
-use crate::transaction::{Dispute, Transaction, TransactionId, TransactionKind};
+use crate::{
+    engine::{ClientHasher, DuplicateDisputePolicy, EngineConfig, InterestPolicy},
+    error::TransactionError,
+    transaction::{
+        ClientId, Currency, Dispute, DisputeState, Transaction, TransactionId, TransactionKind,
+    },
+};
 
-/// The current state of a client's asset and transaction history.
-#[derive(Eq, PartialEq)]
-pub struct Account {
+/// Decimal places of the balances when [`EngineConfig::round_each_op`] is set.
+const ROUNDED_DECIMAL_PLACES: u32 = 4;
+
+/// Rounds `value` to [`ROUNDED_DECIMAL_PLACES`], rounding midpoints to even.
+pub fn round_amount(value: Decimal) -> Decimal {
+    value.round_dp_with_strategy(
+        ROUNDED_DECIMAL_PLACES,
+        RoundingStrategy::MidpointNearestEven,
+    )
+}
+
+/// Position in the run of a transaction accepted by an account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Activity {
+    /// Sequence number of the transaction among all the transactions of the run, from 1.
+    pub sequence: u64,
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+/// Chargeback that locked an account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockInfo {
+    /// Transaction charged back.
+    pub tx: TransactionId,
+    /// Sequence number of the chargeback among all the transactions of the run, see
+    /// [`Account::try_process_at`]. For an account processing transactions on its own, the
+    /// number of transactions it had processed, including the chargeback.
+    pub seq: u64,
+    /// Timestamp of the chargeback, if known.
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+/// Interest credited to an account for the whole days between `from` and `to`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterestEntry {
+    pub amount: Decimal,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    /// Number of transactions in the history when the interest was credited.
+    pub after: usize,
+}
+
+/// Entry of the ledger of an account: its history along with the interest credits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LedgerEntry<'a> {
+    Transaction(&'a Transaction),
+    Interest(&'a InterestEntry),
+}
+
+/// Effect of a transaction processed by an account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessOutcome {
+    /// The transaction changed the account.
+    Applied,
+    /// The transaction is invalid for the account, e.g. a dispute of an unknown
+    /// transaction, and was ignored.
+    Ignored,
+}
+
+/// Counters of the accepted transactions of an account, read by the risk report. They
+/// never change how transactions are processed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RiskCounters {
+    /// Number of deposits.
+    pub deposits: u64,
+    /// Number of disputes, counting every dispute of a transaction disputed again.
+    pub disputes: u64,
+    /// Number of chargebacks.
+    pub chargebacks: u64,
+    /// Every dispute, with the number of transactions the account processed from its
+    /// deposit to it, e.g. 1 for a dispute right after the deposit.
+    pub dispute_delays: Vec<(TransactionId, u64)>,
+    /// Number of disputes of every disputed transaction.
+    pub disputes_per_tx: HashMap<TransactionId, u32, ClientHasher>,
+    /// Position of every deposit in the transactions processed by the account.
+    deposit_rows: HashMap<TransactionId, u64, ClientHasher>,
+}
+
+impl RiskCounters {
+    /// Counters without any transaction, whose maps use `hasher`.
+    fn with_hasher(hasher: &ClientHasher) -> Self {
+        Self {
+            disputes_per_tx: HashMap::with_hasher(hasher.clone()),
+            deposit_rows: HashMap::with_hasher(hasher.clone()),
+            ..Self::default()
+        }
+    }
+
+    /// Counts the accepted transaction `tx_id` of `kind`, the `row`th processed by the
+    /// account.
+    fn record(&mut self, kind: TransactionKind, tx_id: TransactionId, row: u64) {
+        match kind {
+            TransactionKind::Deposit { .. } => {
+                self.deposits += 1;
+                self.deposit_rows.insert(tx_id, row);
+            }
+            TransactionKind::Dispute { .. } => {
+                self.disputes += 1;
+                if let Some(deposit_row) = self.deposit_rows.get(&tx_id) {
+                    self.dispute_delays.push((tx_id, row - deposit_row));
+                }
+                *self.disputes_per_tx.entry(tx_id).or_default() += 1;
+            }
+            TransactionKind::Chargeback => self.chargebacks += 1,
+            _ => {}
+        }
+    }
+}
+
+/// Lifetime figures of the accepted transactions of an account, for reconciliation. The
+/// amounts are in the implicit currency, and withdrawals count the amount actually withdrawn.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GrossTotals {
+    /// Sum of the deposits, with their gross amount.
+    pub deposited: Decimal,
+    /// Sum of the withdrawals.
+    pub withdrawn: Decimal,
+    /// Number of deposits.
+    pub deposits: u64,
+    /// Number of withdrawals.
+    pub withdrawals: u64,
+    /// Number of disputes opened.
+    pub disputes_opened: u64,
+    /// Number of resolves that closed a dispute, partial resolves leaving it open not
+    /// counting.
+    pub disputes_resolved: u64,
+    /// Number of chargebacks.
+    pub chargebacks: u64,
+}
+
+impl GrossTotals {
+    /// Counts the accepted transaction of `kind`, its amount being the one applied.
+    /// `closed` tells whether a resolve closed its dispute.
+    fn record(&mut self, kind: TransactionKind, currency: Option<&Currency>, closed: bool) {
+        let amount = |amount| match currency {
+            Some(_) => Decimal::ZERO,
+            None => amount,
+        };
+        match kind {
+            TransactionKind::Deposit { amount: deposit } => {
+                self.deposits += 1;
+                self.deposited += amount(deposit);
+            }
+            TransactionKind::Withdrawal { amount: withdrawal } => {
+                self.withdrawals += 1;
+                self.withdrawn += amount(withdrawal);
+            }
+            TransactionKind::Dispute { .. } => self.disputes_opened += 1,
+            TransactionKind::Resolve { .. } if closed => self.disputes_resolved += 1,
+            TransactionKind::Chargeback => self.chargebacks += 1,
+            _ => {}
+        }
+    }
+}
+
+impl std::ops::AddAssign for GrossTotals {
+    fn add_assign(&mut self, other: Self) {
+        self.deposited += other.deposited;
+        self.withdrawn += other.withdrawn;
+        self.deposits += other.deposits;
+        self.withdrawals += other.withdrawals;
+        self.disputes_opened += other.disputes_opened;
+        self.disputes_resolved += other.disputes_resolved;
+        self.chargebacks += other.chargebacks;
+    }
+}
+
+/// Funds of an account in one currency.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CurrencyBalance {
     /// Funds available for transactions
     pub available: Decimal,
     /// Funds held due to disputes.
     pub held: Decimal,
+}
+
+impl CurrencyBalance {
+    pub fn total(&self) -> Decimal {
+        self.available + self.held
+    }
+}
+
+/// The current state of a client's asset and transaction history.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Account {
+    /// Funds available for transactions, in the implicit currency.
+    pub available: Decimal,
+    /// Funds held due to disputes, in the implicit currency.
+    pub held: Decimal,
+    /// Funds in the currencies given by [`Transaction::currency`].
+    pub currencies: BTreeMap<Currency, CurrencyBalance>,
     /// If this account can do transactions
     pub locked: bool,
+    /// If an administrative [`TransactionKind::Freeze`] froze this account, independently
+    /// of `locked`. Only deposits and withdrawals are rejected then.
+    pub admin_frozen: bool,
     /// History of transactions of this client, stored in
     /// chronological order.
     pub transactions: IndexMap<TransactionId, Transaction>,
     /// Disputes in this account.
-    pub disputes: HashMap<TransactionId, Dispute>,
+    pub disputes: HashMap<TransactionId, Dispute, ClientHasher>,
+    /// Number of disputes of `disputes` that are still open.
+    open_disputes: usize,
+    /// Deposits recalled by a [`TransactionKind::Refund`].
+    refunded: HashSet<TransactionId, ClientHasher>,
+    /// Number of transactions the account had processed, including the withdrawal, when
+    /// each withdrawal was accepted, for [`EngineConfig::withdrawal_reversal_window`].
+    withdrawal_rows: HashMap<TransactionId, u64, ClientHasher>,
+    /// Withdrawals reversed by a [`TransactionKind::WithdrawalReversal`].
+    reversed_withdrawals: HashSet<TransactionId, ClientHasher>,
+    /// Accepted transactions referencing an earlier one, such as disputes, which the
+    /// history does not keep, with the number of transactions in the history when they were
+    /// accepted. Used by [`Account::replay`].
+    references: Vec<(usize, Transaction)>,
+    /// Number of transactions processed since the account was created, including rejected
+    /// ones.
+    processed: u64,
+    /// Minimum available funds a withdrawal can leave, overriding
+    /// [`EngineConfig::min_balance`].
+    pub min_balance: Option<Decimal>,
+    /// Day of the last withdrawal, in the time zone of [`EngineConfig::day_utc_offset`],
+    /// and the funds withdrawn on it. Only kept with a daily withdrawal limit.
+    pub daily_withdrawn: Option<(NaiveDate, Decimal)>,
+    /// Fees charged on the deposits and withdrawals of this account, see
+    /// [`EngineConfig::deposit_fees`] and [`EngineConfig::withdrawal_fee`].
+    pub fees_paid: Decimal,
+    /// Sum of the deposits of the implicit currency, with their gross amount. Chargebacks do
+    /// not reduce it.
+    pub deposited: Decimal,
+    /// Largest deposit of the implicit currency, with its gross amount.
+    pub largest_deposit: Decimal,
+    /// Counters of the accepted transactions for the risk report.
+    pub risk: RiskCounters,
+    /// Lifetime figures of the accepted transactions, see [`Account::gross_totals`].
+    gross: GrossTotals,
+    /// Number of transactions rejected because the account was locked or frozen.
+    pub locked_rejections: u64,
+    /// Sum of the deposits and withdrawals of the implicit currency rejected because the
+    /// account was locked or frozen.
+    pub locked_rejected_amount: Decimal,
+    /// First transaction the account accepted, set by [`Account::record_activity`].
+    pub first_activity: Option<Activity>,
+    /// Last transaction the account accepted, set by [`Account::record_activity`].
+    pub last_activity: Option<Activity>,
+    /// Interest credited to the account, in chronological order. Interest is not part of
+    /// the history, so it cannot be disputed.
+    pub interest: Vec<InterestEntry>,
+    /// Time up to which interest was accrued, set by the first timestamped transaction.
+    accrued_until: Option<DateTime<Utc>>,
+    /// Chargeback that locked the account, if it is locked by one.
+    lock_info: Option<LockInfo>,
 }
 
 impl Account {
     pub fn new(initial_deposit: Decimal) -> Self {
+        Self::with_hasher(initial_deposit, ClientHasher::default())
+    }
+
+    /// Like [`Account::new`], with the maps of the account keyed by transaction id using
+    /// `hasher`, e.g. the deterministic one of [`EngineConfig::deterministic_hashing`].
+    pub fn with_hasher(initial_deposit: Decimal, hasher: ClientHasher) -> Self {
         Self {
             available: initial_deposit,
             held: Decimal::ZERO,
+            currencies: BTreeMap::new(),
             locked: false,
+            admin_frozen: false,
             transactions: IndexMap::new(),
-            disputes: HashMap::new(),
+            disputes: HashMap::with_hasher(hasher.clone()),
+            open_disputes: 0,
+            refunded: HashSet::with_hasher(hasher.clone()),
+            withdrawal_rows: HashMap::with_hasher(hasher.clone()),
+            reversed_withdrawals: HashSet::with_hasher(hasher.clone()),
+            references: Vec::new(),
+            processed: 0,
+            min_balance: None,
+            daily_withdrawn: None,
+            fees_paid: Decimal::ZERO,
+            deposited: Decimal::ZERO,
+            largest_deposit: Decimal::ZERO,
+            risk: RiskCounters::with_hasher(&hasher),
+            locked_rejections: 0,
+            locked_rejected_amount: Decimal::ZERO,
+            first_activity: None,
+            last_activity: None,
+            interest: Vec::new(),
+            accrued_until: None,
+            lock_info: None,
+            gross: GrossTotals::default(),
         }
     }
 
+    /// Total funds in the implicit currency.
     pub fn total_funds(&self) -> Decimal {
         self.available + self.held
     }
 
+    /// Funds in `currency`, `None` being the implicit currency.
+    pub fn balance(&self, currency: Option<&Currency>) -> CurrencyBalance {
+        match currency {
+            None => CurrencyBalance {
+                available: self.available,
+                held: self.held,
+            },
+            Some(currency) => self.currencies.get(currency).copied().unwrap_or_default(),
+        }
+    }
+
+    /// Funds in every currency the account uses, the implicit currency first. The implicit
+    /// currency is left out when it holds nothing and other currencies are used.
+    pub fn balances(&self) -> impl Iterator<Item = (Option<&Currency>, CurrencyBalance)> {
+        let implicit =
+            self.currencies.is_empty() || !self.available.is_zero() || !self.held.is_zero();
+        implicit
+            .then(|| (None, self.balance(None)))
+            .into_iter()
+            .chain(
+                self.currencies
+                    .iter()
+                    .map(|(currency, balance)| (Some(currency), *balance)),
+            )
+    }
+
+    /// Available and held funds in `currency`, `None` being the implicit currency.
+    fn funds_mut(&mut self, currency: Option<&Currency>) -> (&mut Decimal, &mut Decimal) {
+        match currency {
+            None => (&mut self.available, &mut self.held),
+            Some(currency) => {
+                let balance = self.currencies.entry(currency.clone()).or_default();
+                (&mut balance.available, &mut balance.held)
+            }
+        }
+    }
+
+    /// Updates the client account accordingly to the new transaction received, with the
+    /// default engine configuration.
+    pub fn process_transaction(
+        &mut self,
+        transaction: Transaction,
+    ) -> Result<(), TransactionError> {
+        self.process_transaction_with(transaction, &EngineConfig::default())
+    }
+
     /// Updates the client account accordingly to the new transaction received.
-    pub fn process_transaction(&mut self, transaction: Transaction) {
-        if self.locked {
-            return;
+    /// Transactions that are invalid for this account are ignored, while the ones breaking
+    /// a rule or a limit of `config` are rejected. Transactions of a locked account are
+    /// ignored too.
+    pub fn process_transaction_with(
+        &mut self,
+        transaction: Transaction,
+        config: &EngineConfig,
+    ) -> Result<(), TransactionError> {
+        match self.try_process_with(transaction, config) {
+            Err(TransactionError::AccountLocked) => Ok(()),
+            result => result.map(|_| ()),
+        }
+    }
+
+    /// Like [`Account::process_transaction`], but rejects the transactions of a locked
+    /// account and tells whether the transaction was applied or ignored.
+    pub fn try_process(
+        &mut self,
+        transaction: Transaction,
+    ) -> Result<ProcessOutcome, TransactionError> {
+        self.try_process_with(transaction, &EngineConfig::default())
+    }
+
+    /// Like [`Account::process_transaction_with`], but rejects the transactions of a locked
+    /// account and tells whether the transaction was applied or ignored.
+    pub fn try_process_with(
+        &mut self,
+        transaction: Transaction,
+        config: &EngineConfig,
+    ) -> Result<ProcessOutcome, TransactionError> {
+        let moves_funds = matches!(
+            transaction.kind,
+            TransactionKind::Deposit { .. } | TransactionKind::Withdrawal { .. }
+        );
+        // A chargeback reversal may be what unlocks the account.
+        let locked = self.locked
+            && !transaction.kind.is_admin_op()
+            && transaction.kind != TransactionKind::ChargebackReversal;
+        if locked || (self.admin_frozen && moves_funds) {
+            self.locked_rejections += 1;
+            if let (
+                TransactionKind::Deposit { amount } | TransactionKind::Withdrawal { amount },
+                None,
+            ) = (transaction.kind, &transaction.currency)
+            {
+                self.locked_rejected_amount += amount;
+            }
+            return Err(if locked {
+                TransactionError::AccountLocked
+            } else {
+                TransactionError::AccountFrozen
+            });
+        }
+
+        self.processed += 1;
+        if let (Some(policy), Some(timestamp)) = (&config.interest, transaction.timestamp) {
+            self.accrue_interest(policy, timestamp);
+        }
+        let (kind, tx_id) = (transaction.kind, transaction.id);
+        let currency = transaction.currency.clone();
+        let reference = transaction
+            .references_transaction()
+            .then(|| transaction.clone());
+        let result = self.apply_transaction(transaction, config);
+        if result == Ok(ProcessOutcome::Applied) {
+            self.risk.record(kind, tx_id, self.processed);
+            // The history keeps the amount a withdrawal actually withdrew.
+            let applied = match kind {
+                TransactionKind::Withdrawal { .. } => self.transactions[&tx_id].kind,
+                _ => kind,
+            };
+            let closed = self
+                .disputes
+                .get(&tx_id)
+                .is_some_and(|dispute| !dispute.can_finish());
+            self.gross.record(applied, currency.as_ref(), closed);
+            if let Some(reference) = reference {
+                self.references.push((self.transactions.len(), reference));
+            }
+        }
+        if let Some(horizon) = config.auto_resolve_after {
+            let processed = self.processed;
+            self.resolve_disputes(
+                |dispute| dispute.is_past_horizon(processed, horizon),
+                Dispute::auto_resolve,
+            );
+        }
+        result
+    }
+
+    /// Like [`Account::try_process_with`], for the transaction at `activity` in the run.
+    /// Once accepted, it is recorded as the last activity of the account, and a chargeback
+    /// locking the account keeps its sequence number.
+    pub fn try_process_at(
+        &mut self,
+        transaction: Transaction,
+        config: &EngineConfig,
+        activity: Activity,
+    ) -> Result<ProcessOutcome, TransactionError> {
+        let had_lock = self.lock_info.is_some();
+        let outcome = self.try_process_with(transaction, config)?;
+        if outcome == ProcessOutcome::Applied {
+            self.record_activity(activity);
+            if let (false, Some(lock)) = (had_lock, &mut self.lock_info) {
+                lock.seq = activity.sequence;
+            }
         }
+        Ok(outcome)
+    }
 
+    fn apply_transaction(
+        &mut self,
+        transaction: Transaction,
+        config: &EngineConfig,
+    ) -> Result<ProcessOutcome, TransactionError> {
         let transaction_kind = transaction.kind;
         let tx_id = transaction.id;
+        let timestamp = transaction.timestamp;
+        let currency = transaction.currency.as_ref();
 
-        match transaction_kind {
-            TransactionKind::Deposit { amount } => {
-                if transaction.amount_is_valid() {
-                    self.available += amount;
+        let outcome = if !transaction.amount_is_valid() {
+            ProcessOutcome::Ignored
+        } else {
+            match transaction_kind {
+                TransactionKind::Deposit { amount } => {
+                    // Like the other limits, the deposit limit is in the implicit currency.
+                    if let Some((limit, tier)) = config
+                        .limit(transaction.client, None, |limits| limits.max_deposit)
+                        .filter(|(limit, _)| currency.is_none() && amount > *limit)
+                    {
+                        return Err(TransactionError::DepositLimitExceeded {
+                            limit,
+                            tier: tier.map(str::to_string),
+                        });
+                    }
+                    // The stored deposit keeps the gross amount, which disputes hold.
+                    let fee = match currency {
+                        Some(_) => Decimal::ZERO,
+                        None => config.deposit_fee(transaction.client, amount),
+                    };
+                    *self.funds_mut(currency).0 += amount - fee;
+                    self.fees_paid += fee;
+                    if currency.is_none() {
+                        self.deposited += amount;
+                        self.largest_deposit = self.largest_deposit.max(amount);
+                    }
                     self.transactions.insert(tx_id, transaction);
+                    ProcessOutcome::Applied
                 }
-            }
-            TransactionKind::Withdrawal { amount } => {
-                if transaction.amount_is_valid() && self.available > amount {
-                    self.available -= amount;
+                TransactionKind::Withdrawal { amount } => {
+                    let withdrawn = match currency {
+                        Some(currency) => self.withdraw_currency(currency, amount, config)?,
+                        None => self.withdraw(transaction.client, amount, timestamp, config)?,
+                    };
+                    // The history keeps the amount actually withdrawn, which differs when
+                    // it was clamped.
+                    let transaction = Transaction {
+                        kind: TransactionKind::Withdrawal { amount: withdrawn },
+                        ..transaction
+                    };
                     self.transactions.insert(tx_id, transaction);
+                    self.withdrawal_rows.insert(tx_id, self.processed);
+                    ProcessOutcome::Applied
                 }
-            }
-            TransactionKind::Dispute => {
-                if self.disputes.contains_key(&tx_id) {
-                    println!("This transaction already has an associated dispute.");
+                TransactionKind::Dispute { amount } => self.open_dispute(
+                    transaction.client,
+                    tx_id,
+                    amount,
+                    timestamp,
+                    currency,
+                    config,
+                )?,
+                TransactionKind::Resolve { amount } => {
+                    if config.implicit_dispute_on_resolve && !self.disputes.contains_key(&tx_id) {
+                        self.record_implicit_dispute(tx_id, timestamp, currency)?
+                    } else {
+                        self.resolve_dispute(tx_id, amount, currency, config.round_each_op)?
+                    }
                 }
-                if let Some(transaction) = self.transactions.get(&tx_id)
-                    && let Some(disputed_amount) = transaction.deposit_amount()
-                {
-                    let dispute = Dispute::new();
-                    self.disputes.insert(tx_id, dispute);
-                    self.hold_funds(disputed_amount);
+                TransactionKind::Chargeback => {
+                    self.chargeback_dispute(tx_id, timestamp, currency)?
                 }
-            }
-            TransactionKind::Resolve => {
-                let disputed_amount = self.disputed_deposit(tx_id);
-                if let Some(dispute) = self.disputes.get_mut(&tx_id)
-                    && dispute.can_finish()
-                    && let Some(disputed_amount) = disputed_amount
+                TransactionKind::ChargebackReversal => self.reverse_chargeback(tx_id, currency)?,
+                TransactionKind::Refund => self.refund_deposit(tx_id, currency, config)?,
+                TransactionKind::WithdrawalReversal => {
+                    self.reverse_withdrawal(tx_id, currency, config)?
+                }
+                TransactionKind::RequestEvidence => {
+                    self.escalate_dispute(tx_id, DisputeState::EvidenceRequested, currency)?
+                }
+                TransactionKind::PreArbitration => {
+                    self.escalate_dispute(tx_id, DisputeState::PreArbitration, currency)?
+                }
+                TransactionKind::Arbitration => {
+                    self.escalate_dispute(tx_id, DisputeState::Arbitration, currency)?
+                }
+                TransactionKind::Unlock | TransactionKind::Freeze | TransactionKind::Unfreeze
+                    if !config.allow_admin_ops =>
                 {
-                    dispute.resolve();
-                    self.release_held_funds(disputed_amount);
+                    return Err(TransactionError::AdminOpsDisabled);
                 }
-            }
-            TransactionKind::Chargeback => {
-                let disputed_amount = self.disputed_deposit(tx_id);
-                if let Some(dispute) = self.disputes.get_mut(&tx_id)
-                    && dispute.can_finish()
-                    && let Some(disputed_amount) = disputed_amount
+                TransactionKind::Unlock | TransactionKind::Freeze | TransactionKind::Unfreeze
+                    if self.transactions.contains_key(&tx_id) =>
                 {
-                    dispute.chargeback();
-                    self.chargeback_and_lock(disputed_amount);
+                    return Err(TransactionError::TxIdInUse);
+                }
+                TransactionKind::Unlock => {
+                    if !self.locked {
+                        return Ok(ProcessOutcome::Ignored);
+                    }
+                    self.unlock();
+                    self.transactions.insert(tx_id, transaction);
+                    ProcessOutcome::Applied
+                }
+                TransactionKind::Freeze | TransactionKind::Unfreeze => {
+                    let frozen = transaction_kind == TransactionKind::Freeze;
+                    if self.admin_frozen == frozen {
+                        return Ok(ProcessOutcome::Ignored);
+                    }
+                    self.admin_frozen = frozen;
+                    self.transactions.insert(tx_id, transaction);
+                    ProcessOutcome::Applied
                 }
             }
-        }
-    }
-
-    /// Returns the disputed deposit transaction if it exists.
-    pub fn disputed_deposit(&self, transaction_id: TransactionId) -> Option<Decimal> {
-        let transaction = self.transactions.get(&transaction_id)?;
-        transaction.deposit_amount()
-    }
-
-    /// Decreases the account's available funds and increases the `held` funds. Note that
-    /// if the account does not have enough funds, this will result in a negative balance.
-    /// However, since the held value increases by the same amount that available funds
-    /// decrease, the total sum does not change.
-    pub fn hold_funds(&mut self, disputed_amount: Decimal) {
-        self.available -= disputed_amount;
-        self.held += disputed_amount;
-    }
-
-    /// Releases the held funds back to the account available funds.
-    pub fn release_held_funds(&mut self, disputed_amount: Decimal) {
-        self.held -= disputed_amount;
-        self.available += disputed_amount;
-    }
+        };
 
-    /// Withdraws the held funds from the account.
-    pub fn chargeback_and_lock(&mut self, disputed_amount: Decimal) {
-        self.held -= disputed_amount;
-        self.locked = true;
+        if config.round_each_op {
+            self.round_balances();
+        }
+        Ok(outcome)
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use crate::transaction::ClientId;
-
-    use super::*;
 
-    #[test]
-    fn test_deposit() {
-        let mut transactions = vec![];
-        for i in 0..10 {
-            transactions.push(Transaction {
-                client: ClientId(1),
-                kind: TransactionKind::Deposit {
-                    amount: Decimal::new(10, 0),
-                },
-                id: TransactionId(i),
+    /// Withdraws `amount` of the implicit currency and its fee, checking the limits of
+    /// `config` for `client`. The limits apply to the amount alone, the balances to the
+    /// amount and fee. Returns the withdrawn amount, which is below `amount` if it was
+    /// clamped to the available funds.
+    fn withdraw(
+        &mut self,
+        client: ClientId,
+        amount: Decimal,
+        timestamp: Option<DateTime<Utc>>,
+        config: &EngineConfig,
+    ) -> Result<Decimal, TransactionError> {
+        let (amount, clamped) = clamp_withdrawal(amount, self.available, config)?;
+        if let Some((limit, tier)) = config
+            .limit(client, config.max_withdrawal, |limits| {
+                limits.max_withdrawal
+            })
+            .filter(|(limit, _)| amount > *limit)
+        {
+            return Err(TransactionError::WithdrawalLimitExceeded {
+                limit,
+                tier: tier.map(str::to_string),
             });
         }
-        let expected_available = Decimal::new(100, 0);
-        let account = transactions
-            .into_iter()
-            .fold(Account::new(Decimal::ZERO), |mut acc, tx| {
-                acc.process_transaction(tx);
-                acc
+        let daily_withdrawn = self.daily_withdrawn_after(client, amount, timestamp, config)?;
+        let fee = config
+            .withdrawal_fee
+            .map_or(Decimal::ZERO, |schedule| schedule.fee(amount));
+        let charged = amount + fee;
+        if self.available < charged {
+            return Err(TransactionError::InsufficientFunds {
+                requested: charged,
+                available: self.available,
+            });
+        }
+        if self.available == charged && !clamped {
+            return Err(TransactionError::WholeBalanceWithdrawal {
+                available: self.available,
             });
-
-        assert_eq!(account.available, expected_available);
-        assert_eq!(account.held, Decimal::ZERO);
-        assert_eq!(account.total_funds(), expected_available);
-        for i in 0..10 {
-            assert!(account.transactions.contains_key(&TransactionId(i)));
         }
+        let would_be = self.available - charged;
+        if let Some(min) = self
+            .min_balance
+            .or(config.min_balance)
+            .filter(|min| would_be < *min)
+        {
+            return Err(TransactionError::MinimumBalance { min, would_be });
+        }
+        self.available -= charged;
+        self.fees_paid += fee;
+        if daily_withdrawn.is_some() {
+            self.daily_withdrawn = daily_withdrawn;
+        }
+        Ok(amount)
     }
 
-    #[test]
-    fn test_withdraw() {
-        let mut transactions = vec![];
-        for i in 0..10 {
-            transactions.push(Transaction {
-                client: ClientId(1),
-                kind: TransactionKind::Deposit {
-                    amount: Decimal::new(10, 0),
-                },
-                id: TransactionId(i),
+    /// Withdraws `amount` of `currency`, returning the withdrawn amount like
+    /// [`Account::withdraw`]. The limits and fees of the engine configuration are in the
+    /// implicit currency, so only the available funds are checked.
+    fn withdraw_currency(
+        &mut self,
+        currency: &Currency,
+        amount: Decimal,
+        config: &EngineConfig,
+    ) -> Result<Decimal, TransactionError> {
+        let available = self.balance(Some(currency)).available;
+        let (amount, clamped) = clamp_withdrawal(amount, available, config)?;
+        if available < amount {
+            return Err(TransactionError::InsufficientFunds {
+                requested: amount,
+                available,
             });
         }
+        if available == amount && !clamped {
+            return Err(TransactionError::WholeBalanceWithdrawal { available });
+        }
+        *self.funds_mut(Some(currency)).0 -= amount;
+        Ok(amount)
+    }
 
-        transactions.push(Transaction {
-            client: ClientId(1),
-            kind: TransactionKind::Withdrawal {
-                amount: Decimal::new(5, 0),
-            },
-            id: TransactionId(15),
-        });
-
-        let expected_available = Decimal::new(95, 0);
-        let account = transactions
-            .into_iter()
-            .fold(Account::new(Decimal::ZERO), |mut acc, tx| {
-                acc.process_transaction(tx);
-                acc
+    /// Checks a withdrawal of `amount` against the daily withdrawal limit, returning the
+    /// funds withdrawn on its day once it is accepted. Only the day of the last withdrawal
+    /// is kept, so the total restarts from zero on a new day.
+    fn daily_withdrawn_after(
+        &self,
+        client: ClientId,
+        amount: Decimal,
+        timestamp: Option<DateTime<Utc>>,
+        config: &EngineConfig,
+    ) -> Result<Option<(NaiveDate, Decimal)>, TransactionError> {
+        let Some((limit, tier)) = config.limit(client, config.daily_withdrawal_limit, |limits| {
+            limits.daily_withdrawal_limit
+        }) else {
+            return Ok(None);
+        };
+        let Some(timestamp) = timestamp else {
+            return Err(TransactionError::MissingTimestamp);
+        };
+        let day = match config.day_utc_offset {
+            Some(offset) => timestamp.with_timezone(&offset).date_naive(),
+            None => timestamp.date_naive(),
+        };
+        let used = match self.daily_withdrawn {
+            Some((last_day, used)) if last_day == day => used,
+            _ => Decimal::ZERO,
+        };
+        if used + amount > limit {
+            return Err(TransactionError::DailyLimitExceeded {
+                used,
+                limit,
+                tier: tier.map(str::to_string),
             });
-        assert_eq!(account.available, expected_available);
-        assert_eq!(account.held, Decimal::ZERO);
-        assert!(account.transactions.contains_key(&TransactionId(15)));
-        for i in 0..10 {
-            assert!(account.transactions.contains_key(&TransactionId(i)));
         }
+        Ok(Some((day, used + amount)))
     }
 
-    // Withdraw with insufficient funds should not be processed and should not be added to the transaction history.
-    #[test]
-    fn insufficient_funds() {
-        let mut transactions = vec![];
-        transactions.push(Transaction {
-            client: ClientId(1),
-            kind: TransactionKind::Deposit {
-                amount: Decimal::new(5, 0),
-            },
-            id: TransactionId(1),
-        });
+    /// Opens a dispute of the deposit or withdrawal `tx_id`, holding its amount in its
+    /// currency. Disputes of unknown transactions are ignored, and ones outside of the
+    /// dispute window, in another currency or of withdrawals without
+    /// [`EngineConfig::allow_withdrawal_disputes`] are rejected.
+    fn open_dispute(
+        &mut self,
+        client: ClientId,
+        tx_id: TransactionId,
+        amount: Option<Decimal>,
+        timestamp: Option<DateTime<Utc>>,
+        currency: Option<&Currency>,
+        config: &EngineConfig,
+    ) -> Result<ProcessOutcome, TransactionError> {
+        if self.is_disputed(tx_id) {
+            return match config.on_duplicate_dispute {
+                DuplicateDisputePolicy::Ignore => Ok(ProcessOutcome::Ignored),
+                DuplicateDisputePolicy::Error => Err(TransactionError::AlreadyDisputed),
+            };
+        }
+        if self.refunded.contains(&tx_id) {
+            return Err(TransactionError::Refunded);
+        }
+        // A resolved dispute, by the client or the engine, can be opened again, but a
+        // chargeback is final, even once reversed: the funds already left once.
+        if self.disputes.get(&tx_id).is_some_and(|dispute| {
+            matches!(
+                dispute.state(),
+                DisputeState::ChargedBack | DisputeState::Reversed
+            )
+        }) {
+            return Err(TransactionError::AlreadyChargedBack);
+        }
+        let Some(disputed) = self.transactions.get(&tx_id) else {
+            return Ok(ProcessOutcome::Ignored);
+        };
+        let (disputed_amount, of_withdrawal) = match disputed.kind {
+            TransactionKind::Deposit { amount } => (amount, false),
+            TransactionKind::Withdrawal { .. } if !config.allow_withdrawal_disputes => {
+                return Err(TransactionError::NotDisputable);
+            }
+            TransactionKind::Withdrawal { amount } => (amount, true),
+            _ => return Ok(ProcessOutcome::Ignored),
+        };
+        // A partial dispute holds only its amount, which cannot exceed the transaction's.
+        let disputed_amount = match amount {
+            Some(amount) if amount > disputed_amount => {
+                return Err(TransactionError::DisputeAmountTooLarge {
+                    requested: amount,
+                    disputed: disputed_amount,
+                });
+            }
+            Some(amount) => amount,
+            None => disputed_amount,
+        };
+        if of_withdrawal && self.reversed_withdrawals.contains(&tx_id) {
+            return Err(TransactionError::AlreadyReversed);
+        }
+        let deposit_currency = disputed.currency.clone();
+        check_currency(deposit_currency.as_ref(), currency)?;
+        if let Some(window) = config.dispute_window {
+            let (Some(deposited_at), Some(disputed_at)) = (disputed.timestamp, timestamp) else {
+                return Err(TransactionError::MissingTimestamp);
+            };
+            if disputed_at - deposited_at > window {
+                return Err(TransactionError::DisputeWindowExpired);
+            }
+        }
+        if let Some((_, tier)) = config
+            .limit(client, config.max_open_disputes, |limits| {
+                limits.max_open_disputes
+            })
+            .filter(|(max_open, _)| self.open_disputes >= *max_open)
+        {
+            return Err(TransactionError::TooManyOpenDisputes {
+                tier: tier.map(str::to_string),
+            });
+        }
+        // Like the other limits, the held limit is in the implicit currency.
+        if deposit_currency.is_none()
+            && config
+                .max_held_per_account
+                .is_some_and(|max_held| self.held + disputed_amount > max_held)
+        {
+            return Err(TransactionError::HeldLimitReached);
+        }
 
-        transactions.push(Transaction {
-            client: ClientId(1),
-            kind: TransactionKind::Withdrawal {
-                amount: Decimal::new(100, 0),
-            },
-            id: TransactionId(15),
-        });
+        let dispute = if of_withdrawal {
+            // The withdrawn funds are not on the account anymore, so they are held on top.
+            let held = self.funds_mut(deposit_currency.as_ref()).1;
+            *held += disputed_amount;
+            if config.round_each_op {
+                *held = round_amount(*held);
+            }
+            Dispute::of_withdrawal(disputed_amount, deposit_currency, timestamp, self.processed)
+        } else {
+            self.hold_funds(
+                deposit_currency.as_ref(),
+                disputed_amount,
+                config.round_each_op,
+            );
+            Dispute::new(disputed_amount, deposit_currency, timestamp, self.processed)
+        };
+        self.disputes.insert(tx_id, dispute);
+        self.open_disputes += 1;
+        Ok(ProcessOutcome::Applied)
+    }
 
-        let expected_available = Decimal::new(5, 0);
-        let account = transactions
-            .into_iter()
-            .fold(Account::new(Decimal::ZERO), |mut acc, tx| {
-                acc.process_transaction(tx);
-                acc
+    /// Releases `amount` of the funds held by the open dispute of `tx_id`, or all of them
+    /// without an amount. Transactions without an open dispute are ignored, unless their
+    /// dispute was auto-resolved.
+    fn resolve_dispute(
+        &mut self,
+        tx_id: TransactionId,
+        amount: Option<Decimal>,
+        currency: Option<&Currency>,
+        round: bool,
+    ) -> Result<ProcessOutcome, TransactionError> {
+        self.check_not_auto_resolved(tx_id)?;
+        let Some(dispute) = self.open_dispute_mut(tx_id) else {
+            return Ok(ProcessOutcome::Ignored);
+        };
+        check_currency(dispute.currency(), currency)?;
+        if !dispute.can_resolve() {
+            return Err(TransactionError::InvalidDisputeTransition {
+                from: dispute.state().clone(),
+                to: DisputeState::Resolved,
             });
-        assert_eq!(account.available, expected_available);
-        assert_eq!(account.held, Decimal::ZERO);
-        assert!(!account.transactions.contains_key(&TransactionId(15)));
+        }
+        let dispute_currency = dispute.currency().cloned();
+        let of_withdrawal = dispute.is_of_withdrawal();
+        // Rounding the released part keeps the rest held by the dispute on the same scale as
+        // the balances, so a later full resolve releases exactly what is left.
+        let amount = amount.map(|amount| if round { round_amount(amount) } else { amount });
+        let released = dispute.resolve(amount);
+        if !dispute.can_finish() {
+            self.open_disputes -= 1;
+        }
+        self.settle_resolve(dispute_currency.as_ref(), released, of_withdrawal, round);
+        Ok(ProcessOutcome::Applied)
     }
 
-    // Basic dispute case
-    #[test]
-    fn test_dispute() {
-        // Client deposits 100, then deposits 50 more, then disputes the first transaction.
-        // 100 is moved to held, 50 is still available. Total is 150.
-        let deposit_1 = Transaction {
-            client: ClientId(1),
-            kind: TransactionKind::Deposit {
-                amount: Decimal::new(100, 0),
-            },
-            id: TransactionId(1),
+    /// Records a dispute of the deposit `tx_id` that is resolved as soon as it is opened, so
+    /// the balances do not change. Unknown transactions and withdrawals are ignored.
+    fn record_implicit_dispute(
+        &mut self,
+        tx_id: TransactionId,
+        timestamp: Option<DateTime<Utc>>,
+        currency: Option<&Currency>,
+    ) -> Result<ProcessOutcome, TransactionError> {
+        if self.refunded.contains(&tx_id) {
+            return Err(TransactionError::Refunded);
+        }
+        let Some(deposit) = self.transactions.get(&tx_id) else {
+            return Ok(ProcessOutcome::Ignored);
         };
-
-        let deposit_2 = Transaction {
-            client: ClientId(1),
-            kind: TransactionKind::Deposit {
-                amount: Decimal::new(50, 0),
-            },
-            id: TransactionId(2),
+        let Some(amount) = deposit.deposit_amount() else {
+            return Ok(ProcessOutcome::Ignored);
         };
+        check_currency(deposit.currency.as_ref(), currency)?;
+        let mut dispute = Dispute::new(amount, deposit.currency.clone(), timestamp, self.processed);
+        dispute.resolve(None);
+        self.disputes.insert(tx_id, dispute);
+        Ok(ProcessOutcome::Applied)
+    }
 
-        let dispute = Transaction {
-            client: ClientId(1),
-            kind: TransactionKind::Dispute,
-            id: TransactionId(1), //first deposit
+    /// Charges back the open dispute of `tx_id` and locks the account. Transactions without
+    /// an open dispute are ignored, unless their dispute was auto-resolved.
+    fn chargeback_dispute(
+        &mut self,
+        tx_id: TransactionId,
+        timestamp: Option<DateTime<Utc>>,
+        currency: Option<&Currency>,
+    ) -> Result<ProcessOutcome, TransactionError> {
+        self.check_not_auto_resolved(tx_id)?;
+        let Some(dispute) = self.open_dispute_mut(tx_id) else {
+            return Ok(ProcessOutcome::Ignored);
         };
+        check_currency(dispute.currency(), currency)?;
+        let dispute_currency = dispute.currency().cloned();
+        let of_withdrawal = dispute.is_of_withdrawal();
+        // Only what is still held can be charged back, the rest was already released by
+        // partial resolves and is recorded as shortfall.
+        let charged_back = dispute.chargeback();
+        self.open_disputes -= 1;
+        let lock = LockInfo {
+            tx: tx_id,
+            seq: self.processed,
+            timestamp,
+        };
+        self.chargeback_and_lock(dispute_currency.as_ref(), charged_back, lock);
+        if of_withdrawal {
+            // The withdrawal is reversed, so its funds are credited back.
+            *self.funds_mut(dispute_currency.as_ref()).0 += charged_back;
+        }
+        Ok(ProcessOutcome::Applied)
+    }
 
-        let transactions = vec![deposit_1, deposit_2, dispute];
+    /// Releases the `amount` resolved by a dispute: back to the available funds for a
+    /// deposit, and out of the account for a withdrawal, which stands.
+    fn settle_resolve(
+        &mut self,
+        currency: Option<&Currency>,
+        amount: Decimal,
+        of_withdrawal: bool,
+        round: bool,
+    ) {
+        if !of_withdrawal {
+            self.release_held_funds(currency, amount, round);
+            return;
+        }
+        let held = self.funds_mut(currency).1;
+        *held -= amount;
+        if round {
+            *held = round_amount(*held);
+        }
+    }
 
-        let account = transactions
-            .into_iter()
-            .fold(Account::new(Decimal::ZERO), |mut acc, tx| {
-                acc.process_transaction(tx);
-                acc
+    /// Refunds the deposit `tx_id`, debiting its gross amount from the available funds of
+    /// its currency without locking the account. Refunds of deposits that were disputed or
+    /// refunded are rejected, and so are the ones above the available funds unless
+    /// [`EngineConfig::allow_negative_refunds`] is set. Unknown transactions and withdrawals
+    /// are ignored.
+    fn refund_deposit(
+        &mut self,
+        tx_id: TransactionId,
+        currency: Option<&Currency>,
+        config: &EngineConfig,
+    ) -> Result<ProcessOutcome, TransactionError> {
+        if self.refunded.contains(&tx_id) {
+            return Err(TransactionError::Refunded);
+        }
+        if self.disputes.contains_key(&tx_id) {
+            return Err(TransactionError::AlreadyDisputed);
+        }
+        let Some(deposit) = self.transactions.get(&tx_id) else {
+            return Ok(ProcessOutcome::Ignored);
+        };
+        let Some(amount) = deposit.deposit_amount() else {
+            return Ok(ProcessOutcome::Ignored);
+        };
+        let deposit_currency = deposit.currency.clone();
+        check_currency(deposit_currency.as_ref(), currency)?;
+        let available = self.balance(deposit_currency.as_ref()).available;
+        if amount > available && !config.allow_negative_refunds {
+            return Err(TransactionError::InsufficientFunds {
+                requested: amount,
+                available,
             });
-        assert_eq!(account.available, Decimal::new(50, 0));
-        assert_eq!(account.held, Decimal::new(100, 0));
-        assert_eq!(account.total_funds(), Decimal::new(150, 0));
-        assert_eq!(account.disputes.len(), 1);
-        assert!(account.disputes.contains_key(&TransactionId(1)));
+        }
+        *self.funds_mut(deposit_currency.as_ref()).0 -= amount;
+        self.refunded.insert(tx_id);
+        Ok(ProcessOutcome::Applied)
     }
 
-    #[test]
-    fn test_resolve() {
-        // Client deposits 100, then disputes it, then resolves the dispute.
-        // After the dispute, 100 is held and 0 is available. After the resolve, 100 is available again and 0 is held.
-        let deposit = Transaction {
-            client: ClientId(1),
-            kind: TransactionKind::Deposit {
-                amount: Decimal::new(100, 0),
-            },
-            id: TransactionId(1),
+    /// Credits back the amount of the withdrawal `tx_id` in its currency. Its fee is not
+    /// refunded. Reversals of unknown transactions, deposits, withdrawals already reversed
+    /// and withdrawals outside of the reversal window are rejected, and so are all reversals
+    /// without a [`EngineConfig::withdrawal_reversal_window`].
+    fn reverse_withdrawal(
+        &mut self,
+        tx_id: TransactionId,
+        currency: Option<&Currency>,
+        config: &EngineConfig,
+    ) -> Result<ProcessOutcome, TransactionError> {
+        let Some(withdrawal) = self.transactions.get(&tx_id) else {
+            return Err(TransactionError::UnknownWithdrawal);
+        };
+        let TransactionKind::Withdrawal { amount } = withdrawal.kind else {
+            return Err(TransactionError::UnknownWithdrawal);
+        };
+        let withdrawal_currency = withdrawal.currency.clone();
+        check_currency(withdrawal_currency.as_ref(), currency)?;
+        if self.reversed_withdrawals.contains(&tx_id) {
+            return Err(TransactionError::AlreadyReversed);
+        }
+        if self.disputes.contains_key(&tx_id) {
+            return Err(TransactionError::AlreadyDisputed);
+        }
+        let Some(window) = config.withdrawal_reversal_window else {
+            return Err(TransactionError::NoReversalWindow);
         };
+        let in_window = self
+            .withdrawal_rows
+            .get(&tx_id)
+            .is_some_and(|row| self.processed - row <= window);
+        if !in_window {
+            return Err(TransactionError::ReversalWindowExpired);
+        }
+        *self.funds_mut(withdrawal_currency.as_ref()).0 += amount;
+        self.reversed_withdrawals.insert(tx_id);
+        Ok(ProcessOutcome::Applied)
+    }
 
-        let dispute = Transaction {
-            client: ClientId(1),
-            kind: TransactionKind::Dispute,
-            id: TransactionId(1), //first deposit
+    /// Escalates the open dispute of `tx_id` to `stage`, which must come after its current
+    /// one. Transactions without an open dispute are ignored, unless their dispute was
+    /// auto-resolved.
+    fn escalate_dispute(
+        &mut self,
+        tx_id: TransactionId,
+        stage: DisputeState,
+        currency: Option<&Currency>,
+    ) -> Result<ProcessOutcome, TransactionError> {
+        self.check_not_auto_resolved(tx_id)?;
+        let Some(dispute) = self.open_dispute_mut(tx_id) else {
+            return Ok(ProcessOutcome::Ignored);
         };
+        check_currency(dispute.currency(), currency)?;
+        if !dispute.can_escalate(&stage) {
+            return Err(TransactionError::InvalidDisputeTransition {
+                from: dispute.state().clone(),
+                to: stage,
+            });
+        }
+        dispute.escalate(stage);
+        Ok(ProcessOutcome::Applied)
+    }
 
-        let resolve = Transaction {
-            client: ClientId(1),
-            kind: TransactionKind::Resolve,
-            id: TransactionId(1), //first deposit
+    /// Reverses the chargeback of `tx_id`, making the charged back funds available again.
+    /// The account is unlocked if this chargeback locked it, but stays locked by another
+    /// one. Transactions whose dispute is not charged back are rejected.
+    fn reverse_chargeback(
+        &mut self,
+        tx_id: TransactionId,
+        currency: Option<&Currency>,
+    ) -> Result<ProcessOutcome, TransactionError> {
+        let Some(dispute) = self
+            .disputes
+            .get_mut(&tx_id)
+            .filter(|dispute| *dispute.state() == DisputeState::ChargedBack)
+        else {
+            return Err(TransactionError::NotChargedBack);
         };
+        check_currency(dispute.currency(), currency)?;
+        let dispute_currency = dispute.currency().cloned();
+        let of_withdrawal = dispute.is_of_withdrawal();
+        let reversed = dispute.reverse_chargeback();
+        // Reversing the chargeback of a withdrawal withdraws its funds again.
+        let available = self.funds_mut(dispute_currency.as_ref()).0;
+        if of_withdrawal {
+            *available -= reversed;
+        } else {
+            *available += reversed;
+        }
+        if self.lock_info.is_some_and(|lock| lock.tx == tx_id) {
+            self.unlock();
+        }
+        Ok(ProcessOutcome::Applied)
+    }
 
-        let transactions = vec![deposit, dispute, resolve];
+    /// Rejects the resolves and chargebacks of a dispute the engine already resolved.
+    fn check_not_auto_resolved(&self, tx_id: TransactionId) -> Result<(), TransactionError> {
+        match self.disputes.get(&tx_id).map(Dispute::state) {
+            Some(DisputeState::AutoResolved) => Err(TransactionError::DisputeAutoResolved),
+            _ => Ok(()),
+        }
+    }
 
-        let account = transactions
-            .into_iter()
-            .fold(Account::new(Decimal::ZERO), |mut acc, tx| {
-                acc.process_transaction(tx);
-                acc
-            });
-        assert_eq!(account.available, Decimal::new(100, 0));
-        assert_eq!(account.held, Decimal::ZERO);
-        assert_eq!(account.total_funds(), Decimal::new(100, 0));
-        assert_eq!(account.disputes.len(), 1);
-        assert!(account.disputes.contains_key(&TransactionId(1)));
+    /// The dispute of `tx_id`, if it is open.
+    fn open_dispute_mut(&mut self, tx_id: TransactionId) -> Option<&mut Dispute> {
+        self.disputes
+            .get_mut(&tx_id)
+            .filter(|dispute| dispute.can_finish())
     }
 
-    #[test]
-    fn test_blocked_account_after_chargeback() {
-        // Client deposits 100, then disputes it, then chargebacks the dispute.
-        // After the dispute, 100 is held and 0 is available. After the chargeback, 0 is held, 0 is available and the account is locked.
-        let deposit = Transaction {
-            client: ClientId(1),
-            kind: TransactionKind::Deposit {
-                amount: Decimal::new(100, 0),
-            },
-            id: TransactionId(1),
-        };
+    /// Rounds the balances with [`round_amount`].
+    fn round_balances(&mut self) {
+        let round = round_amount;
+        self.available = round(self.available);
+        self.held = round(self.held);
+        for balance in self.currencies.values_mut() {
+            balance.available = round(balance.available);
+            balance.held = round(balance.held);
+        }
+    }
 
-        let dispute = Transaction {
-            client: ClientId(1),
-            kind: TransactionKind::Dispute,
-            id: TransactionId(1), //first deposit
-        };
-        // at this point client has 100 held and 0 available
-        let chargeback = Transaction {
-            client: ClientId(1),
-            kind: TransactionKind::Chargeback,
-            id: TransactionId(1), //first deposit
-        };
+    /// Auto-resolves every open dispute opened before `cutoff`, releasing its held funds.
+    /// Returns whether a dispute was resolved.
+    pub fn expire_disputes(&mut self, cutoff: DateTime<Utc>) -> bool {
+        self.resolve_disputes(|dispute| dispute.is_stale(cutoff), Dispute::auto_resolve)
+    }
 
-        let transactions = vec![deposit, dispute, chargeback];
+    /// Resolves every open dispute, releasing its held funds. Charged back disputes are
+    /// left alone. Returns whether a dispute was resolved.
+    pub fn resolve_all_open(&mut self) -> bool {
+        self.resolve_disputes(Dispute::can_finish, |dispute| dispute.resolve(None))
+    }
 
-        let account = transactions
-            .into_iter()
-            .fold(Account::new(Decimal::ZERO), |mut acc, tx| {
-                acc.process_transaction(tx);
-                println!("After processing transaction {:?}, account state is: available: {}, held: {}, total: {}, locked: {}",
-                    tx, acc.available, acc.held, acc.total_funds(), acc.locked);
-                acc
-            });
-        assert_eq!(account.available, Decimal::ZERO);
-        assert_eq!(account.held, Decimal::ZERO);
-        assert_eq!(account.total_funds(), Decimal::ZERO);
-        assert_eq!(account.disputes.len(), 1);
-        assert!(account.disputes.contains_key(&TransactionId(1)));
+    /// Fully resolves the open disputes matching `predicate` with `resolve`, releasing their
+    /// held funds in whatever currency they are. Returns whether a dispute was resolved.
+    fn resolve_disputes(
+        &mut self,
+        predicate: impl Fn(&Dispute) -> bool,
+        resolve: impl Fn(&mut Dispute) -> Decimal,
+    ) -> bool {
+        let mut released = Vec::new();
+        for dispute in self.disputes.values_mut() {
+            if dispute.can_finish() && predicate(dispute) {
+                let of_withdrawal = dispute.is_of_withdrawal();
+                released.push((dispute.currency().cloned(), resolve(dispute), of_withdrawal));
+                self.open_disputes -= 1;
+            }
+        }
+        // Whole remainders are released, which are already rounded if the partial resolves
+        // were.
+        let resolved = !released.is_empty();
+        for (currency, amount, of_withdrawal) in released {
+            self.settle_resolve(currency.as_ref(), amount, of_withdrawal, false);
+        }
+        resolved
+    }
+
+    /// Credits the simple interest of the whole days elapsed since the last accrual up to
+    /// `now`, rounded with [`round_amount`]. The rest of a day carries over to the next
+    /// accrual. The first call only starts counting, and locked accounts or accounts
+    /// without available funds accrue nothing. Locked accounts keep moving the start of the
+    /// next accrual, so an unlocked account is not credited for the time it was locked.
+    /// Returns whether interest was credited.
+    pub fn accrue_interest(&mut self, policy: &InterestPolicy, now: DateTime<Utc>) -> bool {
+        if self.locked {
+            self.accrued_until = Some(self.accrued_until.map_or(now, |from| from.max(now)));
+            return false;
+        }
+        let Some(from) = self.accrued_until else {
+            self.accrued_until = Some(now);
+            return false;
+        };
+        let days = (now - from).num_days();
+        if days <= 0 {
+            return false;
+        }
+        let to = from + chrono::Duration::days(days);
+        self.accrued_until = Some(to);
+
+        let amount = round_amount(
+            self.available.max(Decimal::ZERO) * policy.daily_rate * Decimal::from(days),
+        );
+        if amount.is_zero() {
+            return false;
+        }
+        self.available += amount;
+        self.interest.push(InterestEntry {
+            amount,
+            from,
+            to,
+            after: self.transactions.len(),
+        });
+        true
+    }
+
+    /// History of the account with the interest credits, in the order they happened.
+    pub fn ledger(&self) -> impl Iterator<Item = LedgerEntry<'_>> {
+        let mut interest = self.interest.iter().peekable();
+        let mut transactions = self.transactions.values().enumerate().peekable();
+        std::iter::from_fn(move || {
+            let next_position = transactions.peek().map(|(position, _)| *position);
+            match interest.peek() {
+                Some(entry) if next_position.is_none_or(|position| entry.after <= position) => {
+                    interest.next().map(LedgerEntry::Interest)
+                }
+                _ => transactions
+                    .next()
+                    .map(|(_, transaction)| LedgerEntry::Transaction(transaction)),
+            }
+        })
+    }
+
+    /// Account re-derived from an empty one by processing again with `config` its history
+    /// and the accepted transactions referencing an earlier one, in the order they were
+    /// accepted. Only the per-client minimum balance is kept from this account.
+    ///
+    /// The replay differs from this account if it was seeded from a snapshot, if the engine
+    /// resolved its disputes or credited interest outside of its transactions, or if
+    /// rejected transactions counted towards the auto-resolve horizon, the reversal window or
+    /// the interest. The activity is recorded by the engine, so it is not replayed.
+    pub fn replay(&self, config: &EngineConfig) -> Account {
+        let mut account = Account::with_hasher(Decimal::ZERO, self.disputes.hasher().clone());
+        account.min_balance = self.min_balance;
+        let mut references = self.references.iter().peekable();
+        for (position, transaction) in self.transactions.values().enumerate() {
+            while let Some((_, reference)) = references.next_if(|(after, _)| *after <= position) {
+                // Rejections are part of what the replay is compared for.
+                let _ = account.try_process_with(reference.clone(), config);
+            }
+            let _ = account.try_process_with(transaction.clone(), config);
+        }
+        for (_, reference) in references {
+            let _ = account.try_process_with(reference.clone(), config);
+        }
+        account
+    }
+
+    /// Records an accepted transaction as the last activity of the account, and as the first
+    /// one if there was none.
+    pub fn record_activity(&mut self, activity: Activity) {
+        self.first_activity.get_or_insert(activity);
+        self.last_activity = Some(activity);
+    }
+
+    /// Unfreezes the account, e.g. after a chargeback made in error. The charged back funds
+    /// are not restored.
+    pub fn unlock(&mut self) {
+        self.locked = false;
+        self.lock_info = None;
+    }
+
+    /// Lifetime figures of the transactions the account accepted.
+    pub fn gross_totals(&self) -> GrossTotals {
+        self.gross
+    }
+
+    /// Chargeback that locked the account. `None` if the account is not locked, or was
+    /// seeded as locked from a balances snapshot.
+    pub fn lock_info(&self) -> Option<&LockInfo> {
+        self.lock_info.as_ref()
+    }
+
+    /// Transactions of the history whose kind matches `kind_filter`, in processing order.
+    pub fn transactions_of_kind(
+        &self,
+        kind_filter: impl Fn(&TransactionKind) -> bool,
+    ) -> Vec<&Transaction> {
+        self.transactions
+            .values()
+            .filter(|transaction| kind_filter(&transaction.kind))
+            .collect()
+    }
+
+    /// First deposit or withdrawal of the history, `None` if the account recorded none.
+    pub fn first_transaction(&self) -> Option<&Transaction> {
+        self.transactions.values().find(|transaction| {
+            matches!(
+                transaction.kind,
+                TransactionKind::Deposit { .. } | TransactionKind::Withdrawal { .. }
+            )
+        })
+    }
+
+    /// Last deposit or withdrawal of the history, `None` if the account recorded none.
+    /// Disputes, administrative operations and rejected transactions are skipped.
+    pub fn last_transaction(&self) -> Option<&Transaction> {
+        self.transactions.values().rfind(|transaction| {
+            matches!(
+                transaction.kind,
+                TransactionKind::Deposit { .. } | TransactionKind::Withdrawal { .. }
+            )
+        })
+    }
+
+    /// Position of the transaction in the history, i.e. the number of transactions of the
+    /// history processed before it.
+    pub fn tx_position(&self, tx: TransactionId) -> Option<usize> {
+        self.transactions.get_index_of(&tx)
+    }
+
+    /// If the transaction has an open dispute, i.e. one not resolved nor charged back.
+    pub fn is_disputed(&self, tx: TransactionId) -> bool {
+        self.disputes
+            .get(&tx)
+            .is_some_and(|dispute| dispute.can_finish())
+    }
+
+    /// If the deposit was recalled by a [`TransactionKind::Refund`].
+    pub fn is_refunded(&self, tx: TransactionId) -> bool {
+        self.refunded.contains(&tx)
+    }
+
+    /// Number of disputes that are neither resolved nor charged back.
+    pub fn open_dispute_count(&self) -> usize {
+        self.open_disputes
+    }
+
+    /// Sum of the funds of `currency` held by open disputes, i.e. what would be released if
+    /// all of them were resolved. Under correct bookkeeping this is always equal to the
+    /// `held` of [`Account::balance`] in `currency`.
+    pub fn total_open_held(&self, currency: Option<&Currency>) -> Decimal {
+        self.disputes
+            .values()
+            .filter(|dispute| dispute.can_finish() && dispute.currency() == currency)
+            .map(|dispute| dispute.held())
+            .sum()
+    }
+
+    /// Sum of the funds of `currency` removed from the account by chargebacks.
+    pub fn total_charged_back(&self, currency: Option<&Currency>) -> Decimal {
+        self.disputes
+            .values()
+            .filter(|dispute| dispute.currency() == currency)
+            .map(Dispute::charged_back)
+            .sum()
+    }
+
+    /// Returns the disputed deposit transaction if it exists.
+    pub fn disputed_deposit(&self, transaction_id: TransactionId) -> Option<Decimal> {
+        let transaction = self.transactions.get(&transaction_id)?;
+        transaction.deposit_amount()
+    }
+
+    /// Decreases the account's available funds and increases the `held` funds. Note that
+    /// if the account does not have enough funds, this will result in a negative balance.
+    /// However, since the held value increases by the same amount that available funds
+    /// decrease, the total sum does not change. With `round`, both are then rounded like
+    /// the output, see [`EngineConfig::round_each_op`].
+    pub fn hold_funds(
+        &mut self,
+        currency: Option<&Currency>,
+        disputed_amount: Decimal,
+        round: bool,
+    ) {
+        let (available, held) = self.funds_mut(currency);
+        *available -= disputed_amount;
+        *held += disputed_amount;
+        if round {
+            *available = round_amount(*available);
+            *held = round_amount(*held);
+        }
+    }
+
+    /// Releases the held funds back to the account available funds. With `round`, both are
+    /// then rounded like the output, see [`EngineConfig::round_each_op`].
+    pub fn release_held_funds(
+        &mut self,
+        currency: Option<&Currency>,
+        disputed_amount: Decimal,
+        round: bool,
+    ) {
+        let (available, held) = self.funds_mut(currency);
+        *held -= disputed_amount;
+        *available += disputed_amount;
+        if round {
+            *available = round_amount(*available);
+            *held = round_amount(*held);
+        }
+    }
+
+    /// Withdraws the held funds from the account and locks it. The first chargeback
+    /// locking the account is kept as its `lock`.
+    pub fn chargeback_and_lock(
+        &mut self,
+        currency: Option<&Currency>,
+        disputed_amount: Decimal,
+        lock: LockInfo,
+    ) {
+        *self.funds_mut(currency).1 -= disputed_amount;
+        self.locked = true;
+        self.lock_info.get_or_insert(lock);
+    }
+}
+
+/// Clamps a withdrawal of `amount` to the `available` funds with
+/// [`EngineConfig::clamp_withdrawals`], telling whether it was clamped. Withdrawals from an
+/// account without available funds are still rejected.
+fn clamp_withdrawal(
+    amount: Decimal,
+    available: Decimal,
+    config: &EngineConfig,
+) -> Result<(Decimal, bool), TransactionError> {
+    if !config.clamp_withdrawals || amount <= available {
+        return Ok((amount, false));
+    }
+    if available <= Decimal::ZERO {
+        return Err(TransactionError::InsufficientFunds {
+            requested: amount,
+            available,
+        });
+    }
+    Ok((available, true))
+}
+
+/// Rejects a dispute transaction in `given` currency of a deposit in `expected` currency.
+/// Dispute transactions without a currency always match.
+fn check_currency(
+    expected: Option<&Currency>,
+    given: Option<&Currency>,
+) -> Result<(), TransactionError> {
+    if given.is_some_and(|given| Some(given) != expected) {
+        return Err(TransactionError::CurrencyMismatch);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, FixedOffset, TimeZone};
+
+    use crate::{
+        assert_balances,
+        engine::FeeSchedule,
+        testkit::{self, TxSeq, account_from, account_from_with},
+        transaction::ClientId,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_deposit() {
+        let seq = (0..10).fold(TxSeq::new(), |seq, i| seq.deposit(1, i, "10"));
+        let account = account_from(seq);
+
+        assert_balances!(account, available: "100", held: "0", total: "100");
+        for i in 0..10 {
+            assert!(account.transactions.contains_key(&TransactionId(i)));
+        }
+    }
+
+    #[test]
+    fn test_withdraw() {
+        let seq = (0..10).fold(TxSeq::new(), |seq, i| seq.deposit(1, i, "10"));
+        let account = account_from(seq.withdrawal(1, 15, "5"));
+
+        assert_balances!(account, available: "95", held: "0");
+        assert!(account.transactions.contains_key(&TransactionId(15)));
+        for i in 0..10 {
+            assert!(account.transactions.contains_key(&TransactionId(i)));
+        }
+    }
+
+    // Withdraw with insufficient funds should not be processed and should not be added to the transaction history.
+    #[test]
+    fn insufficient_funds() {
+        let mut account = account_from(TxSeq::new().deposit(1, 1, "5"));
+        assert_eq!(
+            account.process_transaction(testkit::withdrawal(1, 15, "100")),
+            Err(TransactionError::InsufficientFunds {
+                requested: Decimal::new(100, 0),
+                available: Decimal::new(5, 0),
+            })
+        );
+        assert_balances!(account, available: "5", held: "0");
+        assert!(!account.transactions.contains_key(&TransactionId(15)));
+    }
+
+    #[test]
+    fn test_withdraw_exact_balance() {
+        let config = EngineConfig {
+            withdrawal_fee: Some(FeeSchedule {
+                flat: Decimal::ONE,
+                ..FeeSchedule::default()
+            }),
+            ..EngineConfig::default()
+        };
+        let mut account = account_from(TxSeq::new().deposit(1, 1, "10"));
+        let result = account.try_process(testkit::withdrawal(1, 2, "10"));
+        assert_eq!(
+            result,
+            Err(TransactionError::WholeBalanceWithdrawal {
+                available: Decimal::TEN
+            })
+        );
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "withdrawal would leave no available funds, withdraw less than 10"
+        );
+        assert_balances!(account, available: "10", total: "10");
+
+        // The fee counts towards the funds needed.
+        let mut account = account_from_with(TxSeq::new().deposit(1, 1, "10"), &config);
+        assert_eq!(
+            account.try_process_with(testkit::withdrawal(1, 2, "9"), &config),
+            Err(TransactionError::WholeBalanceWithdrawal {
+                available: Decimal::TEN
+            })
+        );
+        account
+            .process_transaction_with(testkit::withdrawal(1, 3, "8.5"), &config)
+            .unwrap();
+        assert_balances!(account, available: "0.5", total: "0.5");
+    }
+
+    // Basic dispute case
+    #[test]
+    fn test_dispute() {
+        // Client deposits 100, then deposits 50 more, then disputes the first transaction.
+        // 100 is moved to held, 50 is still available. Total is 150.
+        let account = account_from(
+            TxSeq::new()
+                .deposit(1, 1, "100")
+                .deposit(1, 2, "50")
+                .dispute(1, 1),
+        );
+        assert_balances!(account, available: "50", held: "100", total: "150");
+        assert_eq!(account.disputes.len(), 1);
+        assert!(account.disputes.contains_key(&TransactionId(1)));
+    }
+
+    #[test]
+    fn test_resolve() {
+        // Client deposits 100, then disputes it, then resolves the dispute.
+        // After the dispute, 100 is held and 0 is available. After the resolve, 100 is available again and 0 is held.
+        let account = account_from(
+            TxSeq::new()
+                .deposit(1, 1, "100")
+                .dispute(1, 1)
+                .resolve(1, 1),
+        );
+        assert_balances!(account, available: "100", held: "0", total: "100");
+        assert_eq!(account.disputes.len(), 1);
+        assert!(account.disputes.contains_key(&TransactionId(1)));
+    }
+
+    #[test]
+    fn test_blocked_account_after_chargeback() {
+        // Client deposits 100, then disputes it, then chargebacks the dispute.
+        // After the dispute, 100 is held and 0 is available. After the chargeback, 0 is held, 0 is available and the account is locked.
+        let account = account_from(
+            TxSeq::new()
+                .deposit(1, 1, "100")
+                .dispute(1, 1)
+                .chargeback(1, 1),
+        );
+        assert_balances!(account, available: "0", held: "0", total: "0", locked: true);
+        assert_eq!(account.disputes.len(), 1);
+        assert!(account.disputes.contains_key(&TransactionId(1)));
         assert!(account.locked);
     }
+
+    #[test]
+    fn test_transactions_of_kind() {
+        let account = account_from(
+            TxSeq::new()
+                .deposit(1, 1, "10")
+                .withdrawal(1, 2, "3")
+                .deposit(1, 3, "5")
+                .dispute(1, 1)
+                .withdrawal(1, 4, "1"),
+        );
+
+        let ids = |transactions: Vec<&Transaction>| -> Vec<u32> {
+            transactions
+                .iter()
+                .map(|transaction| transaction.id.0)
+                .collect()
+        };
+        assert_eq!(
+            ids(account
+                .transactions_of_kind(|kind| matches!(kind, TransactionKind::Deposit { .. }))),
+            vec![1, 3]
+        );
+        assert_eq!(
+            ids(account
+                .transactions_of_kind(|kind| matches!(kind, TransactionKind::Withdrawal { .. }))),
+            vec![2, 4]
+        );
+        assert!(
+            account
+                .transactions_of_kind(|kind| matches!(kind, TransactionKind::Dispute { .. }))
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_first_and_last_transaction() {
+        let mut account = Account::new(Decimal::ZERO);
+        assert_eq!(account.first_transaction(), None);
+        assert_eq!(account.last_transaction(), None);
+
+        account
+            .process_transaction(testkit::deposit(1, 1, "10"))
+            .unwrap();
+        assert_eq!(account.first_transaction().map(|tx| tx.id.0), Some(1));
+        assert_eq!(account.last_transaction().map(|tx| tx.id.0), Some(1));
+
+        for transaction in TxSeq::new().withdrawal(1, 2, "1").deposit(1, 3, "10") {
+            account.process_transaction(transaction).unwrap();
+        }
+        // Neither a dispute nor a rejected withdrawal is recorded in the history.
+        account.process_transaction(testkit::dispute(1, 1)).unwrap();
+        assert!(
+            account
+                .process_transaction(testkit::withdrawal(1, 4, "100"))
+                .is_err()
+        );
+
+        assert_eq!(
+            account.first_transaction(),
+            Some(&testkit::deposit(1, 1, "10"))
+        );
+        assert_eq!(
+            account.last_transaction(),
+            Some(&testkit::deposit(1, 3, "10"))
+        );
+
+        // Administrative operations are recorded in the history, but are not monetary.
+        let config = EngineConfig {
+            allow_admin_ops: true,
+            ..EngineConfig::default()
+        };
+        account
+            .try_process_with(testkit::transaction(1, 5, TransactionKind::Freeze), &config)
+            .unwrap();
+        assert_eq!(
+            account.last_transaction(),
+            Some(&testkit::deposit(1, 3, "10"))
+        );
+    }
+
+    #[test]
+    fn test_is_disputed() {
+        let mut account = account_from(TxSeq::new().deposit(1, 1, "100"));
+        assert!(!account.is_disputed(TransactionId(1)));
+
+        account.process_transaction(testkit::dispute(1, 1)).unwrap();
+        assert!(account.is_disputed(TransactionId(1)));
+
+        account.process_transaction(testkit::resolve(1, 1)).unwrap();
+        assert!(!account.is_disputed(TransactionId(1)));
+        assert!(!account.is_disputed(TransactionId(2)));
+    }
+
+    #[test]
+    fn test_total_open_held() {
+        // Three deposits, the first two disputed at the same time. Once the first dispute is
+        // resolved, only the second one is still holding funds.
+        let mut account = account_from(
+            TxSeq::new()
+                .deposit(1, 1, "100")
+                .deposit(1, 2, "50")
+                .deposit(1, 3, "25"),
+        );
+        assert_eq!(account.total_open_held(None), Decimal::ZERO);
+
+        for id in [1, 2] {
+            account
+                .process_transaction(testkit::dispute(1, id))
+                .unwrap();
+        }
+        assert_eq!(account.total_open_held(None), Decimal::new(150, 0));
+        assert_eq!(account.total_open_held(None), account.held);
+
+        account.process_transaction(testkit::resolve(1, 1)).unwrap();
+        assert_eq!(account.total_open_held(None), Decimal::new(50, 0));
+        assert_eq!(account.total_open_held(None), account.held);
+
+        // Disputes in another currency are summed apart.
+        let eur = Currency("EUR".to_string());
+        let in_eur = |transaction| Transaction {
+            currency: Some(eur.clone()),
+            ..transaction
+        };
+        for transaction in TxSeq::new().deposit(1, 4, "30").dispute(1, 4) {
+            account.process_transaction(in_eur(transaction)).unwrap();
+        }
+        assert_eq!(account.total_open_held(Some(&eur)), Decimal::new(30, 0));
+        assert_eq!(
+            account.total_open_held(Some(&eur)),
+            account.balance(Some(&eur)).held
+        );
+        account
+            .process_transaction(in_eur(testkit::chargeback(1, 4)))
+            .unwrap();
+        assert_eq!(account.total_open_held(None), Decimal::new(50, 0));
+        assert_eq!(account.total_open_held(Some(&eur)), Decimal::ZERO);
+        assert_eq!(account.total_charged_back(None), Decimal::ZERO);
+        assert_eq!(account.total_charged_back(Some(&eur)), Decimal::new(30, 0));
+    }
+
+    #[test]
+    fn test_chargeback_after_partial_resolve() {
+        // Client deposits 100 and disputes it, then 30 are released by a partial resolve.
+        // The chargeback can only take the 70 still held, so held does not go negative and
+        // the remaining 30 are recorded as shortfall.
+        let mut account = account_from(
+            TxSeq::new()
+                .deposit(1, 1, "100")
+                .dispute(1, 1)
+                .partial_resolve(1, 1, "30"),
+        );
+        assert_balances!(account, available: "30", held: "70");
+        assert!(account.is_disputed(TransactionId(1)));
+
+        account
+            .process_transaction(testkit::chargeback(1, 1))
+            .unwrap();
+
+        let dispute = &account.disputes[&TransactionId(1)];
+        assert_balances!(account, available: "30", held: "0", total: "30", locked: true);
+        assert_eq!(dispute.held(), Decimal::ZERO);
+        assert_eq!(dispute.shortfall(), Decimal::new(30, 0));
+    }
+
+    #[test]
+    fn test_round_each_op() {
+        // Ten deposits of 0.00005 add up to 0.0005, but rounding to even after each one
+        // brings every intermediate balance back to 0.
+        let deposits = (1..=10).fold(TxSeq::new(), |seq, id| seq.deposit(1, id, "0.00005"));
+        let run = |config: &EngineConfig| account_from_with(deposits.clone(), config).available;
+
+        assert_eq!(run(&EngineConfig::default()), Decimal::new(5, 4));
+        let config = EngineConfig {
+            round_each_op: true,
+            ..EngineConfig::default()
+        };
+        assert_eq!(run(&config), Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_round_partial_resolves() {
+        let config = EngineConfig {
+            round_each_op: true,
+            ..EngineConfig::default()
+        };
+        let mut account = account_from_with(
+            TxSeq::new()
+                .deposit(1, 1, "100")
+                .dispute(1, 1)
+                .partial_resolve(1, 1, "33.33333")
+                .partial_resolve(1, 1, "33.33333")
+                .partial_resolve(1, 1, "33.33333"),
+            &config,
+        );
+        // Each resolve released 33.3333, so the dispute holds exactly what the account does.
+        assert_eq!(account.held, Decimal::new(1, 4));
+        assert_eq!(
+            account.disputes[&TransactionId(1)].held(),
+            Decimal::new(1, 4)
+        );
+
+        account
+            .process_transaction_with(testkit::resolve(1, 1), &config)
+            .unwrap();
+        assert!(account.held.is_zero() && account.held.is_sign_positive());
+        assert_eq!(account.available, Decimal::new(100, 0));
+        assert_eq!(
+            account.disputes[&TransactionId(1)].state(),
+            &DisputeState::Resolved
+        );
+    }
+
+    #[test]
+    fn test_duplicate_dispute() {
+        let run = |policy| {
+            let config = EngineConfig {
+                on_duplicate_dispute: policy,
+                ..EngineConfig::default()
+            };
+            let mut account =
+                account_from_with(TxSeq::new().deposit(1, 1, "10").dispute(1, 1), &config);
+            let duplicate = account.process_transaction_with(testkit::dispute(1, 1), &config);
+            (account, duplicate)
+        };
+
+        for (policy, expected) in [
+            (DuplicateDisputePolicy::Ignore, Ok(())),
+            (
+                DuplicateDisputePolicy::Error,
+                Err(TransactionError::AlreadyDisputed),
+            ),
+        ] {
+            let (account, duplicate) = run(policy);
+            assert_eq!(duplicate, expected);
+            // The funds are only held once.
+            assert_balances!(account, available: "0", held: "10");
+            assert_eq!(account.total_open_held(None), account.held);
+        }
+    }
+
+    #[test]
+    fn test_total_charged_back() {
+        // An account is locked by its first chargeback, so the disputes are set up directly.
+        let mut account = Account::new(Decimal::ZERO);
+        assert_eq!(account.total_charged_back(None), Decimal::ZERO);
+
+        let mut first = Dispute::new(Decimal::new(100, 0), None, None, 0);
+        first.chargeback();
+        let mut second = Dispute::new(Decimal::new(30, 0), None, None, 0);
+        second.resolve(Some(Decimal::new(10, 0)));
+        second.chargeback();
+        let open = Dispute::new(Decimal::new(5, 0), None, None, 0);
+        let mut resolved = Dispute::new(Decimal::new(7, 0), None, None, 0);
+        resolved.resolve(None);
+        for (id, dispute) in [first, second, open, resolved].into_iter().enumerate() {
+            account.disputes.insert(TransactionId(id as u32), dispute);
+        }
+        assert_eq!(account.total_charged_back(None), Decimal::new(120, 0));
+
+        // Without any chargeback, nothing was lost.
+        let account = account_from(TxSeq::new().deposit(1, 1, "10").dispute(1, 1));
+        assert_eq!(account.total_charged_back(None), Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_held_limit() {
+        // With a cap of 120 held, disputing the deposit of 100 is accepted but disputing the
+        // one of 50 would hold 150, so it is rejected and nothing more is held.
+        let config = EngineConfig {
+            max_held_per_account: Some(Decimal::new(120, 0)),
+            ..EngineConfig::default()
+        };
+        let mut account = account_from_with(
+            TxSeq::new().deposit(1, 1, "100").deposit(1, 2, "50"),
+            &config,
+        );
+
+        assert_eq!(
+            account.process_transaction_with(testkit::dispute(1, 1), &config),
+            Ok(())
+        );
+        assert_eq!(
+            account.process_transaction_with(testkit::dispute(1, 2), &config),
+            Err(TransactionError::HeldLimitReached)
+        );
+
+        assert_balances!(account, available: "50", held: "100");
+        assert!(!account.is_disputed(TransactionId(2)));
+    }
+
+    /// Account with a deposit of 100 as transaction 1 and a withdrawal of 30 as 2.
+    fn funded_account() -> Account {
+        account_from(TxSeq::new().deposit(1, 1, "100").withdrawal(1, 2, "30"))
+    }
+
+    #[test]
+    fn test_partial_dispute() {
+        let mut account = account_from(TxSeq::new().deposit(1, 1, "100"));
+
+        // Disputing more than the deposit is rejected and holds nothing.
+        assert_eq!(
+            account.process_transaction(testkit::partial_dispute(1, 1, "150")),
+            Err(TransactionError::DisputeAmountTooLarge {
+                requested: Decimal::new(150, 0),
+                disputed: Decimal::ONE_HUNDRED,
+            })
+        );
+        assert!(!account.is_disputed(TransactionId(1)));
+        assert_balances!(account, available: "100", held: "0");
+
+        // A partial dispute only holds its amount, which a resolve releases.
+        account
+            .process_transaction(testkit::partial_dispute(1, 1, "40"))
+            .unwrap();
+        assert_balances!(account, available: "60", held: "40");
+        account.process_transaction(testkit::resolve(1, 1)).unwrap();
+        assert_balances!(account, available: "100", held: "0");
+    }
+
+    #[test]
+    fn test_open_dispute() {
+        let config = EngineConfig::default();
+        let mut account = funded_account();
+
+        // Unknown transactions are ignored, and withdrawals cannot be disputed by default.
+        account
+            .open_dispute(ClientId(1), TransactionId(7), None, None, None, &config)
+            .unwrap();
+        assert_eq!(
+            account.open_dispute(ClientId(1), TransactionId(2), None, None, None, &config),
+            Err(TransactionError::NotDisputable)
+        );
+        assert!(account.disputes.is_empty());
+
+        account
+            .open_dispute(ClientId(1), TransactionId(1), None, None, None, &config)
+            .unwrap();
+        assert!(account.is_disputed(TransactionId(1)));
+        assert_eq!(account.available, Decimal::new(-30, 0));
+        assert_eq!(account.held, Decimal::new(100, 0));
+
+        // Disputing it again holds nothing more.
+        account
+            .open_dispute(ClientId(1), TransactionId(1), None, None, None, &config)
+            .unwrap();
+        assert_eq!(account.held, Decimal::new(100, 0));
+        let config = EngineConfig {
+            on_duplicate_dispute: DuplicateDisputePolicy::Error,
+            ..EngineConfig::default()
+        };
+        assert_eq!(
+            account.open_dispute(ClientId(1), TransactionId(1), None, None, None, &config),
+            Err(TransactionError::AlreadyDisputed)
+        );
+    }
+
+    #[test]
+    fn test_resolve_dispute() {
+        let mut account = funded_account();
+        account
+            .resolve_dispute(TransactionId(1), None, None, false)
+            .unwrap();
+        assert_eq!(account.available, Decimal::new(70, 0));
+
+        account
+            .open_dispute(
+                ClientId(1),
+                TransactionId(1),
+                None,
+                None,
+                None,
+                &EngineConfig::default(),
+            )
+            .unwrap();
+        account
+            .resolve_dispute(TransactionId(1), Some(Decimal::new(40, 0)), None, false)
+            .unwrap();
+        assert_eq!(account.held, Decimal::new(60, 0));
+        assert!(account.is_disputed(TransactionId(1)));
+
+        account
+            .resolve_dispute(TransactionId(1), None, None, false)
+            .unwrap();
+        assert_eq!(account.held, Decimal::ZERO);
+        assert_eq!(account.available, Decimal::new(70, 0));
+        assert!(!account.is_disputed(TransactionId(1)));
+
+        // A resolved dispute cannot be resolved again.
+        account
+            .resolve_dispute(TransactionId(1), None, None, false)
+            .unwrap();
+        assert_eq!(account.available, Decimal::new(70, 0));
+    }
+
+    #[test]
+    fn test_chargeback_dispute() {
+        let mut account = funded_account();
+        account
+            .chargeback_dispute(TransactionId(1), None, None)
+            .unwrap();
+        assert!(!account.locked);
+
+        account
+            .open_dispute(
+                ClientId(1),
+                TransactionId(1),
+                None,
+                None,
+                None,
+                &EngineConfig::default(),
+            )
+            .unwrap();
+        account
+            .resolve_dispute(TransactionId(1), Some(Decimal::new(25, 0)), None, false)
+            .unwrap();
+        account
+            .chargeback_dispute(TransactionId(1), None, None)
+            .unwrap();
+        assert!(account.locked);
+        assert_eq!(account.held, Decimal::ZERO);
+        assert_eq!(account.available, Decimal::new(-5, 0));
+        assert_eq!(account.total_charged_back(None), Decimal::new(75, 0));
+    }
+
+    #[test]
+    fn test_dispute_window() {
+        let config = EngineConfig {
+            dispute_window: Some(Duration::days(90)),
+            ..EngineConfig::default()
+        };
+        let deposited_at = DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let mut account = Account::new(Decimal::ZERO);
+        for (id, timestamp) in [(1, Some(deposited_at)), (2, Some(deposited_at)), (3, None)] {
+            account
+                .process_transaction(Transaction {
+                    timestamp,
+                    ..testkit::deposit(1, id, "10")
+                })
+                .unwrap();
+        }
+
+        let mut dispute = |id, timestamp| {
+            account.process_transaction_with(
+                Transaction {
+                    timestamp,
+                    ..testkit::dispute(1, id)
+                },
+                &config,
+            )
+        };
+        assert_eq!(dispute(1, Some(deposited_at + Duration::days(90))), Ok(()));
+        assert_eq!(
+            dispute(2, Some(deposited_at + Duration::days(91))),
+            Err(TransactionError::DisputeWindowExpired)
+        );
+        assert_eq!(dispute(2, None), Err(TransactionError::MissingTimestamp));
+        assert_eq!(
+            dispute(3, Some(deposited_at)),
+            Err(TransactionError::MissingTimestamp)
+        );
+
+        assert_eq!(account.held, Decimal::new(10, 0));
+        assert!(!account.is_disputed(TransactionId(2)));
+    }
+
+    #[test]
+    fn test_daily_withdrawal_limit() {
+        let config = EngineConfig {
+            daily_withdrawal_limit: Some(Decimal::new(100, 0)),
+            ..EngineConfig::default()
+        };
+        let mut account = Account::new(Decimal::new(1000, 0));
+        let mut withdraw = |id, amount, time: &str| {
+            let timestamp = DateTime::parse_from_rfc3339(time).unwrap().to_utc();
+            account.process_transaction_with(
+                Transaction {
+                    timestamp: Some(timestamp),
+                    ..testkit::withdrawal(1, id, amount)
+                },
+                &config,
+            )
+        };
+
+        assert_eq!(withdraw(1, "60", "2024-06-01T09:00:00Z"), Ok(()));
+        assert_eq!(
+            withdraw(2, "50", "2024-06-01T23:59:59Z"),
+            Err(TransactionError::DailyLimitExceeded {
+                used: Decimal::new(60, 0),
+                limit: Decimal::new(100, 0),
+                tier: None,
+            })
+        );
+        // Exactly reaching the limit is accepted.
+        assert_eq!(withdraw(3, "40", "2024-06-01T23:59:59Z"), Ok(()));
+        // The total restarts at midnight.
+        assert_eq!(withdraw(4, "100", "2024-06-02T00:00:00Z"), Ok(()));
+        assert_eq!(
+            account.daily_withdrawn,
+            Some((
+                NaiveDate::from_ymd_opt(2024, 6, 2).unwrap(),
+                Decimal::new(100, 0)
+            ))
+        );
+        assert_eq!(account.available, Decimal::new(800, 0));
+
+        assert_eq!(
+            account.process_transaction_with(testkit::withdrawal(1, 5, "1"), &config),
+            Err(TransactionError::MissingTimestamp)
+        );
+    }
+
+    #[test]
+    fn test_daily_withdrawal_limit_offset() {
+        // At UTC-05:00, 03:00 UTC is still the previous day.
+        let config = EngineConfig {
+            daily_withdrawal_limit: Some(Decimal::new(100, 0)),
+            day_utc_offset: Some(FixedOffset::west_opt(5 * 3600).unwrap()),
+            ..EngineConfig::default()
+        };
+        let mut account = Account::new(Decimal::new(1000, 0));
+        let mut withdraw = |id, time: &str| {
+            let timestamp = DateTime::parse_from_rfc3339(time).unwrap().to_utc();
+            account.process_transaction_with(
+                Transaction {
+                    timestamp: Some(timestamp),
+                    ..testkit::withdrawal(1, id, "60")
+                },
+                &config,
+            )
+        };
+
+        assert_eq!(withdraw(1, "2024-06-01T20:00:00Z"), Ok(()));
+        assert!(withdraw(2, "2024-06-02T03:00:00Z").is_err());
+        assert_eq!(withdraw(3, "2024-06-02T05:00:00Z"), Ok(()));
+    }
+
+    #[test]
+    fn test_implicit_dispute_on_resolve() {
+        let mut account = funded_account();
+        account.process_transaction(testkit::resolve(1, 1)).unwrap();
+        assert!(account.disputes.is_empty());
+
+        let config = EngineConfig {
+            implicit_dispute_on_resolve: true,
+            ..EngineConfig::default()
+        };
+        // Withdrawals and unknown transactions still cannot be disputed.
+        for id in [1, 2, 7] {
+            account
+                .process_transaction_with(testkit::resolve(1, id), &config)
+                .unwrap();
+        }
+        let funded = funded_account();
+        assert_balances!(account, available: funded.available, held: "0");
+        assert_eq!(account.disputes.len(), 1);
+        assert_eq!(
+            account.disputes[&TransactionId(1)].state(),
+            &DisputeState::Resolved
+        );
+        assert_eq!(account.total_open_held(None), Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_max_withdrawal() {
+        let config = EngineConfig {
+            max_withdrawal: Some(Decimal::new(50, 0)),
+            ..EngineConfig::default()
+        };
+        let mut account = funded_account();
+        let mut withdraw = |id, amount| {
+            account.process_transaction_with(testkit::withdrawal(1, id, amount), &config)
+        };
+
+        let exceeded = Err(TransactionError::WithdrawalLimitExceeded {
+            limit: Decimal::new(50, 0),
+            tier: None,
+        });
+        assert_eq!(withdraw(3, "51"), exceeded);
+        assert_eq!(withdraw(4, "50"), Ok(()));
+        // Above both the limit and the available funds, the limit is reported.
+        assert_eq!(withdraw(5, "60"), exceeded);
+        assert_eq!(
+            withdraw(6, "30"),
+            Err(TransactionError::InsufficientFunds {
+                requested: Decimal::new(30, 0),
+                available: Decimal::new(20, 0),
+            })
+        );
+        assert_eq!(account.available, Decimal::new(20, 0));
+    }
+
+    #[test]
+    fn test_withdrawal_fee() {
+        let config = EngineConfig {
+            withdrawal_fee: Some(FeeSchedule {
+                flat: Decimal::new(25, 2),
+                percent: Decimal::ONE,
+                ..FeeSchedule::default()
+            }),
+            ..EngineConfig::default()
+        };
+        let mut account = funded_account();
+        let mut process = |transaction| account.process_transaction_with(transaction, &config);
+
+        // 69.5 fits in the 70 available, but not with its fee of 0.25 + 0.695.
+        assert_eq!(
+            process(testkit::withdrawal(1, 3, "69.5")),
+            Err(TransactionError::InsufficientFunds {
+                requested: Decimal::new(70445, 3),
+                available: Decimal::new(70, 0),
+            })
+        );
+        process(testkit::withdrawal(1, 4, "50")).unwrap();
+        // Dispute movements are not charged.
+        process(testkit::dispute(1, 1)).unwrap();
+        process(testkit::resolve(1, 1)).unwrap();
+
+        assert_eq!(account.available, Decimal::new(1925, 2));
+        assert_eq!(account.fees_paid, Decimal::new(75, 2));
+        assert_eq!(
+            account.available + Decimal::new(30 + 50, 0) + account.fees_paid,
+            Decimal::new(100, 0)
+        );
+    }
+
+    #[test]
+    fn test_min_balance() {
+        let config = EngineConfig {
+            min_balance: Some(Decimal::new(10, 0)),
+            ..EngineConfig::default()
+        };
+
+        // 70 available after the funding withdrawal.
+        let mut account = funded_account();
+        assert_eq!(
+            account.process_transaction_with(testkit::withdrawal(1, 3, "65"), &config),
+            Err(TransactionError::MinimumBalance {
+                min: Decimal::new(10, 0),
+                would_be: Decimal::new(5, 0),
+            })
+        );
+        assert_eq!(
+            account.process_transaction_with(testkit::withdrawal(1, 4, "60"), &config),
+            Ok(())
+        );
+        assert_eq!(account.available, Decimal::new(10, 0));
+
+        // Disputes are not withdrawals, so they can go below the minimum.
+        account
+            .process_transaction_with(testkit::dispute(1, 1), &config)
+            .unwrap();
+        assert_eq!(account.available, Decimal::new(-90, 0));
+
+        // The minimum of the account overrides the one of the config.
+        let mut account = funded_account();
+        account.min_balance = Some(Decimal::new(50, 0));
+        assert_eq!(
+            account.process_transaction_with(testkit::withdrawal(1, 3, "30"), &config),
+            Err(TransactionError::MinimumBalance {
+                min: Decimal::new(50, 0),
+                would_be: Decimal::new(40, 0),
+            })
+        );
+        account.min_balance = Some(Decimal::ZERO);
+        assert_eq!(
+            account.process_transaction_with(testkit::withdrawal(1, 3, "65"), &config),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_max_open_disputes() {
+        let config = EngineConfig {
+            max_open_disputes: Some(2),
+            ..EngineConfig::default()
+        };
+        let deposits = (1..=4).fold(TxSeq::new(), |seq, id| seq.deposit(1, id, "10"));
+        let mut account = account_from_with(deposits, &config);
+        let mut process = |transaction| account.process_transaction_with(transaction, &config);
+
+        assert_eq!(process(testkit::dispute(1, 1)), Ok(()));
+        assert_eq!(process(testkit::dispute(1, 2)), Ok(()));
+        assert_eq!(
+            process(testkit::dispute(1, 3)),
+            Err(TransactionError::TooManyOpenDisputes { tier: None })
+        );
+
+        // A partial resolve keeps the dispute open.
+        process(testkit::partial_resolve(1, 1, "4")).unwrap();
+        assert_eq!(
+            process(testkit::dispute(1, 3)),
+            Err(TransactionError::TooManyOpenDisputes { tier: None })
+        );
+        process(testkit::resolve(1, 1)).unwrap();
+        assert_eq!(process(testkit::dispute(1, 3)), Ok(()));
+
+        assert_eq!(account.open_dispute_count(), 2);
+        assert_eq!(account.held, Decimal::new(20, 0));
+        account.resolve_all_open();
+        assert_eq!(account.open_dispute_count(), 0);
+    }
+
+    #[test]
+    fn test_auto_resolve_after() {
+        let config = EngineConfig {
+            auto_resolve_after: Some(2),
+            ..EngineConfig::default()
+        };
+        let mut account = funded_account();
+        let mut process = |transaction| account.process_transaction_with(transaction, &config);
+
+        process(testkit::deposit(1, 3, "10")).unwrap();
+        process(testkit::dispute(1, 1)).unwrap();
+        process(testkit::dispute(1, 3)).unwrap();
+        // The second transaction after the dispute of 1 auto-resolves it, even if rejected.
+        assert_eq!(
+            process(testkit::withdrawal(1, 4, "500")),
+            Err(TransactionError::InsufficientFunds {
+                requested: Decimal::new(500, 0),
+                available: Decimal::new(-30, 0),
+            })
+        );
+
+        assert_eq!(
+            process(testkit::resolve(1, 1)),
+            Err(TransactionError::DisputeAutoResolved)
+        );
+        assert_eq!(
+            process(testkit::chargeback(1, 1)),
+            Err(TransactionError::DisputeAutoResolved)
+        );
+        assert_eq!(account.open_dispute_count(), 0);
+        assert_balances!(account, available: "80", held: "0", locked: false);
+        for tx in [1, 3] {
+            assert_eq!(
+                account.disputes[&TransactionId(tx)].state(),
+                &DisputeState::AutoResolved
+            );
+        }
+    }
+
+    #[test]
+    fn test_currencies() {
+        let usd = Currency("USD".to_string());
+        let eur = Currency("EUR".to_string());
+        let mut account = Account::new(Decimal::ZERO);
+        let mut process = |transaction, currency: Option<&Currency>| {
+            account.process_transaction(Transaction {
+                currency: currency.cloned(),
+                ..transaction
+            })
+        };
+
+        process(testkit::deposit(1, 1, "100"), Some(&usd)).unwrap();
+        process(testkit::deposit(1, 2, "50"), Some(&eur)).unwrap();
+        // Withdrawals only use the funds of their own currency.
+        assert_eq!(
+            process(testkit::withdrawal(1, 3, "80"), Some(&eur)),
+            Err(TransactionError::InsufficientFunds {
+                requested: Decimal::new(80, 0),
+                available: Decimal::new(50, 0),
+            })
+        );
+        process(testkit::withdrawal(1, 4, "20"), Some(&eur)).unwrap();
+
+        // Disputes settle in the currency of the deposit.
+        assert_eq!(
+            process(testkit::dispute(1, 2), Some(&usd)),
+            Err(TransactionError::CurrencyMismatch)
+        );
+        process(testkit::dispute(1, 2), None).unwrap();
+        assert_eq!(
+            process(testkit::chargeback(1, 2), Some(&usd)),
+            Err(TransactionError::CurrencyMismatch)
+        );
+        process(testkit::resolve(1, 2), Some(&eur)).unwrap();
+        process(testkit::dispute(1, 2), Some(&eur)).unwrap();
+
+        let balance = |available, held| CurrencyBalance {
+            available: Decimal::new(available, 0),
+            held: Decimal::new(held, 0),
+        };
+        assert_eq!(account.balance(Some(&usd)), balance(100, 0));
+        assert_eq!(account.balance(Some(&eur)), balance(-20, 50));
+        assert_eq!(account.balance(None), balance(0, 0));
+        assert_eq!(
+            account.balances().collect::<Vec<_>>(),
+            vec![
+                (Some(&eur), balance(-20, 50)),
+                (Some(&usd), balance(100, 0))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tx_position() {
+        let mut account = funded_account();
+        // Positions follow the processing order, not the transaction ids.
+        account
+            .process_transaction(testkit::deposit(1, 0, "10"))
+            .unwrap();
+        assert_eq!(account.tx_position(TransactionId(1)), Some(0));
+        assert_eq!(account.tx_position(TransactionId(2)), Some(1));
+        assert_eq!(account.tx_position(TransactionId(0)), Some(2));
+        assert_eq!(account.tx_position(TransactionId(3)), None);
+    }
+
+    #[test]
+    fn test_try_process() {
+        let mut account = funded_account();
+
+        assert_eq!(
+            account.try_process(testkit::deposit(1, 3, "10")),
+            Ok(ProcessOutcome::Applied)
+        );
+        assert_eq!(
+            account.try_process(testkit::dispute(1, 9)),
+            Ok(ProcessOutcome::Ignored)
+        );
+        account.try_process(testkit::dispute(1, 1)).unwrap();
+        account.try_process(testkit::chargeback(1, 1)).unwrap();
+
+        assert_eq!(
+            account.try_process(testkit::deposit(1, 4, "10")),
+            Err(TransactionError::AccountLocked)
+        );
+        // The other entry points keep ignoring them.
+        assert_eq!(
+            account.process_transaction(testkit::deposit(1, 4, "10")),
+            Ok(())
+        );
+        assert!(!account.transactions.contains_key(&TransactionId(4)));
+    }
+
+    #[test]
+    fn test_lock_info() {
+        let mut account = Account::new(Decimal::ZERO);
+        let config = EngineConfig {
+            allow_admin_ops: true,
+            ..EngineConfig::default()
+        };
+        let at = |hour| {
+            DateTime::parse_from_rfc3339(&format!("2024-03-01T{hour:02}:00:00Z"))
+                .unwrap()
+                .to_utc()
+        };
+        let deposit = TransactionKind::Deposit {
+            amount: Decimal::new(10, 0),
+        };
+        let mut process = |kind, id, hour| {
+            let transaction = Transaction {
+                timestamp: Some(at(hour)),
+                ..testkit::transaction(1, id, kind)
+            };
+            account.try_process_with(transaction, &config).unwrap();
+            account.lock_info().copied()
+        };
+
+        process(deposit, 1, 1);
+        process(deposit, 2, 2);
+        process(TransactionKind::Dispute { amount: None }, 2, 3);
+        let lock = process(TransactionKind::Chargeback, 2, 4);
+        assert_eq!(
+            lock,
+            Some(LockInfo {
+                tx: TransactionId(2),
+                seq: 4,
+                timestamp: Some(at(4)),
+            })
+        );
+
+        assert_eq!(process(TransactionKind::Unlock, 5, 5), None);
+        process(deposit, 6, 6);
+        process(TransactionKind::Dispute { amount: None }, 6, 7);
+        let lock = process(TransactionKind::Chargeback, 6, 8);
+        assert_eq!(
+            lock,
+            Some(LockInfo {
+                tx: TransactionId(6),
+                seq: 8,
+                timestamp: Some(at(8)),
+            })
+        );
+    }
+
+    #[test]
+    fn test_unlock() {
+        let config = EngineConfig {
+            allow_admin_ops: true,
+            ..EngineConfig::default()
+        };
+        let transaction = |kind, id| testkit::transaction(1, id, kind);
+        let mut account = account_from_with(
+            TxSeq::new()
+                .deposit(1, 1, "100")
+                .deposit(1, 2, "50")
+                .dispute(1, 2)
+                .chargeback(1, 2),
+            &config,
+        );
+        assert!(account.locked);
+
+        // Without the policy, unlocking is refused.
+        assert_eq!(
+            account.try_process(transaction(TransactionKind::Unlock, 3)),
+            Err(TransactionError::AdminOpsDisabled)
+        );
+        assert!(account.locked);
+
+        assert_eq!(
+            account.try_process_with(transaction(TransactionKind::Unlock, 3), &config),
+            Ok(ProcessOutcome::Applied)
+        );
+        assert!(!account.locked);
+        assert_eq!(
+            account.transactions[&TransactionId(3)].kind,
+            TransactionKind::Unlock
+        );
+        assert_eq!(
+            account.try_process_with(transaction(TransactionKind::Unlock, 4), &config),
+            Ok(ProcessOutcome::Ignored)
+        );
+
+        assert_eq!(
+            account.try_process_with(testkit::withdrawal(1, 5, "20"), &config),
+            Ok(ProcessOutcome::Applied)
+        );
+        assert_balances!(account, available: "80", held: "0", locked: false);
+    }
+
+    #[test]
+    fn test_dispute_after_unlock() {
+        let config = EngineConfig {
+            allow_admin_ops: true,
+            ..EngineConfig::default()
+        };
+        let mut account = account_from_with(
+            TxSeq::new()
+                .deposit(1, 1, "100")
+                .dispute(1, 1)
+                .chargeback(1, 1)
+                .push(testkit::transaction(1, 2, TransactionKind::Unlock))
+                .deposit(1, 3, "50"),
+            &config,
+        );
+
+        // The charged back deposit cannot be disputed, and so charged back, again.
+        assert_eq!(
+            account.try_process_with(testkit::dispute(1, 1), &config),
+            Err(TransactionError::AlreadyChargedBack)
+        );
+        assert_eq!(
+            account.try_process_with(testkit::chargeback(1, 1), &config),
+            Ok(ProcessOutcome::Ignored)
+        );
+        assert_balances!(account, available: "50", total: "50", locked: false);
+        assert_eq!(
+            account.disputes[&TransactionId(1)].state(),
+            &DisputeState::ChargedBack
+        );
+
+        // Nor once the chargeback is reversed.
+        account
+            .try_process_with(
+                testkit::transaction(1, 1, TransactionKind::ChargebackReversal),
+                &config,
+            )
+            .unwrap();
+        assert_eq!(
+            account.try_process_with(testkit::dispute(1, 1), &config),
+            Err(TransactionError::AlreadyChargedBack)
+        );
+        assert_balances!(account, available: "150", total: "150");
+    }
+
+    #[test]
+    fn test_interest_after_unlock() {
+        let config = EngineConfig {
+            allow_admin_ops: true,
+            interest: Some(InterestPolicy {
+                daily_rate: Decimal::new(1, 2),
+            }),
+            ..EngineConfig::default()
+        };
+        let on_day = |day, transaction| Transaction {
+            timestamp: Some(Utc.with_ymd_and_hms(2024, 1, day, 0, 0, 0).unwrap()),
+            ..transaction
+        };
+        let mut account = Account::new(Decimal::ZERO);
+        for transaction in [
+            on_day(1, testkit::deposit(1, 1, "1000")),
+            on_day(1, testkit::deposit(1, 2, "100")),
+            on_day(1, testkit::dispute(1, 2)),
+            on_day(1, testkit::chargeback(1, 2)),
+            on_day(11, testkit::transaction(1, 3, TransactionKind::Unlock)),
+            on_day(12, testkit::deposit(1, 4, "10")),
+        ] {
+            account.try_process_with(transaction, &config).unwrap();
+        }
+
+        // Only the day after the unlock earns interest, not the ten days locked.
+        assert_balances!(account, available: "1020", locked: false);
+    }
+
+    #[test]
+    fn test_chargeback_reversal() {
+        let config = EngineConfig {
+            allow_admin_ops: true,
+            ..EngineConfig::default()
+        };
+        let process = |account: &mut Account, kind, id| {
+            account.try_process_with(testkit::transaction(1, id, kind), &config)
+        };
+        let reversal = TransactionKind::ChargebackReversal;
+        let mut account = account_from_with(
+            TxSeq::new()
+                .deposit(1, 1, "100")
+                .deposit(1, 2, "30")
+                .deposit(1, 3, "20")
+                .dispute(1, 2)
+                .dispute(1, 3),
+            &config,
+        );
+
+        // Only charged back disputes can be reversed.
+        assert_eq!(
+            process(&mut account, reversal, 2),
+            Err(TransactionError::NotChargedBack)
+        );
+        assert_eq!(
+            process(&mut account, reversal, 1),
+            Err(TransactionError::NotChargedBack)
+        );
+
+        // Two chargebacks, the account being locked by the second one after an unlock.
+        for (kind, id) in [
+            (TransactionKind::Chargeback, 2),
+            (TransactionKind::Unlock, 4),
+            (TransactionKind::Chargeback, 3),
+        ] {
+            process(&mut account, kind, id).unwrap();
+        }
+        assert_eq!(account.available, Decimal::new(100, 0));
+        assert_eq!(
+            account.lock_info().map(|lock| lock.tx),
+            Some(TransactionId(3))
+        );
+
+        // Reversing the first chargeback returns its funds but keeps the lock.
+        assert_eq!(
+            process(&mut account, reversal, 2),
+            Ok(ProcessOutcome::Applied)
+        );
+        assert_eq!(account.available, Decimal::new(130, 0));
+        assert!(account.locked);
+        assert_eq!(
+            account.disputes[&TransactionId(2)].state(),
+            &DisputeState::Reversed
+        );
+        assert_eq!(
+            process(&mut account, reversal, 2),
+            Err(TransactionError::NotChargedBack)
+        );
+
+        // Reversing the locking chargeback unlocks the account.
+        assert_eq!(
+            process(&mut account, reversal, 3),
+            Ok(ProcessOutcome::Applied)
+        );
+        assert_eq!(account.available, Decimal::new(150, 0));
+        assert_eq!(account.held, Decimal::ZERO);
+        assert!(!account.locked);
+        assert_eq!(account.lock_info(), None);
+        assert_eq!(account.total_charged_back(None), Decimal::ZERO);
+        // The deposits, the unlock and the disputes are kept.
+        assert_eq!(account.transactions.len(), 4);
+        assert_eq!(account.disputes.len(), 2);
+    }
+
+    #[test]
+    fn test_dispute_escalation() {
+        let mut account = Account::new(Decimal::ZERO);
+        let transaction = |kind, id| testkit::transaction(1, id, kind);
+        for id in [1, 2] {
+            let deposit = TransactionKind::Deposit {
+                amount: Decimal::new(10 * i64::from(id), 0),
+            };
+            account.try_process(transaction(deposit, id)).unwrap();
+            account
+                .try_process(transaction(TransactionKind::Dispute { amount: None }, id))
+                .unwrap();
+        }
+
+        // The funds stay held through the open stages, until the chargeback.
+        for kind in [
+            TransactionKind::RequestEvidence,
+            TransactionKind::PreArbitration,
+        ] {
+            assert_eq!(
+                account.try_process(transaction(kind, 1)),
+                Ok(ProcessOutcome::Applied)
+            );
+            assert_eq!(account.held, Decimal::new(30, 0));
+            assert!(account.is_disputed(TransactionId(1)));
+        }
+        assert_eq!(
+            account.try_process(transaction(TransactionKind::RequestEvidence, 1)),
+            Err(TransactionError::InvalidDisputeTransition {
+                from: DisputeState::PreArbitration,
+                to: DisputeState::EvidenceRequested,
+            })
+        );
+        assert_eq!(
+            account.try_process(transaction(TransactionKind::Chargeback, 1)),
+            Ok(ProcessOutcome::Applied)
+        );
+        assert_eq!(account.held, Decimal::new(20, 0));
+        assert_eq!(
+            account.disputes[&TransactionId(1)].history(),
+            [
+                DisputeState::Disputed,
+                DisputeState::EvidenceRequested,
+                DisputeState::PreArbitration,
+                DisputeState::ChargedBack,
+            ]
+        );
+
+        // A dispute in arbitration cannot be resolved anymore, only charged back.
+        let mut account = Account::new(Decimal::ZERO);
+        account
+            .try_process(transaction(
+                TransactionKind::Deposit {
+                    amount: Decimal::TEN,
+                },
+                3,
+            ))
+            .unwrap();
+        for kind in [
+            TransactionKind::Dispute { amount: None },
+            TransactionKind::Arbitration,
+        ] {
+            account.try_process(transaction(kind, 3)).unwrap();
+        }
+        assert_eq!(
+            account.try_process(transaction(TransactionKind::Resolve { amount: None }, 3)),
+            Err(TransactionError::InvalidDisputeTransition {
+                from: DisputeState::Arbitration,
+                to: DisputeState::Resolved,
+            })
+        );
+        assert_eq!(account.held, Decimal::TEN);
+        assert_eq!(
+            account.try_process(transaction(TransactionKind::Chargeback, 3)),
+            Ok(ProcessOutcome::Applied)
+        );
+        assert!(account.locked);
+        assert_eq!(account.held, Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_clamp_withdrawals() {
+        let transaction = |kind, id| testkit::transaction(1, id, kind);
+        let withdrawal = TransactionKind::Withdrawal {
+            amount: Decimal::new(100, 0),
+        };
+        let mut account = Account::new(Decimal::new(60, 0));
+
+        // Rejected by default.
+        assert_eq!(
+            account.try_process(transaction(withdrawal, 1)),
+            Err(TransactionError::InsufficientFunds {
+                requested: Decimal::new(100, 0),
+                available: Decimal::new(60, 0),
+            })
+        );
+
+        let config = EngineConfig {
+            clamp_withdrawals: true,
+            ..EngineConfig::default()
+        };
+        assert_eq!(
+            account.try_process_with(transaction(withdrawal, 2), &config),
+            Ok(ProcessOutcome::Applied)
+        );
+        assert_eq!(account.available, Decimal::ZERO);
+        assert_eq!(
+            account.transactions[&TransactionId(2)].kind,
+            TransactionKind::Withdrawal {
+                amount: Decimal::new(60, 0)
+            }
+        );
+
+        // Nothing is left to withdraw.
+        assert_eq!(
+            account.try_process_with(transaction(withdrawal, 3), &config),
+            Err(TransactionError::InsufficientFunds {
+                requested: Decimal::new(100, 0),
+                available: Decimal::ZERO,
+            })
+        );
+    }
+
+    #[test]
+    fn test_refund() {
+        let transaction = |kind, id| testkit::transaction(1, id, kind);
+        let deposit = |amount| TransactionKind::Deposit {
+            amount: Decimal::new(amount, 0),
+        };
+        let mut account = Account::new(Decimal::ZERO);
+        for (kind, id) in [(deposit(50), 1), (deposit(30), 2)] {
+            account.try_process(transaction(kind, id)).unwrap();
+        }
+
+        // Refund then dispute.
+        assert_eq!(
+            account.try_process(transaction(TransactionKind::Refund, 1)),
+            Ok(ProcessOutcome::Applied)
+        );
+        assert_eq!(account.available, Decimal::new(30, 0));
+        assert!(!account.locked);
+        assert!(account.is_refunded(TransactionId(1)));
+        assert_eq!(
+            account.try_process(transaction(TransactionKind::Dispute { amount: None }, 1)),
+            Err(TransactionError::Refunded)
+        );
+        assert_eq!(
+            account.try_process(transaction(TransactionKind::Refund, 1)),
+            Err(TransactionError::Refunded)
+        );
+
+        // Dispute then refund, even once the dispute is resolved.
+        account
+            .try_process(transaction(TransactionKind::Dispute { amount: None }, 2))
+            .unwrap();
+        assert_eq!(
+            account.try_process(transaction(TransactionKind::Refund, 2)),
+            Err(TransactionError::AlreadyDisputed)
+        );
+        account
+            .try_process(transaction(TransactionKind::Resolve { amount: None }, 2))
+            .unwrap();
+        assert_eq!(
+            account.try_process(transaction(TransactionKind::Refund, 2)),
+            Err(TransactionError::AlreadyDisputed)
+        );
+        assert_eq!(account.available, Decimal::new(30, 0));
+
+        // Refunds beyond the available funds depend on the policy.
+        account
+            .try_process(transaction(
+                TransactionKind::Withdrawal {
+                    amount: Decimal::new(20, 0),
+                },
+                3,
+            ))
+            .unwrap();
+        account.try_process(transaction(deposit(15), 4)).unwrap();
+        account
+            .try_process(transaction(
+                TransactionKind::Withdrawal {
+                    amount: Decimal::new(20, 0),
+                },
+                5,
+            ))
+            .unwrap();
+        assert_eq!(account.available, Decimal::new(5, 0));
+        assert_eq!(
+            account.try_process(transaction(TransactionKind::Refund, 4)),
+            Err(TransactionError::InsufficientFunds {
+                requested: Decimal::new(15, 0),
+                available: Decimal::new(5, 0),
+            })
+        );
+        let config = EngineConfig {
+            allow_negative_refunds: true,
+            ..EngineConfig::default()
+        };
+        assert_eq!(
+            account.try_process_with(transaction(TransactionKind::Refund, 4), &config),
+            Ok(ProcessOutcome::Applied)
+        );
+        assert_eq!(account.available, Decimal::new(-10, 0));
+        assert!(!account.locked);
+    }
+
+    #[test]
+    fn test_withdrawal_reversal() {
+        let transaction = |kind, id| testkit::transaction(1, id, kind);
+        let withdrawal = |amount| TransactionKind::Withdrawal {
+            amount: Decimal::new(amount, 0),
+        };
+        let reversal = TransactionKind::WithdrawalReversal;
+        let config = EngineConfig {
+            withdrawal_reversal_window: Some(2),
+            ..EngineConfig::default()
+        };
+        let mut account = Account::new(Decimal::ZERO);
+        let process = |account: &mut Account, kind, id| {
+            account.try_process_with(transaction(kind, id), &config)
+        };
+        process(
+            &mut account,
+            TransactionKind::Deposit {
+                amount: Decimal::new(100, 0),
+            },
+            1,
+        )
+        .unwrap();
+        process(&mut account, withdrawal(30), 2).unwrap();
+        process(&mut account, withdrawal(20), 3).unwrap();
+        assert_eq!(account.available, Decimal::new(50, 0));
+
+        // In the window: the reversal is the second transaction after the withdrawal.
+        assert_eq!(
+            process(&mut account, reversal, 2),
+            Ok(ProcessOutcome::Applied)
+        );
+        assert_eq!(account.available, Decimal::new(80, 0));
+        assert_eq!(
+            process(&mut account, reversal, 2),
+            Err(TransactionError::AlreadyReversed)
+        );
+
+        // Out of the window: the third transaction after the withdrawal.
+        assert_eq!(
+            process(&mut account, reversal, 3),
+            Err(TransactionError::ReversalWindowExpired)
+        );
+        assert_eq!(
+            process(&mut account, reversal, 1),
+            Err(TransactionError::UnknownWithdrawal)
+        );
+        assert_eq!(
+            process(&mut account, reversal, 9),
+            Err(TransactionError::UnknownWithdrawal)
+        );
+        assert_eq!(account.available, Decimal::new(80, 0));
+
+        // Without a window, withdrawals cannot be reversed, even right after them.
+        account.try_process(transaction(withdrawal(10), 4)).unwrap();
+        assert_eq!(
+            account.try_process(transaction(reversal, 4)),
+            Err(TransactionError::NoReversalWindow)
+        );
+        assert_eq!(account.available, Decimal::new(70, 0));
+    }
+
+    #[test]
+    fn test_withdrawal_disputes() {
+        let config = EngineConfig {
+            allow_withdrawal_disputes: true,
+            ..EngineConfig::default()
+        };
+        let transaction = |kind, id| testkit::transaction(1, id, kind);
+        let dispute = |id| transaction(TransactionKind::Dispute { amount: None }, id);
+
+        // Rejected without the setting.
+        let mut account = funded_account();
+        assert_eq!(
+            account.try_process(dispute(2)),
+            Err(TransactionError::NotDisputable)
+        );
+        assert_eq!(account.available, Decimal::new(70, 0));
+        assert_eq!(account.held, Decimal::ZERO);
+
+        // The withdrawn amount is held on top of the funds, then removed on a resolve.
+        assert_eq!(
+            account.try_process_with(dispute(2), &config),
+            Ok(ProcessOutcome::Applied)
+        );
+        assert_eq!(account.available, Decimal::new(70, 0));
+        assert_eq!(account.held, Decimal::new(30, 0));
+        account
+            .try_process_with(
+                transaction(TransactionKind::Resolve { amount: None }, 2),
+                &config,
+            )
+            .unwrap();
+        assert_eq!(account.available, Decimal::new(70, 0));
+        assert_eq!(account.held, Decimal::ZERO);
+
+        // Or credited back on a chargeback.
+        let mut account = funded_account();
+        account.try_process_with(dispute(2), &config).unwrap();
+        account
+            .try_process_with(transaction(TransactionKind::Chargeback, 2), &config)
+            .unwrap();
+        assert_eq!(account.available, Decimal::new(100, 0));
+        assert_eq!(account.held, Decimal::ZERO);
+        assert!(account.locked);
+    }
+
+    #[test]
+    fn test_freeze() {
+        let mut account = Account::new(Decimal::ZERO);
+        let config = EngineConfig {
+            allow_admin_ops: true,
+            ..EngineConfig::default()
+        };
+        let transaction = |kind, id| testkit::transaction(1, id, kind);
+        let deposit = |amount| TransactionKind::Deposit {
+            amount: Decimal::new(amount, 0),
+        };
+        let applied = Ok(ProcessOutcome::Applied);
+
+        assert_eq!(
+            account.try_process_with(transaction(deposit(100), 1), &config),
+            applied
+        );
+        assert_eq!(
+            account.try_process_with(transaction(TransactionKind::Freeze, 2), &config),
+            applied
+        );
+        assert_eq!(
+            account.try_process_with(transaction(deposit(50), 3), &config),
+            Err(TransactionError::AccountFrozen)
+        );
+        // Disputes are still processed while frozen.
+        assert_eq!(
+            account.try_process_with(
+                transaction(TransactionKind::Dispute { amount: None }, 1),
+                &config
+            ),
+            applied
+        );
+        assert_eq!(
+            account.try_process_with(
+                transaction(TransactionKind::Resolve { amount: None }, 1),
+                &config
+            ),
+            applied
+        );
+        assert_eq!(
+            account.try_process_with(transaction(TransactionKind::Unfreeze, 4), &config),
+            applied
+        );
+        assert_eq!(
+            account.try_process_with(transaction(deposit(50), 5), &config),
+            applied
+        );
+        assert_eq!(account.available, Decimal::new(150, 0));
+        assert_eq!(account.locked_rejections, 1);
+
+        // Freezing a locked account is accepted, and unfreezing it does not unlock it.
+        for (kind, id) in [
+            (TransactionKind::Dispute { amount: None }, 5),
+            (TransactionKind::Chargeback, 5),
+        ] {
+            account
+                .try_process_with(transaction(kind, id), &config)
+                .unwrap();
+        }
+        assert_eq!(
+            account.try_process_with(transaction(TransactionKind::Freeze, 6), &config),
+            applied
+        );
+        assert!(account.locked && account.admin_frozen);
+        assert_eq!(
+            account.try_process_with(transaction(TransactionKind::Unfreeze, 7), &config),
+            applied
+        );
+        assert!(account.locked && !account.admin_frozen);
+        assert_eq!(
+            account.try_process_with(transaction(deposit(10), 8), &config),
+            Err(TransactionError::AccountLocked)
+        );
+
+        // Without the policy, freezing is refused.
+        assert_eq!(
+            Account::new(Decimal::ZERO).try_process(transaction(TransactionKind::Freeze, 1)),
+            Err(TransactionError::AdminOpsDisabled)
+        );
+    }
+
+    #[test]
+    fn test_admin_ops_reusing_tx_id() {
+        let config = EngineConfig {
+            allow_admin_ops: true,
+            ..EngineConfig::default()
+        };
+        let mut account = account_from_with(TxSeq::new().deposit(1, 1, "10"), &config);
+
+        // Admin rows are kept in the history by id, so they must not replace the deposit.
+        for kind in [TransactionKind::Freeze, TransactionKind::Unfreeze] {
+            assert_eq!(
+                account.try_process_with(testkit::transaction(1, 1, kind), &config),
+                Err(TransactionError::TxIdInUse)
+            );
+        }
+        assert!(!account.admin_frozen);
+        assert_eq!(
+            account.transactions[&TransactionId(1)],
+            testkit::deposit(1, 1, "10")
+        );
+
+        account
+            .try_process_with(testkit::dispute(1, 1), &config)
+            .unwrap();
+        assert_balances!(account, available: "0", held: "10");
+        assert_balances!(account.replay(&config), available: "0", held: "10");
+    }
 }
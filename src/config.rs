@@ -0,0 +1,700 @@
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, bail};
+use chrono::{Duration, FixedOffset};
+use clap::Args;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use tracing_subscriber::EnvFilter;
+
+use crate::{
+    engine::{
+        DuplicateDisputePolicy, EngineConfig, FeeSchedule, InterestPolicy, parse_dispute_window,
+    },
+    logging::LogFormat,
+    output::{Rates, ReportOptions},
+    risk::RiskThresholds,
+    transaction::Currency,
+};
+
+/// Prefix of the environment variables overriding the config file, followed by the
+/// section and the key, e.g. `PAYMENTS_ENGINE_ROUND_EACH_OP`.
+const ENV_PREFIX: &str = "PAYMENTS_";
+
+/// Settings of a run, read from a TOML file such as:
+///
+/// ```toml
+/// [engine]
+/// max_held_per_account = "10000"
+/// max_open_disputes = 5
+/// on_duplicate_dispute = "error"
+/// dispute_window = "90d"
+/// min_balance = "10.00"
+/// max_withdrawal = "1000"
+/// withdrawal_reversal_window = 10
+/// daily_withdrawal_limit = "5000"
+/// day_utc_offset = "-05:00"
+/// allow_admin_ops = true
+/// allow_negative_refunds = true
+/// allow_withdrawal_disputes = true
+/// tx_ids_unique_per_client = false
+/// aml_cumulative_threshold = "10000"
+/// aml_single_threshold = "5000"
+///
+/// [engine.withdrawal_fee]
+/// flat = "0.25"
+/// percent = "1"
+/// max = "20"
+///
+/// [engine.deposit_fees.default]
+/// percent = "0"
+///
+/// [engine.deposit_fees.b]
+/// percent = "0.5"
+///
+/// [engine.tier_limits.b]
+/// max_deposit = "500"
+/// max_withdrawal = "100"
+/// daily_withdrawal_limit = "1000"
+/// max_open_disputes = 1
+///
+/// [engine.interest]
+/// daily_rate = "0.0001"
+///
+/// [output]
+/// minor_units = true
+/// bool_as_int = true
+/// include_fees = true
+/// aml_columns = true
+/// extended_columns = true
+/// summary_row = true
+/// rates = "rates.csv"
+/// report_currency = "USD"
+///
+/// [risk]
+/// max_disputes = 5
+/// max_chargeback_percent = "10"
+/// immediate_dispute_rows = 1
+/// max_disputes_per_tx = 2
+///
+/// [log]
+/// format = "json"
+/// level = "info"
+/// ```
+///
+/// Every key is optional. Environment variables override the file, and command line
+/// flags override both.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub engine: EngineConfig,
+    pub output: OutputConfig,
+    /// Thresholds of the report written with `--risk-report`.
+    pub risk: RiskThresholds,
+    pub log: LogConfig,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputConfig {
+    /// Writes amounts as integer minor units, see [`ReportOptions::minor_units`].
+    pub minor_units: bool,
+    /// Writes `locked` as an integer, see [`ReportOptions::bool_as_int`].
+    pub bool_as_int: bool,
+    /// Also writes seeded accounts without transactions, see
+    /// [`ReportOptions::include_inactive`].
+    pub include_inactive: bool,
+    /// Writes the deposit and withdrawal fees paid by every client, see [`ReportOptions::include_fees`].
+    pub include_fees: bool,
+    /// Writes the anti-money laundering flag of every client, see
+    /// [`ReportOptions::aml_columns`].
+    pub aml_columns: bool,
+    /// Writes when every client was first and last active, see
+    /// [`ReportOptions::extended_columns`].
+    pub extended_columns: bool,
+    /// Appends a row with the sums of the report, see [`ReportOptions::summary_row`].
+    pub summary_row: bool,
+    /// Rates file converting the totals to `report_currency`, see [`Rates::read`].
+    pub rates: Option<PathBuf>,
+    /// Currency of the converted totals, which the implicit currency is taken to be.
+    pub report_currency: Option<Currency>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub format: LogFormat,
+    /// Filter directive, such as `info`. `RUST_LOG` is used if it is not set.
+    pub level: Option<String>,
+    /// Logs the final state of every account at the end of a run, see
+    /// [`crate::logging::log_summary`].
+    pub summary: bool,
+}
+
+/// Command line flags overriding the config file and the environment.
+#[derive(Debug, Clone, Default, Args)]
+pub struct ConfigFlags {
+    /// Writes amounts as integer minor units (ten-thousandths) instead of decimals.
+    #[arg(long, global = true)]
+    pub minor_units: bool,
+    /// Writes `locked` as `1` or `0` instead of `true` or `false`.
+    #[arg(long, global = true)]
+    pub bool_as_int: bool,
+    /// Also writes the accounts seeded with `--seed` that had no transaction.
+    #[arg(long, global = true)]
+    pub include_inactive: bool,
+    /// Adds a column with the deposit and withdrawal fees paid by every client.
+    #[arg(long, global = true)]
+    pub include_fees: bool,
+    /// Adds a column flagging the clients whose deposits exceed the anti-money laundering
+    /// thresholds.
+    #[arg(long, global = true)]
+    pub aml_columns: bool,
+    /// Adds columns with the sequence number and timestamp of the first and last accepted
+    /// transaction of every client.
+    #[arg(long, global = true)]
+    pub extended_columns: bool,
+    /// Appends a `total` row with the sums of the balances and the number of locked accounts.
+    #[arg(long, global = true)]
+    pub summary_row: bool,
+    /// CSV file of `currency,rate` rows converting the totals to `--report-currency`.
+    #[arg(long, global = true, requires = "report_currency")]
+    pub rates: Option<PathBuf>,
+    /// Currency the totals are converted to with `--rates`.
+    #[arg(long, global = true, requires = "rates")]
+    pub report_currency: Option<String>,
+    /// Format of the logs written to stderr.
+    #[arg(long, global = true, value_enum)]
+    pub log_format: Option<LogFormat>,
+    /// Logs filter, such as `info` or `payments=debug`. Overrides `RUST_LOG`.
+    #[arg(long, global = true)]
+    pub log_level: Option<String>,
+    /// Logs one line per client with its final balances and number of disputes.
+    #[arg(long, global = true)]
+    pub log_summary: bool,
+    /// Maximum funds an account can have held. Disputes that would exceed it are rejected.
+    #[arg(long, global = true)]
+    pub max_held_per_account: Option<Decimal>,
+    /// Maximum number of open disputes per account. Further disputes are rejected.
+    #[arg(long, global = true)]
+    pub max_open_disputes: Option<usize>,
+    /// What to do with a dispute of a transaction that is already disputed.
+    #[arg(long, global = true, value_enum)]
+    pub on_duplicate_dispute: Option<DuplicateDisputePolicy>,
+    /// Longest time after a deposit it can still be disputed, such as `90d`. Requires
+    /// timestamps on the input.
+    #[arg(long, global = true, value_parser = parse_dispute_window)]
+    pub dispute_window: Option<Duration>,
+    /// Minimum available funds a withdrawal can leave, unless the `min_balance` column of
+    /// the seed overrides it for the client.
+    #[arg(long, global = true)]
+    pub min_balance: Option<Decimal>,
+    /// Maximum amount of a single withdrawal. Larger withdrawals are rejected.
+    #[arg(long, global = true)]
+    pub max_withdrawal: Option<Decimal>,
+    /// Maximum funds a client can withdraw per calendar day. Requires timestamps on the
+    /// input.
+    #[arg(long, global = true)]
+    pub daily_withdrawal_limit: Option<Decimal>,
+    /// Offset from UTC of the time zone days start in for the daily withdrawal limit, such
+    /// as `+02:00`.
+    #[arg(long, global = true, allow_hyphen_values = true)]
+    pub day_utc_offset: Option<FixedOffset>,
+    /// Flat fee charged on every withdrawal.
+    #[arg(long, global = true)]
+    pub withdrawal_fee_flat: Option<Decimal>,
+    /// Fee charged on every withdrawal as a percentage of its amount, e.g. `1` for 1%.
+    #[arg(long, global = true)]
+    pub withdrawal_fee_percent: Option<Decimal>,
+    /// Minimum fee of a withdrawal.
+    #[arg(long, global = true)]
+    pub withdrawal_fee_min: Option<Decimal>,
+    /// Maximum fee of a withdrawal.
+    #[arg(long, global = true)]
+    pub withdrawal_fee_max: Option<Decimal>,
+    /// Interest credited to the available funds for every day elapsed between the
+    /// transactions of a client, as a fraction such as `0.0001`. Requires timestamps on the
+    /// input.
+    #[arg(long, global = true)]
+    pub interest_daily_rate: Option<Decimal>,
+    /// Accepts administrative transactions, such as `unlock`.
+    #[arg(long, global = true)]
+    pub allow_admin_ops: bool,
+    /// Number of transactions of the account after a withdrawal within which it can still
+    /// be reversed, the reversal included.
+    #[arg(long, global = true)]
+    pub withdrawal_reversal_window: Option<u64>,
+    /// Accepts disputes of withdrawals, instead of rejecting them.
+    #[arg(long, global = true)]
+    pub allow_withdrawal_disputes: bool,
+    /// Lets refunds take the available funds below zero, instead of rejecting them.
+    #[arg(long, global = true)]
+    pub allow_negative_refunds: bool,
+    /// Withdraws the available funds on withdrawals above them, instead of rejecting them.
+    #[arg(long, global = true)]
+    pub clamp_withdrawals: bool,
+    /// Rejects the deposits and withdrawals reusing the id of another client, instead of
+    /// only requiring transaction ids to be unique per client.
+    #[arg(long, global = true)]
+    pub tx_ids_globally_unique: bool,
+    /// Flags the clients whose deposits add up to more than this, with `--aml-columns`.
+    #[arg(long, global = true)]
+    pub aml_cumulative_threshold: Option<Decimal>,
+    /// Flags the clients with a single deposit of more than this, with `--aml-columns`.
+    #[arg(long, global = true)]
+    pub aml_single_threshold: Option<Decimal>,
+}
+
+impl Config {
+    /// Reads the config file, if any, applies the `PAYMENTS_*` variables of `env` over it,
+    /// then the flags, and validates the result.
+    pub fn load(
+        path: Option<&Path>,
+        env: impl IntoIterator<Item = (String, String)>,
+        flags: &ConfigFlags,
+    ) -> anyhow::Result<Self> {
+        let mut table = match path {
+            Some(path) => {
+                let text = fs::read_to_string(path)
+                    .with_context(|| format!("failed to read {}", path.display()))?;
+                text.parse::<toml::Table>()
+                    .with_context(|| format!("invalid config file {}", path.display()))?
+            }
+            None => toml::Table::new(),
+        };
+        apply_env(&mut table, env)?;
+
+        let mut config: Config = serde_path_to_error::deserialize(table).map_err(|err| {
+            anyhow::anyhow!("invalid config: {}: {}", err.path(), err.inner().message())
+        })?;
+        config.apply_flags(flags);
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn apply_flags(&mut self, flags: &ConfigFlags) {
+        self.output.minor_units |= flags.minor_units;
+        self.output.bool_as_int |= flags.bool_as_int;
+        self.output.include_inactive |= flags.include_inactive;
+        self.output.include_fees |= flags.include_fees;
+        self.output.aml_columns |= flags.aml_columns;
+        self.output.extended_columns |= flags.extended_columns;
+        self.output.summary_row |= flags.summary_row;
+        self.log.summary |= flags.log_summary;
+        self.engine.allow_admin_ops |= flags.allow_admin_ops;
+        self.engine.clamp_withdrawals |= flags.clamp_withdrawals;
+        self.engine.allow_negative_refunds |= flags.allow_negative_refunds;
+        self.engine.allow_withdrawal_disputes |= flags.allow_withdrawal_disputes;
+        if flags.tx_ids_globally_unique {
+            self.engine.tx_ids_unique_per_client = false;
+        }
+        if let Some(rates) = &flags.rates {
+            self.output.rates = Some(rates.clone());
+        }
+        if let Some(currency) = &flags.report_currency {
+            self.output.report_currency = Some(Currency(currency.clone()));
+        }
+        if let Some(format) = flags.log_format {
+            self.log.format = format;
+        }
+        if let Some(level) = &flags.log_level {
+            self.log.level = Some(level.clone());
+        }
+        if let Some(max_held) = flags.max_held_per_account {
+            self.engine.max_held_per_account = Some(max_held);
+        }
+        if let Some(window) = flags.withdrawal_reversal_window {
+            self.engine.withdrawal_reversal_window = Some(window);
+        }
+        if let Some(max_open) = flags.max_open_disputes {
+            self.engine.max_open_disputes = Some(max_open);
+        }
+        if let Some(policy) = flags.on_duplicate_dispute {
+            self.engine.on_duplicate_dispute = policy;
+        }
+        if let Some(window) = flags.dispute_window {
+            self.engine.dispute_window = Some(window);
+        }
+        if let Some(min) = flags.min_balance {
+            self.engine.min_balance = Some(min);
+        }
+        if let Some(limit) = flags.max_withdrawal {
+            self.engine.max_withdrawal = Some(limit);
+        }
+        if let Some(limit) = flags.daily_withdrawal_limit {
+            self.engine.daily_withdrawal_limit = Some(limit);
+        }
+        if let Some(offset) = flags.day_utc_offset {
+            self.engine.day_utc_offset = Some(offset);
+        }
+        if let Some(threshold) = flags.aml_cumulative_threshold {
+            self.engine.aml_cumulative_threshold = Some(threshold);
+        }
+        if let Some(threshold) = flags.aml_single_threshold {
+            self.engine.aml_single_threshold = Some(threshold);
+        }
+        if let Some(daily_rate) = flags.interest_daily_rate {
+            self.engine.interest = Some(InterestPolicy { daily_rate });
+        }
+        let fee_flags = [
+            flags.withdrawal_fee_flat,
+            flags.withdrawal_fee_percent,
+            flags.withdrawal_fee_min,
+            flags.withdrawal_fee_max,
+        ];
+        if fee_flags.iter().any(Option::is_some) {
+            let fee = self
+                .engine
+                .withdrawal_fee
+                .get_or_insert_with(FeeSchedule::default);
+            if let Some(flat) = flags.withdrawal_fee_flat {
+                fee.flat = flat;
+            }
+            if let Some(percent) = flags.withdrawal_fee_percent {
+                fee.percent = percent;
+            }
+            if let Some(min) = flags.withdrawal_fee_min {
+                fee.min = Some(min);
+            }
+            if let Some(max) = flags.withdrawal_fee_max {
+                fee.max = Some(max);
+            }
+        }
+    }
+
+    /// Checks the values that can be parsed but are out of range.
+    fn validate(&self) -> anyhow::Result<()> {
+        if let Some(max_held) = self
+            .engine
+            .max_held_per_account
+            .filter(|max_held| *max_held <= Decimal::ZERO)
+        {
+            bail!("invalid config: engine.max_held_per_account: must be positive, got {max_held}");
+        }
+        if let Some(min) = self.engine.min_balance.filter(|min| *min < Decimal::ZERO) {
+            bail!("invalid config: engine.min_balance: must not be negative, got {min}");
+        }
+        if let Some(limit) = self
+            .engine
+            .max_withdrawal
+            .filter(|limit| *limit <= Decimal::ZERO)
+        {
+            bail!("invalid config: engine.max_withdrawal: must be positive, got {limit}");
+        }
+        if let Some(limit) = self
+            .engine
+            .daily_withdrawal_limit
+            .filter(|limit| *limit <= Decimal::ZERO)
+        {
+            bail!("invalid config: engine.daily_withdrawal_limit: must be positive, got {limit}");
+        }
+        if self.engine.withdrawal_reversal_window == Some(0) {
+            bail!("invalid config: engine.withdrawal_reversal_window: must be positive, got 0");
+        }
+        if self.engine.clamp_withdrawals && self.engine.withdrawal_fee.is_some() {
+            bail!(
+                "invalid config: engine.clamp_withdrawals: cannot be combined with engine.withdrawal_fee"
+            );
+        }
+        if let Some(rate) = self
+            .engine
+            .interest
+            .map(|interest| interest.daily_rate)
+            .filter(|rate| *rate < Decimal::ZERO)
+        {
+            bail!("invalid config: engine.interest.daily_rate: must not be negative, got {rate}");
+        }
+        if self.risk.max_chargeback_percent < Decimal::ZERO {
+            bail!(
+                "invalid config: risk.max_chargeback_percent: must not be negative, got {}",
+                self.risk.max_chargeback_percent
+            );
+        }
+        let aml_thresholds = [
+            (
+                "aml_cumulative_threshold",
+                self.engine.aml_cumulative_threshold,
+            ),
+            ("aml_single_threshold", self.engine.aml_single_threshold),
+        ];
+        for (name, threshold) in aml_thresholds {
+            if let Some(threshold) = threshold.filter(|threshold| *threshold < Decimal::ZERO) {
+                bail!("invalid config: engine.{name}: must not be negative, got {threshold}");
+            }
+        }
+        if let Some(fee) = &self.engine.withdrawal_fee {
+            validate_fee("engine.withdrawal_fee", fee)?;
+        }
+        for (tier, fee) in &self.engine.deposit_fees {
+            validate_fee(&format!("engine.deposit_fees.{tier}"), fee)?;
+        }
+        for (tier, limits) in &self.engine.tier_limits {
+            let amounts = [
+                ("max_deposit", limits.max_deposit),
+                ("max_withdrawal", limits.max_withdrawal),
+                ("daily_withdrawal_limit", limits.daily_withdrawal_limit),
+            ];
+            for (name, limit) in amounts {
+                if let Some(limit) = limit.filter(|limit| *limit <= Decimal::ZERO) {
+                    bail!(
+                        "invalid config: engine.tier_limits.{tier}.{name}: must be positive, got {limit}"
+                    );
+                }
+            }
+        }
+        match (&self.output.rates, &self.output.report_currency) {
+            (Some(_), None) => {
+                bail!("invalid config: output.rates requires output.report_currency")
+            }
+            (None, Some(_)) => {
+                bail!("invalid config: output.report_currency requires output.rates")
+            }
+            _ => {}
+        }
+        if let Some(level) = &self.log.level {
+            EnvFilter::try_new(level)
+                .map_err(|err| anyhow::anyhow!("invalid config: log.level: {err}"))?;
+        }
+        Ok(())
+    }
+
+    /// Options of the report, reading the rates file if there is one.
+    pub fn report_options(&self) -> anyhow::Result<ReportOptions> {
+        let rates = match (&self.output.rates, &self.output.report_currency) {
+            (Some(path), Some(currency)) => Some(
+                File::open(path)
+                    .map_err(csv::Error::from)
+                    .and_then(|file| Rates::read(currency.clone(), file))
+                    .with_context(|| format!("invalid rates file {}", path.display()))?,
+            ),
+            _ => None,
+        };
+        Ok(ReportOptions {
+            minor_units: self.output.minor_units,
+            bool_as_int: self.output.bool_as_int,
+            include_inactive: self.output.include_inactive,
+            include_fees: self.output.include_fees,
+            rates,
+            aml_columns: self.output.aml_columns,
+            extended_columns: self.output.extended_columns,
+            summary_row: self.output.summary_row,
+        })
+    }
+}
+
+/// Checks that the amounts of the fee schedule at `path` are not negative and that its
+/// minimum is not above its maximum.
+fn validate_fee(path: &str, fee: &FeeSchedule) -> anyhow::Result<()> {
+    let amounts = [
+        ("flat", Some(fee.flat)),
+        ("percent", Some(fee.percent)),
+        ("min", fee.min),
+        ("max", fee.max),
+    ];
+    for (key, amount) in amounts {
+        if let Some(amount) = amount.filter(|amount| *amount < Decimal::ZERO) {
+            bail!("invalid config: {path}.{key}: must not be negative, got {amount}");
+        }
+    }
+    if let (Some(min), Some(max)) = (fee.min, fee.max) {
+        if min > max {
+            bail!("invalid config: {path}: min {min} is above max {max}");
+        }
+    }
+    Ok(())
+}
+
+/// Sets the values of the `PAYMENTS_<SECTION>_<KEY>` variables in `table`. Values are read
+/// as TOML, falling back to plain strings, so both `PAYMENTS_LOG_FORMAT=json` and
+/// `PAYMENTS_OUTPUT_MINOR_UNITS=true` work.
+fn apply_env(
+    table: &mut toml::Table,
+    env: impl IntoIterator<Item = (String, String)>,
+) -> anyhow::Result<()> {
+    for (name, value) in env {
+        let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+            continue;
+        };
+        let key = key.to_lowercase();
+        let Some((section, key)) = key.split_once('_') else {
+            bail!("invalid config: environment variable {name} does not name a key");
+        };
+
+        let value = toml::from_str::<toml::Table>(&format!("value = {value}"))
+            .ok()
+            .and_then(|mut parsed| parsed.remove("value"))
+            .unwrap_or(toml::Value::String(value));
+        let section = table
+            .entry(section)
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+        let Some(section) = section.as_table_mut() else {
+            bail!("invalid config: {name} sets a key of a value that is not a section");
+        };
+        section.insert(key.to_string(), value);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use tempfile::NamedTempFile;
+
+    use super::*;
+
+    fn file(contents: &str) -> NamedTempFile {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+        file
+    }
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_precedence() {
+        let file = file(
+            "[engine]\n\
+             max_held_per_account = \"100\"\n\
+             on_duplicate_dispute = \"error\"\n\
+             dispute_window = \"90d\"\n\
+             [log]\n\
+             format = \"json\"\n\
+             level = \"debug\"\n",
+        );
+        let vars = env(&[
+            ("PAYMENTS_ENGINE_MAX_HELD_PER_ACCOUNT", "200"),
+            ("PAYMENTS_LOG_LEVEL", "info"),
+            ("PAYMENTS_OUTPUT_MINOR_UNITS", "true"),
+            ("HOME", "/root"),
+        ]);
+        let flags = ConfigFlags {
+            max_held_per_account: Some(Decimal::new(300, 0)),
+            ..ConfigFlags::default()
+        };
+
+        let from_file = Config::load(Some(file.path()), [], &ConfigFlags::default()).unwrap();
+        assert_eq!(
+            from_file.engine.max_held_per_account,
+            Some(Decimal::new(100, 0))
+        );
+        assert_eq!(from_file.log.level.as_deref(), Some("debug"));
+        assert!(!from_file.output.minor_units);
+
+        let from_env =
+            Config::load(Some(file.path()), vars.clone(), &ConfigFlags::default()).unwrap();
+        assert_eq!(
+            from_env.engine.max_held_per_account,
+            Some(Decimal::new(200, 0))
+        );
+        assert_eq!(from_env.log.level.as_deref(), Some("info"));
+        assert!(from_env.output.minor_units);
+
+        let from_flags = Config::load(Some(file.path()), vars, &flags).unwrap();
+        assert_eq!(
+            from_flags.engine.max_held_per_account,
+            Some(Decimal::new(300, 0))
+        );
+        // Values without a flag keep the file or environment value.
+        assert_eq!(
+            from_flags.engine.on_duplicate_dispute,
+            DuplicateDisputePolicy::Error
+        );
+        assert_eq!(from_flags.engine.dispute_window, Some(Duration::days(90)));
+        assert_eq!(from_flags.log.format, LogFormat::Json);
+        assert_eq!(from_flags.log.level.as_deref(), Some("info"));
+    }
+
+    #[test]
+    fn test_without_file() {
+        assert_eq!(
+            Config::load(None, [], &ConfigFlags::default()).unwrap(),
+            Config::default()
+        );
+    }
+
+    #[test]
+    fn test_invalid_values() {
+        let load = |contents: &str| {
+            Config::load(Some(file(contents).path()), [], &ConfigFlags::default())
+                .unwrap_err()
+                .to_string()
+        };
+
+        let err = load("[engine]\non_duplicate_dispute = \"panic\"\n");
+        assert!(
+            err.starts_with("invalid config: engine.on_duplicate_dispute: unknown variant `panic`"),
+            "{err}"
+        );
+        let err = load("[engine]\nmax_held = \"100\"\n");
+        assert!(
+            err.starts_with("invalid config: engine.max_held: unknown field"),
+            "{err}"
+        );
+        let err = load("[engine]\ndispute_window = \"soon\"\n");
+        assert!(
+            err.starts_with("invalid config: engine.dispute_window:"),
+            "{err}"
+        );
+        let err = load("[engine.withdrawal_fee]\nmin = \"5\"\nmax = \"1\"\n");
+        assert_eq!(
+            err,
+            "invalid config: engine.withdrawal_fee: min 5 is above max 1"
+        );
+        let err = load("[engine.deposit_fees.b]\npercent = \"-0.5\"\n");
+        assert_eq!(
+            err,
+            "invalid config: engine.deposit_fees.b.percent: must not be negative, got -0.5"
+        );
+        let err = load("[engine.tier_limits.b]\nmax_withdrawal = \"0\"\n");
+        assert_eq!(
+            err,
+            "invalid config: engine.tier_limits.b.max_withdrawal: must be positive, got 0"
+        );
+        let err = load("[engine]\naml_single_threshold = \"-1\"\n");
+        assert_eq!(
+            err,
+            "invalid config: engine.aml_single_threshold: must not be negative, got -1"
+        );
+        let err =
+            load("[engine]\nclamp_withdrawals = true\n[engine.withdrawal_fee]\nflat = \"1\"\n");
+        assert_eq!(
+            err,
+            "invalid config: engine.clamp_withdrawals: cannot be combined with engine.withdrawal_fee"
+        );
+        let err = load("[engine]\nwithdrawal_reversal_window = 0\n");
+        assert_eq!(
+            err,
+            "invalid config: engine.withdrawal_reversal_window: must be positive, got 0"
+        );
+        let err = load("[output]\nrates = \"rates.csv\"\n");
+        assert_eq!(
+            err,
+            "invalid config: output.rates requires output.report_currency"
+        );
+        let err = load("[engine]\nmax_held_per_account = \"-5\"\n");
+        assert_eq!(
+            err,
+            "invalid config: engine.max_held_per_account: must be positive, got -5"
+        );
+
+        let err = Config::load(
+            None,
+            env(&[("PAYMENTS_ENGINE_ROUND_EACH_OP", "sometimes")]),
+            &ConfigFlags::default(),
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("invalid config: engine.round_each_op:"),
+            "{err}"
+        );
+    }
+}
@@ -0,0 +1,255 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Write},
+    os::unix::net::UnixListener,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    engine::{Engine, EngineOutput, Totals},
+    transaction::{ClientId, Transaction},
+};
+
+/// Command sent to the daemon, as a JSON object on its own line.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "cmd", rename_all = "lowercase")]
+pub enum Command {
+    /// Processes a transaction, e.g. `{"cmd":"tx","type":"deposit","client":1,"tx":1,"amount":"2.5"}`.
+    Tx(Transaction),
+    /// Returns the state of a client account.
+    Query { client: ClientId },
+    /// Returns the state of every account.
+    Report,
+    /// Writes the balances of every account to `path`.
+    Snapshot { path: PathBuf },
+    /// Stops the daemon, writing the configured snapshot first.
+    Shutdown,
+}
+
+/// Answer to a [`Command`], written as a JSON object on its own line.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum Response {
+    /// The command succeeded.
+    Ok,
+    /// The transaction was rejected by the engine.
+    Rejected { reason: String },
+    /// The command could not be parsed or executed.
+    Error { message: String },
+    /// State of the queried account, one per currency it uses, empty if it does not exist.
+    Account { accounts: Vec<EngineOutput> },
+    /// State of every account, ordered by client, and their sums per currency, `None` if
+    /// they overflow.
+    Report {
+        accounts: Vec<EngineOutput>,
+        totals: Option<Vec<Totals>>,
+    },
+}
+
+/// Long-running engine that answers JSON commands sent over a Unix socket.
+pub struct Daemon {
+    engine: Engine,
+    /// Balances snapshot loaded at startup, if it exists, and written on shutdown.
+    snapshot: Option<PathBuf>,
+}
+
+impl Daemon {
+    /// Creates the daemon, seeding `engine` from the `snapshot` file if it exists.
+    pub fn new(mut engine: Engine, snapshot: Option<PathBuf>) -> csv::Result<Self> {
+        if let Some(path) = snapshot.as_ref().filter(|path| path.exists()) {
+            engine.load_balances(File::open(path)?)?;
+        }
+        Ok(Self { engine, snapshot })
+    }
+
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    /// Serves one connection at a time until a client sends [`Command::Shutdown`], then
+    /// removes the socket file.
+    pub fn run(&mut self, listener: UnixListener) -> io::Result<()> {
+        let socket = listener.local_addr()?.as_pathname().map(Path::to_path_buf);
+        for stream in listener.incoming() {
+            if self.serve_connection(stream?)? {
+                break;
+            }
+        }
+        if let Some(socket) = socket {
+            fs::remove_file(socket)?;
+        }
+        Ok(())
+    }
+
+    /// Answers the commands of a connection until it is closed. Returns whether the client
+    /// asked the daemon to shut down.
+    pub fn serve_connection<S: io::Read + Write>(&mut self, stream: S) -> io::Result<bool> {
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+
+        while reader.read_line(&mut line)? > 0 {
+            if !line.trim().is_empty() {
+                let (response, shutdown) = match serde_json::from_str(&line) {
+                    Ok(command) => self.execute(command),
+                    Err(err) => (
+                        Response::Error {
+                            message: err.to_string(),
+                        },
+                        false,
+                    ),
+                };
+
+                let stream = reader.get_mut();
+                serde_json::to_writer(&mut *stream, &response)?;
+                writeln!(stream)?;
+                if shutdown {
+                    return Ok(true);
+                }
+            }
+            line.clear();
+        }
+
+        Ok(false)
+    }
+
+    /// Executes a command, returning its response and whether the daemon should stop.
+    pub fn execute(&mut self, command: Command) -> (Response, bool) {
+        match command {
+            Command::Tx(transaction) => match self.engine.process_transaction(transaction) {
+                Ok(()) => (Response::Ok, false),
+                Err(reason) => (
+                    Response::Rejected {
+                        reason: reason.to_string(),
+                    },
+                    false,
+                ),
+            },
+            Command::Query { client } => (
+                Response::Account {
+                    accounts: self.engine.account_outputs(client),
+                },
+                false,
+            ),
+            Command::Report => (
+                Response::Report {
+                    accounts: self.engine.output(),
+                    totals: self.engine.totals(),
+                },
+                false,
+            ),
+            Command::Snapshot { path } => (self.write_snapshot(&path), false),
+            Command::Shutdown => match self.snapshot.clone() {
+                Some(path) => (self.write_snapshot(&path), true),
+                None => (Response::Ok, true),
+            },
+        }
+    }
+
+    fn write_snapshot(&self, path: &Path) -> Response {
+        let result = File::create(path)
+            .map_err(csv::Error::from)
+            .and_then(|file| self.engine.write_balances(file));
+        match result {
+            Ok(()) => Response::Ok,
+            Err(err) => Response::Error {
+                message: err.to_string(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{os::unix::net::UnixStream, thread};
+
+    use rust_decimal::Decimal;
+
+    use super::*;
+
+    fn request(stream: &mut BufReader<UnixStream>, command: &str) -> Response {
+        writeln!(stream.get_mut(), "{command}").unwrap();
+        let mut line = String::new();
+        stream.read_line(&mut line).unwrap();
+        serde_json::from_str(&line).unwrap()
+    }
+
+    #[test]
+    fn test_dispute_flow() {
+        let dir = tempfile::tempdir().unwrap();
+        let snapshot = dir.path().join("balances.csv");
+        let (client, server) = UnixStream::pair().unwrap();
+
+        let daemon_snapshot = snapshot.clone();
+        let daemon = thread::spawn(move || {
+            let mut daemon = Daemon::new(Engine::new(), Some(daemon_snapshot)).unwrap();
+            assert!(daemon.serve_connection(server).unwrap());
+            daemon
+        });
+
+        let mut client = BufReader::new(client);
+        let deposit = r#"{"cmd":"tx","type":"deposit","client":1,"tx":1,"amount":"100"}"#;
+        assert_eq!(request(&mut client, deposit), Response::Ok);
+        let deposit = r#"{"cmd":"tx","type":"deposit","client":1,"tx":2,"amount":"20"}"#;
+        assert_eq!(request(&mut client, deposit), Response::Ok);
+        let dispute = r#"{"cmd":"tx","type":"dispute","client":1,"tx":1}"#;
+        assert_eq!(request(&mut client, dispute), Response::Ok);
+
+        let Response::Account { accounts } = request(&mut client, r#"{"cmd":"query","client":1}"#)
+        else {
+            panic!("expected an account");
+        };
+        let [account] = &accounts[..] else {
+            panic!("expected a single currency");
+        };
+        assert_eq!(account.available, Decimal::new(20, 0));
+        assert_eq!(account.held, Decimal::new(100, 0));
+        assert_eq!(
+            request(&mut client, r#"{"cmd":"query","client":2}"#),
+            Response::Account {
+                accounts: Vec::new()
+            }
+        );
+
+        let withdrawal = r#"{"cmd":"tx","type":"withdrawal","client":1,"tx":3,"amount":"50"}"#;
+        assert_eq!(
+            request(&mut client, withdrawal),
+            Response::Rejected {
+                reason: "insufficient available funds, requested 50 with 20 available".to_string()
+            }
+        );
+        assert!(matches!(
+            request(&mut client, r#"{"cmd":"refund"}"#),
+            Response::Error { .. }
+        ));
+
+        let chargeback = r#"{"cmd":"tx","type":"chargeback","client":1,"tx":1}"#;
+        assert_eq!(request(&mut client, chargeback), Response::Ok);
+        let Response::Report { accounts, totals } = request(&mut client, r#"{"cmd":"report"}"#)
+        else {
+            panic!("expected a report");
+        };
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(accounts[0].total, Decimal::new(20, 0));
+        assert!(accounts[0].locked);
+        assert_eq!(
+            totals,
+            Some(vec![Totals {
+                currency: None,
+                accounts: 1,
+                locked: 1,
+                available: Decimal::new(20, 0),
+                held: Decimal::ZERO,
+                total: Decimal::new(20, 0),
+            }])
+        );
+
+        assert_eq!(request(&mut client, r#"{"cmd":"shutdown"}"#), Response::Ok);
+        daemon.join().unwrap();
+
+        // The snapshot written on shutdown seeds the next daemon.
+        let restarted = Daemon::new(Engine::new(), Some(snapshot)).unwrap();
+        assert_eq!(restarted.engine().output(), accounts);
+    }
+}
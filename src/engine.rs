@@ -0,0 +1,2584 @@
+#[cfg(feature = "csv")]
+use std::io::{self, Read, Write};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, btree_map::Entry},
+    hash::{BuildHasher, DefaultHasher, RandomState},
+};
+
+use chrono::{DateTime, Duration, FixedOffset, Utc};
+#[cfg(feature = "cli")]
+use clap::ValueEnum;
+use rust_decimal::Decimal;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    account::{Account, Activity, GrossTotals, ProcessOutcome, round_amount},
+    error::TransactionError,
+    transaction::{
+        ClientId, Currency, Dispute, Tenant, Transaction, TransactionId, TransactionKind,
+    },
+};
+#[cfg(feature = "csv")]
+use crate::{input, output};
+
+/// Balance of a client, as stored in a balances snapshot.
+#[cfg(feature = "csv")]
+#[derive(Debug, Serialize, Deserialize)]
+struct Balance {
+    client: ClientId,
+    available: Decimal,
+    held: Decimal,
+    locked: bool,
+    /// Minimum balance of the client, overriding [`EngineConfig::min_balance`].
+    #[serde(default)]
+    min_balance: Option<Decimal>,
+    /// Currency of the balance, `None` being the implicit currency.
+    #[serde(default)]
+    currency: Option<Currency>,
+    /// Tenant of the client, if any.
+    #[serde(default)]
+    tenant: Option<Tenant>,
+    /// If the account is frozen by an administrative operation, see
+    /// [`Account::admin_frozen`].
+    #[serde(default)]
+    admin_frozen: bool,
+}
+
+/// Settings changing how the engine processes transactions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct EngineConfig {
+    /// Creates an empty account for any transaction of an unknown client, instead of only
+    /// for deposits.
+    pub create_on_any_transaction: bool,
+    /// Hashes clients, and the transaction ids of the maps of their accounts, with fixed
+    /// keys, so iterating over them has the same order on every run. Useful to get
+    /// reproducible debug traces.
+    pub deterministic_hashing: bool,
+    /// Maximum funds an account can have held. Disputes that would exceed it are rejected.
+    pub max_held_per_account: Option<Decimal>,
+    /// Rounds balances to 4 decimal places, with banker's rounding, after every
+    /// transaction instead of only when writing the output. The amounts of partial resolves
+    /// are rounded too, so disputes never keep a remainder below the output precision.
+    pub round_each_op: bool,
+    /// Maximum number of open disputes per account. Disputes beyond it are rejected until
+    /// one of the open ones is resolved or charged back.
+    pub max_open_disputes: Option<usize>,
+    /// Auto-resolves the disputes still open once the account processed that many more
+    /// transactions. Resolves and chargebacks of auto-resolved disputes are rejected.
+    pub auto_resolve_after: Option<u64>,
+    /// Treats a resolve of a deposit that was never disputed as a dispute resolved as soon
+    /// as it is opened: the balances do not change, but the dispute is recorded.
+    pub implicit_dispute_on_resolve: bool,
+    /// What to do with a dispute of a transaction that already has an open dispute.
+    pub on_duplicate_dispute: DuplicateDisputePolicy,
+    /// Longest time after a deposit it can still be disputed. Later disputes are rejected,
+    /// and so are the ones of or on transactions without a timestamp.
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            serialize_with = "serialize_dispute_window",
+            deserialize_with = "deserialize_dispute_window",
+            skip_serializing_if = "Option::is_none"
+        )
+    )]
+    pub dispute_window: Option<Duration>,
+    /// Minimum available funds a withdrawal can leave on an account. Disputes can still take
+    /// the available funds below it.
+    pub min_balance: Option<Decimal>,
+    /// Maximum amount of a single withdrawal, whatever the balance.
+    pub max_withdrawal: Option<Decimal>,
+    /// Clamps the withdrawals above the available funds to those funds instead of
+    /// rejecting them, recording the clamped amount in the history. Cannot be combined with
+    /// [`EngineConfig::withdrawal_fee`].
+    pub clamp_withdrawals: bool,
+    /// Maximum funds a client can withdraw per calendar day. Withdrawals without a
+    /// timestamp are rejected.
+    pub daily_withdrawal_limit: Option<Decimal>,
+    /// Offset from UTC of the time zone the days of [`EngineConfig::daily_withdrawal_limit`]
+    /// start in, UTC if not set.
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            serialize_with = "serialize_utc_offset",
+            deserialize_with = "deserialize_utc_offset",
+            skip_serializing_if = "Option::is_none"
+        )
+    )]
+    pub day_utc_offset: Option<FixedOffset>,
+    /// Fee charged on top of every accepted withdrawal of the implicit currency. Disputes
+    /// and chargebacks are never charged.
+    pub withdrawal_fee: Option<FeeSchedule>,
+    /// Fee of the deposits of the implicit currency per client tier, taken from the
+    /// deposited amount. The [`DEFAULT_TIER`] schedule applies to clients without a tier.
+    pub deposit_fees: BTreeMap<String, FeeSchedule>,
+    /// Limits per client tier, overriding the global ones for the clients of the tier. The
+    /// [`DEFAULT_TIER`] limits apply to clients without a tier.
+    pub tier_limits: BTreeMap<String, TierLimits>,
+    /// Tier of the clients, usually read with [`Engine::load_tiers`]. It is not part of
+    /// the config file, so it is neither serialized nor deserialized.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub client_tiers: HashMap<ClientId, String>,
+    /// Accepts administrative transactions, such as [`TransactionKind::Unlock`]. They are
+    /// rejected otherwise.
+    pub allow_admin_ops: bool,
+    /// Accepts the disputes of withdrawals, which hold the withdrawn amount until the
+    /// withdrawal stands with a resolve or is credited back with a chargeback. They are
+    /// rejected otherwise.
+    pub allow_withdrawal_disputes: bool,
+    /// Accepts a [`TransactionKind::WithdrawalReversal`] only if the account processed at
+    /// most that many transactions since the withdrawal, including the reversal. Without it,
+    /// reversals are rejected.
+    pub withdrawal_reversal_window: Option<u64>,
+    /// Lets a [`TransactionKind::Refund`] take the available funds below zero. Refunds of
+    /// more than the available funds are rejected otherwise.
+    pub allow_negative_refunds: bool,
+    /// Only requires the transaction ids to be unique per client, the default. When unset,
+    /// they are globally unique, and deposits and withdrawals reusing the id of another
+    /// client are rejected, see [`Engine::process_transaction`].
+    pub tx_ids_unique_per_client: bool,
+    /// Interest credited to the available funds of the accounts for every day elapsed
+    /// between their timestamped transactions, see [`Account::accrue_interest`].
+    pub interest: Option<InterestPolicy>,
+    /// Flags the accounts whose deposits of the implicit currency add up to more than this,
+    /// see [`EngineConfig::aml_flag`].
+    pub aml_cumulative_threshold: Option<Decimal>,
+    /// Flags the accounts with a single deposit of the implicit currency of more than this,
+    /// see [`EngineConfig::aml_flag`].
+    pub aml_single_threshold: Option<Decimal>,
+}
+
+/// Limits of the clients of a tier, see [`EngineConfig::tier_limits`]. Unset limits are
+/// the global ones.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct TierLimits {
+    /// Maximum amount of a single deposit of the implicit currency. There is no global one.
+    pub max_deposit: Option<Decimal>,
+    /// Overrides [`EngineConfig::max_withdrawal`].
+    pub max_withdrawal: Option<Decimal>,
+    /// Overrides [`EngineConfig::daily_withdrawal_limit`].
+    pub daily_withdrawal_limit: Option<Decimal>,
+    /// Overrides [`EngineConfig::max_open_disputes`].
+    pub max_open_disputes: Option<usize>,
+}
+
+/// Tier of the clients without one in [`EngineConfig::client_tiers`].
+pub const DEFAULT_TIER: &str = "default";
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        Self {
+            create_on_any_transaction: false,
+            deterministic_hashing: false,
+            max_held_per_account: None,
+            round_each_op: false,
+            max_open_disputes: None,
+            auto_resolve_after: None,
+            implicit_dispute_on_resolve: false,
+            on_duplicate_dispute: DuplicateDisputePolicy::default(),
+            dispute_window: None,
+            min_balance: None,
+            max_withdrawal: None,
+            clamp_withdrawals: false,
+            daily_withdrawal_limit: None,
+            day_utc_offset: None,
+            withdrawal_fee: None,
+            deposit_fees: BTreeMap::new(),
+            tier_limits: BTreeMap::new(),
+            client_tiers: HashMap::new(),
+            allow_admin_ops: false,
+            allow_withdrawal_disputes: false,
+            withdrawal_reversal_window: None,
+            allow_negative_refunds: false,
+            tx_ids_unique_per_client: true,
+            interest: None,
+            aml_cumulative_threshold: None,
+            aml_single_threshold: None,
+        }
+    }
+}
+
+impl EngineConfig {
+    /// Tier of `client`, [`DEFAULT_TIER`] if it has none.
+    pub fn tier(&self, client: ClientId) -> &str {
+        self.client_tiers
+            .get(&client)
+            .map_or(DEFAULT_TIER, String::as_str)
+    }
+
+    /// If `tier` has a deposit fee schedule or limits.
+    pub fn has_tier(&self, tier: &str) -> bool {
+        self.deposit_fees.contains_key(tier) || self.tier_limits.contains_key(tier)
+    }
+
+    /// Fee of a deposit of `amount` by `client`, according to its tier. It is never more
+    /// than the deposit.
+    pub fn deposit_fee(&self, client: ClientId, amount: Decimal) -> Decimal {
+        self.deposit_fees
+            .get(self.tier(client))
+            .map_or(Decimal::ZERO, |schedule| schedule.fee(amount).min(amount))
+    }
+
+    /// Limit of `client` picked by `limit` from its tier limits, with the name of the tier,
+    /// or the `global` one if its tier does not set it.
+    pub fn limit<T>(
+        &self,
+        client: ClientId,
+        global: Option<T>,
+        limit: impl Fn(&TierLimits) -> Option<T>,
+    ) -> Option<(T, Option<&str>)> {
+        let tier = self.tier(client);
+        match self.tier_limits.get(tier).and_then(limit) {
+            Some(value) => Some((value, Some(tier))),
+            None => global.map(|value| (value, None)),
+        }
+    }
+
+    /// Anti-money laundering thresholds exceeded by the deposits of `account`. The deposits
+    /// count with their gross amount, even once charged back.
+    pub fn aml_flag(&self, account: &Account) -> AmlFlag {
+        let exceeds = |threshold: Option<Decimal>, amount| threshold.is_some_and(|t| amount > t);
+        match (
+            exceeds(self.aml_cumulative_threshold, account.deposited),
+            exceeds(self.aml_single_threshold, account.largest_deposit),
+        ) {
+            (false, false) => AmlFlag::None,
+            (true, false) => AmlFlag::Cumulative,
+            (false, true) => AmlFlag::Single,
+            (true, true) => AmlFlag::Both,
+        }
+    }
+}
+
+/// Anti-money laundering thresholds of [`EngineConfig`] an account exceeded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AmlFlag {
+    None,
+    /// The deposits add up to more than [`EngineConfig::aml_cumulative_threshold`].
+    Cumulative,
+    /// A deposit is more than [`EngineConfig::aml_single_threshold`].
+    Single,
+    /// Both thresholds are exceeded.
+    Both,
+}
+
+impl AmlFlag {
+    pub fn name(self) -> &'static str {
+        match self {
+            AmlFlag::None => "none",
+            AmlFlag::Cumulative => "cumulative",
+            AmlFlag::Single => "single",
+            AmlFlag::Both => "both",
+        }
+    }
+}
+
+/// Parses a dispute window such as `90d` or `12h 30m`.
+#[cfg(feature = "serde")]
+pub fn parse_dispute_window(text: &str) -> Result<Duration, String> {
+    let window = humantime::parse_duration(text).map_err(|err| err.to_string())?;
+    Duration::from_std(window).map_err(|_| format!("duration {text:?} is too long"))
+}
+
+/// Writes the window in the format read by [`parse_dispute_window`]. Whole days are written
+/// as such, e.g. `90d`, as humantime would turn them into months of 30.44 days.
+#[cfg(feature = "serde")]
+fn serialize_dispute_window<S: serde::Serializer>(
+    window: &Option<Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let Some(window) = window else {
+        return serializer.serialize_none();
+    };
+    if *window > Duration::zero() && *window == Duration::days(window.num_days()) {
+        return serializer.collect_str(&format_args!("{}d", window.num_days()));
+    }
+    let window = window
+        .to_std()
+        .map_err(|_| serde::ser::Error::custom(format!("negative dispute window {window}")))?;
+    serializer.collect_str(&humantime::format_duration(window))
+}
+
+#[cfg(feature = "serde")]
+fn deserialize_dispute_window<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Duration>, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    parse_dispute_window(&text)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+#[cfg(feature = "serde")]
+fn serialize_utc_offset<S: serde::Serializer>(
+    offset: &Option<FixedOffset>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match offset {
+        Some(offset) => serializer.collect_str(offset),
+        None => serializer.serialize_none(),
+    }
+}
+
+#[cfg(feature = "serde")]
+fn deserialize_utc_offset<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<FixedOffset>, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    text.parse()
+        .map(Some)
+        .map_err(|_| serde::de::Error::custom(format!("invalid UTC offset {text:?}")))
+}
+
+/// Handling of a dispute of a transaction that already has an open dispute. The funds are
+/// only held once either way.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "lowercase")
+)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+pub enum DuplicateDisputePolicy {
+    /// Accepts the dispute without any effect.
+    #[default]
+    Ignore,
+    /// Rejects the dispute with [`TransactionError::AlreadyDisputed`].
+    Error,
+}
+
+/// Fee of a withdrawal: a flat part plus a percentage of the amount, clamped between `min`
+/// and `max`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct FeeSchedule {
+    pub flat: Decimal,
+    /// Percentage of the withdrawn amount, e.g. `1` for 1%.
+    pub percent: Decimal,
+    pub min: Option<Decimal>,
+    pub max: Option<Decimal>,
+}
+
+impl FeeSchedule {
+    /// Fee of a withdrawal of `amount`, rounded with [`round_amount`].
+    pub fn fee(&self, amount: Decimal) -> Decimal {
+        let mut fee = self.flat + amount * self.percent / Decimal::ONE_HUNDRED;
+        if let Some(min) = self.min {
+            fee = fee.max(min);
+        }
+        if let Some(max) = self.max {
+            fee = fee.min(max);
+        }
+        round_amount(fee)
+    }
+}
+
+/// Simple interest on the available funds of the accounts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct InterestPolicy {
+    /// Interest of a day, as a fraction of the available funds, e.g. `0.0001`.
+    pub daily_rate: Decimal,
+}
+
+/// Hasher of the client map and of the maps of the accounts, either randomly seeded or with
+/// fixed keys.
+#[derive(Debug, Clone)]
+pub enum ClientHasher {
+    Random(RandomState),
+    Deterministic,
+}
+
+impl Default for ClientHasher {
+    fn default() -> Self {
+        Self::Random(RandomState::new())
+    }
+}
+
+impl BuildHasher for ClientHasher {
+    type Hasher = DefaultHasher;
+
+    fn build_hasher(&self) -> DefaultHasher {
+        match self {
+            Self::Random(state) => state.build_hasher(),
+            Self::Deterministic => DefaultHasher::new(),
+        }
+    }
+}
+
+/// State of a client account in one currency, as reported in the output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct EngineOutput {
+    /// Tenant of the client, if any.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub tenant: Option<Tenant>,
+    pub client: ClientId,
+    /// Currency of the balances, `None` being the implicit currency.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub currency: Option<Currency>,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    /// If the account is locked by a chargeback or frozen by an administrative operation.
+    pub locked: bool,
+}
+
+impl EngineOutput {
+    /// State of the account in the implicit currency.
+    pub fn new(client: ClientId, account: &Account) -> Self {
+        Self::in_currency(client, account, None)
+    }
+
+    /// State of the account in `currency`, `None` being the implicit currency.
+    pub fn in_currency(client: ClientId, account: &Account, currency: Option<&Currency>) -> Self {
+        let balance = account.balance(currency);
+        Self {
+            tenant: None,
+            client,
+            currency: currency.cloned(),
+            available: balance.available,
+            held: balance.held,
+            total: balance.total(),
+            locked: account.locked || account.admin_frozen,
+        }
+    }
+
+    /// If the available or the total balance is negative, e.g. after a chargeback of funds
+    /// already withdrawn.
+    pub fn is_negative(&self) -> bool {
+        self.available < Decimal::ZERO || self.total < Decimal::ZERO
+    }
+
+    /// Amount the client would have to pay back for neither balance to be negative.
+    pub fn shortfall(&self) -> Decimal {
+        (-self.available.min(self.total)).max(Decimal::ZERO)
+    }
+}
+
+/// Sums of the final balances of the accounts in one currency, see [`Engine::totals`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Totals {
+    /// Currency of the sums, `None` being the implicit currency.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub currency: Option<Currency>,
+    /// Number of rows of the output in the currency, one per account using it.
+    pub accounts: u64,
+    /// Number of those rows whose account is locked or frozen.
+    pub locked: u64,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+}
+
+impl Totals {
+    /// Adds a row of the output in the same currency to the totals, `None` if a sum
+    /// overflows.
+    pub fn checked_add(self, output: &EngineOutput) -> Option<Self> {
+        debug_assert_eq!(self.currency, output.currency);
+        Some(Self {
+            currency: self.currency,
+            accounts: self.accounts + 1,
+            locked: self.locked + u64::from(output.locked),
+            available: self.available.checked_add(output.available)?,
+            held: self.held.checked_add(output.held)?,
+            total: self.total.checked_add(output.total)?,
+        })
+    }
+
+    /// Sums of `rows` per currency, the implicit currency first and then ordered by
+    /// currency. Without rows, the sums are zero in the implicit currency. `None` if a sum
+    /// overflows.
+    pub fn by_currency<'a>(rows: impl IntoIterator<Item = &'a EngineOutput>) -> Option<Vec<Self>> {
+        let mut totals: BTreeMap<Option<Currency>, Self> = BTreeMap::new();
+        for row in rows {
+            let sums = totals.entry(row.currency.clone()).or_insert_with(|| Self {
+                currency: row.currency.clone(),
+                ..Self::default()
+            });
+            *sums = std::mem::take(sums).checked_add(row)?;
+        }
+        if totals.is_empty() {
+            return Some(vec![Self::default()]);
+        }
+        Some(totals.into_values().collect())
+    }
+}
+
+/// Client whose account differs between two engines.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountDiff {
+    pub client: ClientId,
+    /// State of the account in the first engine, if it exists there.
+    pub left: Option<EngineOutput>,
+    /// State of the account in the second engine, if it exists there.
+    pub right: Option<EngineOutput>,
+    /// Number of disputes of the account in the first engine.
+    pub left_disputes: usize,
+    /// Number of disputes of the account in the second engine.
+    pub right_disputes: usize,
+}
+
+/// Routes transactions to their client accounts and owns the state of all of them.
+///
+/// Transactions with a tenant are processed by a separate engine per tenant, with the same
+/// config, so the same client id in two tenants has two accounts.
+#[derive(Clone, Default)]
+pub struct Engine {
+    /// Accounts of every client seen so far.
+    pub clients: HashMap<ClientId, Account, ClientHasher>,
+    pub config: EngineConfig,
+    /// Clients whose account changed since the last [`Engine::drain_changed`].
+    changed: BTreeSet<ClientId>,
+    /// Clients seeded by [`Engine::load_balances`] that had no transaction since.
+    inactive: BTreeSet<ClientId>,
+    /// Latest timestamp of the processed transactions.
+    latest_timestamp: Option<DateTime<Utc>>,
+    /// Client of every deposit and withdrawal id, while ids are globally unique.
+    tx_owners: HashMap<TransactionId, ClientId>,
+    /// Sequence number of the last transaction processed.
+    sequence: u64,
+    /// Engines of the tenants seen so far, see [`Engine::tenant_engine`].
+    tenants: BTreeMap<Tenant, Engine>,
+    /// Tenant of the accounts of this engine, `None` unless it is the engine of a tenant.
+    tenant: Option<Tenant>,
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_config(config: EngineConfig) -> Self {
+        let hasher = if config.deterministic_hashing {
+            ClientHasher::Deterministic
+        } else {
+            ClientHasher::default()
+        };
+        Self {
+            clients: HashMap::with_hasher(hasher),
+            config,
+            changed: BTreeSet::new(),
+            inactive: BTreeSet::new(),
+            latest_timestamp: None,
+            tx_owners: HashMap::new(),
+            sequence: 0,
+            tenants: BTreeMap::new(),
+            tenant: None,
+        }
+    }
+
+    /// Engine of the accounts of `tenant`, this one for `None`, if the tenant had a
+    /// transaction.
+    pub fn tenant_engine(&self, tenant: Option<&Tenant>) -> Option<&Engine> {
+        match tenant {
+            Some(tenant) => self.tenants.get(tenant),
+            None => Some(self),
+        }
+    }
+
+    /// Tenant of the accounts of this engine, if it is the engine of a tenant.
+    pub fn tenant(&self) -> Option<&Tenant> {
+        self.tenant.as_ref()
+    }
+
+    /// Engines of the tenants seen so far, ordered by tenant.
+    pub fn tenant_engines(&self) -> impl Iterator<Item = &Engine> {
+        self.tenants.values()
+    }
+
+    /// Engine of the accounts of `tenant`, created with the same config if needed.
+    fn tenant_engine_mut(&mut self, tenant: Tenant) -> &mut Engine {
+        match self.tenants.entry(tenant) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let mut engine = Engine::with_config(self.config.clone());
+                engine.tenant = Some(entry.key().clone());
+                entry.insert(engine)
+            }
+        }
+    }
+
+    /// Fails if `transaction` references a transaction, e.g. as part of a dispute, that its
+    /// client does not have in its tenant, but has in another one.
+    fn check_dispute_tenant(&self, transaction: &Transaction) -> Result<(), TransactionError> {
+        if !transaction.references_transaction()
+            || (self.tenants.is_empty() && transaction.tenant.is_none())
+        {
+            return Ok(());
+        }
+        let has_transaction = |engine: &Engine| {
+            engine
+                .clients
+                .get(&transaction.client)
+                .is_some_and(|account| account.transactions.contains_key(&transaction.id))
+        };
+        if self
+            .tenant_engine(transaction.tenant.as_ref())
+            .is_some_and(has_transaction)
+        {
+            return Ok(());
+        }
+        let in_other_tenant = std::iter::once(self)
+            .chain(self.tenants.values())
+            .filter(|engine| engine.tenant != transaction.tenant)
+            .any(has_transaction);
+        if in_other_tenant {
+            return Err(TransactionError::CrossTenantDispute);
+        }
+        Ok(())
+    }
+
+    /// Processes a transaction on the client's account. If the client does not have an
+    /// account yet, one is only created for a deposit, and dropped if the deposit is
+    /// rejected, unless [`EngineConfig::create_on_any_transaction`] is set. Transactions
+    /// of a locked account are rejected with [`TransactionError::AccountLocked`], and
+    /// counted by the account.
+    ///
+    /// Transactions with a tenant go to the account of the client in that tenant. Disputes,
+    /// resolves and chargebacks of a transaction the client only has in another tenant are
+    /// rejected with [`TransactionError::CrossTenantDispute`].
+    ///
+    /// Unless [`EngineConfig::tx_ids_unique_per_client`] is set, deposits and withdrawals
+    /// reusing the id of another client's accepted deposit or withdrawal are rejected with
+    /// [`TransactionError::TxIdReused`]. Ids are owned per tenant.
+    pub fn process_transaction(
+        &mut self,
+        transaction: Transaction,
+    ) -> Result<(), TransactionError> {
+        self.process_transaction_at(self.sequence + 1, transaction)
+    }
+
+    /// Like [`Engine::process_transaction`], but with the `sequence` number of the
+    /// transaction in the run given, for callers processing parts of a run separately, such
+    /// as shards. Later transactions continue from it.
+    pub fn process_transaction_at(
+        &mut self,
+        sequence: u64,
+        transaction: Transaction,
+    ) -> Result<(), TransactionError> {
+        self.sequence = sequence;
+        if self.tenant.is_none() {
+            self.check_dispute_tenant(&transaction)?;
+            if let Some(tenant) = transaction.tenant.clone() {
+                self.latest_timestamp = self.latest_timestamp.max(transaction.timestamp);
+                return self
+                    .tenant_engine_mut(tenant)
+                    .process_transaction_at(sequence, transaction);
+            }
+        }
+        let claims_id = self.check_tx_id(&transaction)?;
+        let creates_account = self.config.create_on_any_transaction
+            || matches!(transaction.kind, TransactionKind::Deposit { .. });
+
+        // Accounts created by any transaction are kept even if it is rejected.
+        let created = !self.config.create_on_any_transaction
+            && creates_account
+            && !self.clients.contains_key(&transaction.client);
+        let client = if creates_account {
+            let hasher = self.clients.hasher().clone();
+            self.clients
+                .entry(transaction.client)
+                .or_insert_with(|| Account::with_hasher(Decimal::ZERO, hasher))
+        } else {
+            match self.clients.get_mut(&transaction.client) {
+                Some(client) => client,
+                None => return Ok(()),
+            }
+        };
+        let (client_id, tx_id) = (transaction.client, transaction.id);
+        let activity = Activity {
+            sequence,
+            timestamp: transaction.timestamp,
+        };
+        self.latest_timestamp = self.latest_timestamp.max(transaction.timestamp);
+        self.inactive.remove(&client_id);
+        match client.try_process_at(transaction, &self.config, activity) {
+            Ok(ProcessOutcome::Applied) => {
+                if claims_id {
+                    self.tx_owners.insert(tx_id, client_id);
+                }
+            }
+            Ok(ProcessOutcome::Ignored) => {}
+            Err(reason) => {
+                // The account only existed for the rejected transaction.
+                if created {
+                    self.clients.remove(&client_id);
+                }
+                return Err(reason);
+            }
+        }
+        self.changed.insert(client_id);
+        Ok(())
+    }
+
+    /// Fails if the transaction is a deposit or withdrawal reusing the id of another
+    /// client's accepted deposit or withdrawal, returning whether it claims its id once
+    /// accepted. Rejected transactions claim nothing, so the first client to have an id
+    /// accepted keeps it. Ids are never claimed with
+    /// [`EngineConfig::tx_ids_unique_per_client`].
+    fn check_tx_id(&self, transaction: &Transaction) -> Result<bool, TransactionError> {
+        let introduces_id = matches!(
+            transaction.kind,
+            TransactionKind::Deposit { .. } | TransactionKind::Withdrawal { .. }
+        );
+        if self.config.tx_ids_unique_per_client || !introduces_id {
+            return Ok(false);
+        }
+        match self.tx_owners.get(&transaction.id) {
+            Some(&owner) if owner != transaction.client => {
+                Err(TransactionError::TxIdReused { owner })
+            }
+            _ => Ok(true),
+        }
+    }
+
+    /// Processes the transactions in order, all of them or none: if one is rejected, the
+    /// accounts of the clients of the batch, the sequence number and the latest timestamp of
+    /// the run are restored to their state before it and the reason is returned.
+    pub fn apply_atomic(&mut self, txs: &[Transaction]) -> Result<(), TransactionError> {
+        let clients: BTreeSet<ClientId> =
+            txs.iter().map(|transaction| transaction.client).collect();
+        let snapshot: Vec<_> = clients
+            .into_iter()
+            .map(|client| {
+                (
+                    client,
+                    self.clients.get(&client).cloned(),
+                    self.changed.contains(&client),
+                    self.inactive.contains(&client),
+                )
+            })
+            .collect();
+        // Tenants are rare enough in batches to restore all of them.
+        let tenants = txs
+            .iter()
+            .any(|transaction| transaction.tenant.is_some())
+            .then(|| self.tenants.clone());
+        let new_ids: Vec<TransactionId> = txs
+            .iter()
+            .map(|transaction| transaction.id)
+            .filter(|id| !self.tx_owners.contains_key(id))
+            .collect();
+        let (sequence, latest_timestamp) = (self.sequence, self.latest_timestamp);
+
+        for transaction in txs {
+            if let Err(reason) = self.process_transaction(transaction.clone()) {
+                for id in new_ids {
+                    self.tx_owners.remove(&id);
+                }
+                self.sequence = sequence;
+                self.latest_timestamp = latest_timestamp;
+                if let Some(tenants) = tenants {
+                    self.tenants = tenants;
+                }
+                for (client, account, changed, inactive) in snapshot {
+                    match account {
+                        Some(account) => self.clients.insert(client, account),
+                        None => self.clients.remove(&client),
+                    };
+                    if !changed {
+                        self.changed.remove(&client);
+                    }
+                    if inactive {
+                        self.inactive.insert(client);
+                    }
+                }
+                return Err(reason);
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves the accounts of `other` into this engine, replacing existing accounts of the
+    /// same clients in the same tenants.
+    pub fn merge(&mut self, other: Engine) {
+        for (tenant, engine) in other.tenants {
+            match self.tenants.entry(tenant) {
+                Entry::Occupied(mut entry) => entry.get_mut().merge(engine),
+                Entry::Vacant(entry) => {
+                    entry.insert(engine);
+                }
+            }
+        }
+        self.inactive
+            .retain(|client| !other.clients.contains_key(client));
+        self.clients.extend(other.clients);
+        self.changed.extend(other.changed);
+        self.inactive.extend(other.inactive);
+        self.latest_timestamp = self.latest_timestamp.max(other.latest_timestamp);
+        self.tx_owners.extend(other.tx_owners);
+        self.sequence = self.sequence.max(other.sequence);
+    }
+
+    /// Credits every account with the interest due up to the latest timestamp processed,
+    /// at the end of a run. Does nothing without [`EngineConfig::interest`].
+    pub fn accrue_final_interest(&mut self) {
+        let (Some(policy), Some(until)) = (self.config.interest, self.latest_timestamp) else {
+            return;
+        };
+        for (client, account) in &mut self.clients {
+            if account.accrue_interest(&policy, until) {
+                self.changed.insert(*client);
+            }
+        }
+        for engine in self.tenants.values_mut() {
+            engine.latest_timestamp = Some(until);
+            engine.accrue_final_interest();
+        }
+    }
+
+    /// Moves the accounts of the clients matching `predicate` into a new engine with the
+    /// same config and the same claimed transaction ids, in every tenant.
+    pub fn split_off(&mut self, predicate: impl Fn(ClientId) -> bool) -> Engine {
+        self.split_clients(&predicate)
+    }
+
+    fn split_clients(&mut self, predicate: &dyn Fn(ClientId) -> bool) -> Engine {
+        let mut split = Engine::with_config(self.config.clone());
+        split.tx_owners = self.tx_owners.clone();
+        split.tenant = self.tenant.clone();
+        split.tenants = self
+            .tenants
+            .iter_mut()
+            .map(|(tenant, engine)| (tenant.clone(), engine.split_clients(predicate)))
+            .collect();
+        let clients: Vec<ClientId> = self
+            .clients
+            .keys()
+            .copied()
+            .filter(|client| predicate(*client))
+            .collect();
+        for client in clients {
+            if let Some(account) = self.clients.remove(&client) {
+                split.clients.insert(client, account);
+            }
+            if self.changed.remove(&client) {
+                split.changed.insert(client);
+            }
+            if self.inactive.remove(&client) {
+                split.inactive.insert(client);
+            }
+        }
+        split
+    }
+
+    /// Sequence number of the last transaction processed, 0 before the first one.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// If the client had a transaction, as opposed to an account only seeded by
+    /// [`Engine::load_balances`].
+    pub fn is_active(&self, client: ClientId) -> bool {
+        !self.inactive.contains(&client)
+    }
+
+    /// State of every account changed since the last call, one per currency it uses like
+    /// [`Engine::output`], ordered by tenant, those without a tenant first, and then by
+    /// client. An account counts as changed once a transaction of it is accepted, even if it
+    /// had no effect, e.g. a dispute of an unknown transaction.
+    pub fn drain_changed(&mut self) -> Vec<EngineOutput> {
+        let mut changed: Vec<_> = std::mem::take(&mut self.changed)
+            .into_iter()
+            .flat_map(|client| self.account_outputs(client))
+            .collect();
+        for engine in self.tenants.values_mut() {
+            changed.extend(engine.drain_changed());
+        }
+        changed
+    }
+
+    /// Writes the state of every account changed since the last call as JSON Lines, one
+    /// object per account and currency ordered by client, like [`Engine::drain_changed`].
+    #[cfg(feature = "cli")]
+    pub fn emit_deltas_jsonl<W: Write>(&mut self, mut w: W) -> io::Result<()> {
+        for output in self.drain_changed() {
+            serde_json::to_writer(&mut w, &output)?;
+            w.write_all(b"\n")?;
+        }
+        w.flush()
+    }
+
+    /// Seeds accounts from a balances CSV with the `client, available, held, locked` columns,
+    /// and optional `min_balance`, `currency`, `tenant` and `admin_frozen` ones, replacing
+    /// any existing account of the same client in the same tenant.
+    ///
+    /// Seeded accounts start with an empty history, so transactions that happened before the
+    /// snapshot cannot be disputed, and funds already held cannot be released by a resolve.
+    #[cfg(feature = "csv")]
+    pub fn load_balances<R: Read>(&mut self, r: R) -> csv::Result<()> {
+        // Clients have one row per currency, only the first one replaces the account.
+        let mut loaded = BTreeSet::new();
+        for balance in input::reader(r).deserialize() {
+            let balance: Balance = balance?;
+            let engine = match balance.tenant.clone() {
+                Some(tenant) if self.tenant.is_none() => self.tenant_engine_mut(tenant),
+                _ => &mut *self,
+            };
+            if loaded.insert((balance.tenant, balance.client)) {
+                engine.clients.remove(&balance.client);
+            }
+            let hasher = engine.clients.hasher().clone();
+            let account = engine
+                .clients
+                .entry(balance.client)
+                .or_insert_with(|| Account::with_hasher(Decimal::ZERO, hasher));
+            match balance.currency {
+                Some(currency) => {
+                    let funds = account.currencies.entry(currency).or_default();
+                    funds.available = balance.available;
+                    funds.held = balance.held;
+                }
+                None => {
+                    account.available = balance.available;
+                    account.held = balance.held;
+                }
+            }
+            account.locked = balance.locked;
+            account.admin_frozen = balance.admin_frozen;
+            account.min_balance = balance.min_balance;
+            engine.changed.insert(balance.client);
+            engine.inactive.insert(balance.client);
+        }
+        Ok(())
+    }
+
+    /// Assigns tiers to clients from a CSV with the `client, tier` columns, see
+    /// [`EngineConfig::deposit_fees`] and [`EngineConfig::tier_limits`]. Fails on tiers
+    /// without a fee schedule nor limits. The tiers apply to the clients of every tenant,
+    /// including the tenants already seeded.
+    #[cfg(feature = "csv")]
+    pub fn load_tiers<R: Read>(&mut self, r: R) -> csv::Result<()> {
+        for row in input::reader(r).deserialize() {
+            let (client, tier): (ClientId, String) = row?;
+            if !self.config.has_tier(&tier) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("client {} has the unknown tier {tier:?}", client.0),
+                )
+                .into());
+            }
+            for engine in self.tenants.values_mut() {
+                engine.config.client_tiers.insert(client, tier.clone());
+            }
+            self.config.client_tiers.insert(client, tier);
+        }
+        Ok(())
+    }
+
+    /// Writes the balances of every account in the format read by [`Engine::load_balances`],
+    /// ordered by client.
+    #[cfg(feature = "csv")]
+    pub fn write_balances<W: Write>(&self, w: W) -> csv::Result<()> {
+        let mut wtr = csv::Writer::from_writer(w);
+        for output in self.output() {
+            let account = &self
+                .tenant_engine(output.tenant.as_ref())
+                .unwrap_or(self)
+                .clients[&output.client];
+            wtr.serialize(Balance {
+                client: output.client,
+                available: output.available,
+                held: output.held,
+                locked: account.locked,
+                min_balance: account.min_balance,
+                currency: output.currency,
+                tenant: output.tenant,
+                admin_frozen: account.admin_frozen,
+            })?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+
+    /// Writes the history of every account in the format transactions are read in, ordered
+    /// by tenant, those without a tenant first, then by client and then in processing order.
+    /// Interest credits are written as `interest` rows without a transaction id, see
+    /// [`output::write_ledger`].
+    #[cfg(feature = "csv")]
+    pub fn export_history<W: Write>(&self, w: W) -> csv::Result<()> {
+        let mut accounts = Vec::new();
+        for engine in std::iter::once(self).chain(self.tenants.values()) {
+            let mut clients: Vec<_> = engine.clients.iter().collect();
+            clients.sort_by_key(|(client, _)| **client);
+            accounts.extend(clients);
+        }
+        output::write_ledger(
+            accounts
+                .into_iter()
+                .flat_map(|(client, account)| account.ledger().map(|entry| (*client, entry))),
+            w,
+        )
+    }
+
+    /// State of the client account in the implicit currency, if it exists.
+    pub fn account_output(&self, client: ClientId) -> Option<EngineOutput> {
+        let account = self.clients.get(&client)?;
+        Some(EngineOutput {
+            tenant: self.tenant.clone(),
+            ..EngineOutput::new(client, account)
+        })
+    }
+
+    /// State of the client account, one per currency it uses like [`Engine::output`], empty
+    /// if it does not exist.
+    pub fn account_outputs(&self, client: ClientId) -> Vec<EngineOutput> {
+        let Some(account) = self.clients.get(&client) else {
+            return Vec::new();
+        };
+        account
+            .balances()
+            .map(|(currency, _)| EngineOutput {
+                tenant: self.tenant.clone(),
+                ..EngineOutput::in_currency(client, account, currency)
+            })
+            .collect()
+    }
+
+    /// Funds the client can spend in the implicit currency, excluding the held funds, if
+    /// the account exists.
+    pub fn available(&self, client: ClientId) -> Option<Decimal> {
+        self.clients.get(&client).map(|account| account.available)
+    }
+
+    /// State of every account, one per currency it uses, ordered by tenant, those without a
+    /// tenant first, then by client and then by currency, the implicit currency first.
+    pub fn output(&self) -> Vec<EngineOutput> {
+        let mut clients: Vec<_> = self.clients.keys().copied().collect();
+        clients.sort_unstable();
+        let mut output: Vec<_> = clients
+            .into_iter()
+            .flat_map(|client| self.account_outputs(client))
+            .collect();
+        for engine in self.tenants.values() {
+            output.extend(engine.output());
+        }
+        output
+    }
+
+    /// Account of `client` re-derived from its history, see [`Account::replay`], to compare
+    /// it with the live one. Only looks at the accounts without a tenant.
+    pub fn replay_client(&self, client: ClientId) -> Option<Account> {
+        self.clients
+            .get(&client)
+            .map(|account| account.replay(&self.config))
+    }
+
+    /// Rows of [`Engine::output`] split into those of unlocked and of locked accounts, each
+    /// in the same order.
+    pub fn partition_by_locked(&self) -> (Vec<EngineOutput>, Vec<EngineOutput>) {
+        self.output().into_iter().partition(|output| !output.locked)
+    }
+
+    /// Sums of the rows of [`Engine::output`] per currency, computed from the final
+    /// balances, see [`Totals::by_currency`]. `None` if a sum overflows.
+    pub fn totals(&self) -> Option<Vec<Totals>> {
+        Totals::by_currency(&self.output())
+    }
+
+    /// Rows of [`Engine::output`] of the accounts with a negative balance, as told by
+    /// [`EngineOutput::is_negative`].
+    pub fn negative_accounts(&self) -> Vec<EngineOutput> {
+        self.output()
+            .into_iter()
+            .filter(EngineOutput::is_negative)
+            .collect()
+    }
+
+    /// Sum of the [`Account::gross_totals`] of every account, those of the tenants included.
+    pub fn gross_totals(&self) -> GrossTotals {
+        let mut totals = GrossTotals::default();
+        for engine in std::iter::once(self).chain(self.tenant_engines()) {
+            for account in engine.clients.values() {
+                totals += account.gross_totals();
+            }
+        }
+        totals
+    }
+
+    /// Lists the clients whose balances, locked status or number of disputes differ from
+    /// `other`, including clients that only have an account in one of the engines. The
+    /// differences are ordered by tenant, those without a tenant first, and then by client.
+    pub fn diff(&self, other: &Engine) -> Vec<AccountDiff> {
+        let mut diffs = self.diff_clients(other);
+        let tenants: BTreeSet<&Tenant> = self.tenants.keys().chain(other.tenants.keys()).collect();
+        for tenant in tenants {
+            let empty = Engine::default();
+            let left = self.tenants.get(tenant).unwrap_or(&empty);
+            let right = other.tenants.get(tenant).unwrap_or(&empty);
+            diffs.extend(left.diff_clients(right));
+        }
+        diffs
+    }
+
+    /// Differences of [`Engine::diff`] between the accounts of both engines, without their
+    /// tenants.
+    fn diff_clients(&self, other: &Engine) -> Vec<AccountDiff> {
+        let clients: BTreeSet<ClientId> = self
+            .clients
+            .keys()
+            .chain(other.clients.keys())
+            .copied()
+            .collect();
+
+        clients
+            .into_iter()
+            .filter_map(|client| {
+                let left_disputes = self.clients.get(&client).map_or(0, |a| a.disputes.len());
+                let right_disputes = other.clients.get(&client).map_or(0, |a| a.disputes.len());
+                let diff = AccountDiff {
+                    client,
+                    left: self.account_output(client),
+                    right: other.account_output(client),
+                    left_disputes,
+                    right_disputes,
+                };
+                (diff.left != diff.right || left_disputes != right_disputes).then_some(diff)
+            })
+            .collect()
+    }
+
+    /// Rough estimate of the memory used by the accounts, for capacity planning: the size of
+    /// every account and of the entries of its history and disputes. Allocator overhead,
+    /// spare capacity and memos are not counted.
+    pub fn approx_memory_bytes(&self) -> usize {
+        let accounts: usize = self
+            .clients
+            .values()
+            .map(|account| {
+                size_of::<(ClientId, Account)>()
+                    + account.transactions.len() * size_of::<(TransactionId, Transaction)>()
+                    + account.disputes.len() * size_of::<(TransactionId, Dispute)>()
+            })
+            .sum();
+        accounts
+            + self
+                .tenants
+                .values()
+                .map(Engine::approx_memory_bytes)
+                .sum::<usize>()
+    }
+
+    /// Sum of the deposit and withdrawal fees charged to every account.
+    pub fn fees_collected(&self) -> Decimal {
+        let fees: Decimal = self.clients.values().map(|account| account.fees_paid).sum();
+        fees + self
+            .tenants
+            .values()
+            .map(Engine::fees_collected)
+            .sum::<Decimal>()
+    }
+
+    /// Every dispute of every account, those of the tenants included, open or not, in no
+    /// particular order, along with the tenant of the account.
+    pub fn all_disputes(
+        &self,
+    ) -> impl Iterator<Item = (Option<&Tenant>, ClientId, TransactionId, &Dispute)> {
+        std::iter::once(self)
+            .chain(self.tenants.values())
+            .flat_map(|engine| {
+                engine.clients.iter().flat_map(move |(client, account)| {
+                    account
+                        .disputes
+                        .iter()
+                        .map(move |(tx, dispute)| (engine.tenant(), *client, *tx, dispute))
+                })
+            })
+    }
+
+    /// Lists the disputes whose disputed transaction is missing from the account history,
+    /// those of the tenants included, ordered by tenant, those without a tenant first, then
+    /// by client and transaction. This cannot happen when transactions are only processed,
+    /// but can once the history was pruned.
+    pub fn dangling_disputes(&self) -> Vec<(Option<Tenant>, ClientId, TransactionId)> {
+        let mut dangling: Vec<_> = std::iter::once(self)
+            .chain(self.tenants.values())
+            .flat_map(|engine| {
+                engine.clients.iter().flat_map(move |(client, account)| {
+                    account
+                        .disputes
+                        .keys()
+                        .filter(|tx| !account.transactions.contains_key(*tx))
+                        .map(move |tx| (engine.tenant.clone(), *client, *tx))
+                })
+            })
+            .collect();
+        dangling.sort();
+        dangling
+    }
+
+    /// Auto-resolves every open dispute older than `max_age`, releasing its held funds back
+    /// to the client. Disputes opened by transactions without a timestamp never expire.
+    pub fn expire_disputes(&mut self, now: DateTime<Utc>, max_age: Duration) {
+        let cutoff = now - max_age;
+        for (client, account) in &mut self.clients {
+            if account.expire_disputes(cutoff) {
+                self.changed.insert(*client);
+            }
+        }
+        for engine in self.tenants.values_mut() {
+            engine.expire_disputes(now, max_age);
+        }
+    }
+
+    /// Resolves every open dispute of every account, releasing its held funds back to the
+    /// client. Charged back disputes are left alone.
+    pub fn resolve_all_open(&mut self) {
+        for (client, account) in &mut self.clients {
+            if account.resolve_all_open() {
+                self.changed.insert(*client);
+            }
+        }
+        for engine in self.tenants.values_mut() {
+            engine.resolve_all_open();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::{
+        testkit::{self, TxSeq, engine_from},
+        transaction::DisputeState,
+    };
+
+    #[test]
+    #[cfg(feature = "csv")]
+    fn test_load_balances() {
+        let balances = "client, available, held, locked
+1, 100.0, 20.0, false
+2, 5.5, 0, true
+";
+        let mut engine = Engine::new();
+        engine.load_balances(balances.as_bytes()).unwrap();
+
+        engine
+            .process_transaction(testkit::withdrawal(1, 1, "40"))
+            .unwrap();
+        let locked = engine.process_transaction(testkit::withdrawal(2, 2, "1"));
+        assert_eq!(locked, Err(TransactionError::AccountLocked));
+
+        let first = &engine.clients[&ClientId(1)];
+        assert_eq!(first.available, Decimal::new(60, 0));
+        assert_eq!(first.held, Decimal::new(20, 0));
+        assert_eq!(first.total_funds(), Decimal::new(80, 0));
+        assert!(!first.locked);
+
+        // Locked accounts keep rejecting transactions after being seeded.
+        let second = &engine.clients[&ClientId(2)];
+        assert_eq!(second.available, Decimal::new(55, 1));
+        assert_eq!(second.held, Decimal::ZERO);
+        assert!(second.locked);
+    }
+
+    #[test]
+    #[cfg(feature = "csv")]
+    fn test_load_currencies() {
+        let balances = "client,available,held,locked,min_balance,currency,tenant,admin_frozen
+1,10,0,false,,,,false
+1,20,5,false,,EUR,,false
+2,7,0,true,,USD,,true
+";
+        let mut engine = Engine::new();
+        engine.load_balances(balances.as_bytes()).unwrap();
+        let mut snapshot = Vec::new();
+        engine.write_balances(&mut snapshot).unwrap();
+        assert_eq!(String::from_utf8(snapshot).unwrap(), balances);
+
+        // Loading again replaces the accounts instead of adding to them.
+        engine
+            .load_balances("client,available,held,locked\n1,3,0,false\n".as_bytes())
+            .unwrap();
+        assert!(engine.clients[&ClientId(1)].currencies.is_empty());
+        assert_eq!(engine.clients[&ClientId(1)].available, Decimal::new(3, 0));
+    }
+
+    #[test]
+    #[cfg(feature = "csv")]
+    fn test_load_min_balance() {
+        let balances = "client, available, held, locked, min_balance
+1, 100.0, 0, false, 50
+2, 100.0, 0, false,
+";
+        let mut engine = Engine::with_config(EngineConfig {
+            min_balance: Some(Decimal::new(10, 0)),
+            ..EngineConfig::default()
+        });
+        engine.load_balances(balances.as_bytes()).unwrap();
+
+        let withdrawal = |client| {
+            testkit::transaction(
+                client,
+                client.into(),
+                TransactionKind::Withdrawal {
+                    amount: Decimal::new(60, 0),
+                },
+            )
+        };
+        assert_eq!(
+            engine.process_transaction(withdrawal(1)),
+            Err(TransactionError::MinimumBalance {
+                min: Decimal::new(50, 0),
+                would_be: Decimal::new(40, 0),
+            })
+        );
+        assert_eq!(engine.process_transaction(withdrawal(2)), Ok(()));
+
+        let mut snapshot = Vec::new();
+        engine.write_balances(&mut snapshot).unwrap();
+        assert_eq!(
+            String::from_utf8(snapshot).unwrap(),
+            "client,available,held,locked,min_balance,currency,tenant,admin_frozen\n\
+             1,100,0,false,50,,,false\n\
+             2,40,0,false,,,,false\n"
+        );
+    }
+
+    #[test]
+    fn test_diff() {
+        let transactions = [
+            (
+                1,
+                1,
+                TransactionKind::Deposit {
+                    amount: Decimal::new(100, 0),
+                },
+            ),
+            (
+                2,
+                2,
+                TransactionKind::Deposit {
+                    amount: Decimal::new(50, 0),
+                },
+            ),
+            (1, 1, TransactionKind::Dispute { amount: None }),
+        ];
+        let mut left = Engine::new();
+        let mut right = Engine::new();
+        for (client, id, kind) in transactions {
+            let transaction = testkit::transaction(client, id, kind);
+            left.process_transaction(transaction.clone()).unwrap();
+            right.process_transaction(transaction).unwrap();
+        }
+        assert!(left.diff(&right).is_empty());
+
+        right
+            .process_transaction(testkit::withdrawal(2, 3, "20"))
+            .unwrap();
+
+        let diff = left.diff(&right);
+        assert_eq!(diff.len(), 1);
+        assert_eq!(diff[0].client, ClientId(2));
+        assert_eq!(
+            diff[0].left.as_ref().unwrap().available,
+            Decimal::new(50, 0)
+        );
+        assert_eq!(
+            diff[0].right.as_ref().unwrap().available,
+            Decimal::new(30, 0)
+        );
+        assert_eq!((diff[0].left_disputes, diff[0].right_disputes), (0, 0));
+    }
+
+    #[test]
+    fn test_expire_disputes() {
+        // Client deposits 100 and 50. The first deposit is disputed ten days before `now`,
+        // the second one a day before. With a max age of a week only the first is released.
+        let now = Utc.with_ymd_and_hms(2024, 6, 15, 0, 0, 0).unwrap();
+        let transactions = vec![
+            testkit::deposit(1, 1, "100"),
+            testkit::deposit(1, 2, "50"),
+            Transaction {
+                timestamp: Some(now - Duration::days(10)),
+                ..testkit::dispute(1, 1)
+            },
+            Transaction {
+                timestamp: Some(now - Duration::days(1)),
+                ..testkit::dispute(1, 2)
+            },
+        ];
+
+        let mut engine = Engine::new();
+        for tx in transactions {
+            engine.process_transaction(tx).unwrap();
+        }
+        engine.expire_disputes(now, Duration::days(7));
+
+        let account = &engine.clients[&ClientId(1)];
+        assert_eq!(account.available, Decimal::new(100, 0));
+        assert_eq!(account.held, Decimal::new(50, 0));
+        assert_eq!(account.total_funds(), Decimal::new(150, 0));
+        assert!(!account.disputes[&TransactionId(1)].can_finish());
+        assert!(account.disputes[&TransactionId(2)].can_finish());
+    }
+
+    #[test]
+    fn test_resolve_all_open() {
+        // Client 1 deposits 100 and 50, disputes both and charges back the first one.
+        // Client 2 deposits 30 and disputes it.
+        let mut engine = engine_from(
+            TxSeq::new()
+                .deposit(1, 1, "100")
+                .deposit(1, 2, "50")
+                .deposit(2, 3, "30")
+                .dispute(1, 1)
+                .dispute(1, 2)
+                .dispute(2, 3)
+                .chargeback(1, 1),
+        );
+        engine.resolve_all_open();
+
+        let first = &engine.clients[&ClientId(1)];
+        assert_eq!(first.available, Decimal::new(50, 0));
+        assert_eq!(first.held, Decimal::ZERO);
+        assert!(first.locked);
+        assert_eq!(first.total_funds(), Decimal::new(50, 0));
+        assert!(first.disputes.values().all(|dispute| !dispute.can_finish()));
+
+        let second = &engine.clients[&ClientId(2)];
+        assert_eq!(second.available, Decimal::new(30, 0));
+        assert_eq!(second.held, Decimal::ZERO);
+        assert!(!second.is_disputed(TransactionId(3)));
+    }
+
+    #[test]
+    fn test_resolve_all_open_currency() {
+        let currency = Currency("EUR".to_string());
+        let eur = |transaction| Transaction {
+            currency: Some(currency.clone()),
+            ..transaction
+        };
+        let mut engine = Engine::new();
+        for transaction in [testkit::deposit(1, 1, "10"), testkit::dispute(1, 1)] {
+            engine.process_transaction(eur(transaction)).unwrap();
+        }
+        engine.drain_changed();
+
+        // Only the EUR balances move, which still marks the client as changed.
+        engine.resolve_all_open();
+        let changed = engine.drain_changed();
+        assert_eq!(changed.len(), 1);
+        assert_eq!(changed[0].client, ClientId(1));
+        let balance = engine.clients[&ClientId(1)].balance(Some(&currency));
+        assert_eq!(
+            (balance.available, balance.held),
+            (Decimal::TEN, Decimal::ZERO)
+        );
+        // Nothing is left to resolve.
+        engine.resolve_all_open();
+        assert!(engine.drain_changed().is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "csv")]
+    fn test_memo_in_history() {
+        let input = "\
+type,client,tx,amount,memo
+deposit,2,1,10.0,invoice 42
+deposit,1,2,5.0,
+withdrawal,1,3,1.0,\"refund, partial\"
+";
+        let mut engine = Engine::new();
+        for transaction in input::reader(input.as_bytes()).deserialize() {
+            engine.process_transaction(transaction.unwrap()).unwrap();
+        }
+
+        let history = &engine.clients[&ClientId(2)].transactions;
+        assert_eq!(
+            history[&TransactionId(1)].memo.as_deref(),
+            Some("invoice 42")
+        );
+        assert_eq!(
+            engine.clients[&ClientId(1)].transactions[&TransactionId(2)].memo,
+            None
+        );
+
+        let mut exported = Vec::new();
+        engine.export_history(&mut exported).unwrap();
+        assert_eq!(
+            String::from_utf8(exported).unwrap(),
+            "\
+type,client,tx,amount,timestamp,memo,currency,tenant
+deposit,1,2,5,,,,
+withdrawal,1,3,1,,\"refund, partial\",,
+deposit,2,1,10,,invoice 42,,
+"
+        );
+    }
+
+    #[test]
+    fn test_withdrawal_fees() {
+        let schedule = FeeSchedule {
+            flat: Decimal::new(25, 2),
+            percent: Decimal::ONE,
+            min: Some(Decimal::ONE),
+            max: Some(Decimal::new(5, 0)),
+        };
+        assert_eq!(schedule.fee(Decimal::new(10, 0)), Decimal::ONE);
+        assert_eq!(schedule.fee(Decimal::new(200, 0)), Decimal::new(225, 2));
+        assert_eq!(schedule.fee(Decimal::new(1000, 0)), Decimal::new(5, 0));
+
+        let mut engine = Engine::with_config(EngineConfig {
+            withdrawal_fee: Some(schedule),
+            ..EngineConfig::default()
+        });
+        let transactions = [
+            (
+                1,
+                TransactionKind::Deposit {
+                    amount: Decimal::new(1000, 0),
+                },
+            ),
+            (
+                2,
+                TransactionKind::Deposit {
+                    amount: Decimal::new(500, 0),
+                },
+            ),
+            (
+                1,
+                TransactionKind::Withdrawal {
+                    amount: Decimal::new(10, 0),
+                },
+            ),
+            (
+                1,
+                TransactionKind::Withdrawal {
+                    amount: Decimal::new(600, 0),
+                },
+            ),
+            (
+                2,
+                TransactionKind::Withdrawal {
+                    amount: Decimal::new(200, 0),
+                },
+            ),
+        ];
+        for (id, (client, kind)) in transactions.into_iter().enumerate() {
+            engine
+                .process_transaction(testkit::transaction(client, id as u32, kind))
+                .unwrap();
+        }
+
+        assert_eq!(engine.fees_collected(), Decimal::new(825, 2));
+        // Deposits minus withdrawals is what the clients have left plus the fees.
+        let balances: Decimal = engine.output().iter().map(|output| output.total).sum();
+        assert_eq!(
+            balances + engine.fees_collected(),
+            Decimal::new(1500 - 810, 0)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "csv")]
+    fn test_deposit_fee_tiers() {
+        let percent = |percent| FeeSchedule {
+            percent,
+            ..FeeSchedule::default()
+        };
+        let mut engine = Engine::with_config(EngineConfig {
+            deposit_fees: BTreeMap::from([
+                ("a".to_string(), percent(Decimal::ZERO)),
+                ("b".to_string(), percent(Decimal::new(5, 1))),
+                (DEFAULT_TIER.to_string(), percent(Decimal::ONE)),
+            ]),
+            ..EngineConfig::default()
+        });
+        engine
+            .load_tiers("client,tier\n1,a\n2,b\n".as_bytes())
+            .unwrap();
+        let err = engine
+            .load_tiers("client,tier\n3,gold\n".as_bytes())
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("client 3 has the unknown tier \"gold\"")
+        );
+
+        let transactions = [
+            (
+                1,
+                1,
+                TransactionKind::Deposit {
+                    amount: Decimal::new(1000, 0),
+                },
+            ),
+            (
+                2,
+                2,
+                TransactionKind::Deposit {
+                    amount: Decimal::new(1000, 0),
+                },
+            ),
+            // Client 3 has no tier, so it pays the default fee.
+            (
+                3,
+                3,
+                TransactionKind::Deposit {
+                    amount: Decimal::new(1000, 0),
+                },
+            ),
+            (2, 2, TransactionKind::Dispute { amount: None }),
+        ];
+        for (client, id, kind) in transactions {
+            engine
+                .process_transaction(testkit::transaction(client, id, kind))
+                .unwrap();
+        }
+
+        let available = |client| engine.clients[&ClientId(client)].available;
+        assert_eq!(available(1), Decimal::new(1000, 0));
+        assert_eq!(available(3), Decimal::new(990, 0));
+        // The dispute holds the gross amount of the deposit, fee included.
+        let disputed = &engine.clients[&ClientId(2)];
+        assert_eq!(disputed.held, Decimal::new(1000, 0));
+        assert_eq!(disputed.available, Decimal::new(-5, 0));
+        assert_eq!(
+            disputed.transactions[&TransactionId(2)].deposit_amount(),
+            Some(Decimal::new(1000, 0))
+        );
+        assert_eq!(engine.fees_collected(), Decimal::new(15, 0));
+    }
+
+    #[test]
+    fn test_tier_limits() {
+        let strict = TierLimits {
+            max_deposit: Some(Decimal::new(100, 0)),
+            max_withdrawal: Some(Decimal::new(20, 0)),
+            max_open_disputes: Some(1),
+            ..TierLimits::default()
+        };
+        let mut engine = Engine::with_config(EngineConfig {
+            max_withdrawal: Some(Decimal::new(50, 0)),
+            tier_limits: BTreeMap::from([("strict".to_string(), strict)]),
+            client_tiers: HashMap::from([(ClientId(1), "strict".to_string())]),
+            ..EngineConfig::default()
+        });
+        let deposit = |amount| TransactionKind::Deposit {
+            amount: Decimal::new(amount, 0),
+        };
+        let withdrawal = |amount| TransactionKind::Withdrawal {
+            amount: Decimal::new(amount, 0),
+        };
+
+        // Client 1, in the strict tier, and client 2, in the default one, do the same
+        // transactions.
+        let mut rejected = Vec::new();
+        for client in [1, 2] {
+            let transactions = [
+                (1, deposit(150)),
+                (2, deposit(80)),
+                (3, deposit(10)),
+                (4, withdrawal(30)),
+                (5, withdrawal(60)),
+                (2, TransactionKind::Dispute { amount: None }),
+                (3, TransactionKind::Dispute { amount: None }),
+            ];
+            for (id, kind) in transactions {
+                let result = engine.process_transaction(testkit::transaction(
+                    client,
+                    u32::from(client) * 10 + id,
+                    kind,
+                ));
+                if let Err(reason) = result {
+                    rejected.push((client, id, reason));
+                }
+            }
+        }
+        let strict = || Some("strict".to_string());
+        assert_eq!(
+            rejected,
+            vec![
+                (
+                    1,
+                    1,
+                    TransactionError::DepositLimitExceeded {
+                        limit: Decimal::new(100, 0),
+                        tier: strict(),
+                    }
+                ),
+                (
+                    1,
+                    4,
+                    TransactionError::WithdrawalLimitExceeded {
+                        limit: Decimal::new(20, 0),
+                        tier: strict(),
+                    }
+                ),
+                (
+                    1,
+                    5,
+                    TransactionError::WithdrawalLimitExceeded {
+                        limit: Decimal::new(20, 0),
+                        tier: strict(),
+                    }
+                ),
+                (
+                    1,
+                    3,
+                    TransactionError::TooManyOpenDisputes { tier: strict() }
+                ),
+                (
+                    2,
+                    5,
+                    TransactionError::WithdrawalLimitExceeded {
+                        limit: Decimal::new(50, 0),
+                        tier: None,
+                    }
+                ),
+            ]
+        );
+        assert_eq!(
+            rejected[0].2.to_string(),
+            "deposit above the limit of 100 of tier \"strict\""
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "csv")]
+    fn test_interest() {
+        let input = "\
+type,client,tx,amount,timestamp
+deposit,1,1,1000,2024-01-01T00:00:00Z
+deposit,3,2,500,2024-01-01T00:00:00Z
+dispute,3,2,,2024-01-02T00:00:00Z
+chargeback,3,2,,2024-01-02T00:00:00Z
+withdrawal,1,3,100,2024-01-04T12:00:00Z
+deposit,2,4,10,2024-01-09T00:00:00Z
+";
+        let mut engine = Engine::with_config(EngineConfig {
+            interest: Some(InterestPolicy {
+                daily_rate: Decimal::new(1, 3),
+            }),
+            ..EngineConfig::default()
+        });
+        for transaction in input::reader(input.as_bytes()).deserialize() {
+            engine.process_transaction(transaction.unwrap()).unwrap();
+        }
+        engine.accrue_final_interest();
+
+        // 3 whole days on 1000 before the withdrawal, the half day carrying over, then 5
+        // days on 903 up to the last timestamp of the run.
+        assert_eq!(
+            engine.clients[&ClientId(1)].available,
+            Decimal::new(907515, 3)
+        );
+        assert_eq!(engine.clients[&ClientId(2)].available, Decimal::new(10, 0));
+        // A day of interest on 500, then nothing once the account is locked.
+        assert_eq!(engine.clients[&ClientId(3)].available, Decimal::new(5, 1));
+
+        let mut exported = Vec::new();
+        engine.export_history(&mut exported).unwrap();
+        let exported = String::from_utf8(exported).unwrap();
+        assert!(
+            exported.contains(
+                "\
+deposit,1,1,1000,2024-01-01T00:00:00+00:00,,,
+interest,1,,3.000,2024-01-04T00:00:00+00:00,,,
+withdrawal,1,3,100,2024-01-04T12:00:00+00:00,,,
+interest,1,,4.5150,2024-01-09T00:00:00+00:00,,,
+"
+            ),
+            "{exported}"
+        );
+    }
+
+    #[test]
+    fn test_drain_changed() {
+        let mut engine = Engine::new();
+        for (client, id) in [(2, 1), (1, 2), (2, 3)] {
+            engine
+                .process_transaction(testkit::deposit(client, id, "10"))
+                .unwrap();
+        }
+        let drained: Vec<ClientId> = engine.drain_changed().iter().map(|o| o.client).collect();
+        assert_eq!(drained, vec![ClientId(1), ClientId(2)]);
+        assert!(engine.drain_changed().is_empty());
+
+        engine
+            .process_transaction(testkit::deposit(3, 4, "10"))
+            .unwrap();
+        let drained = engine.drain_changed();
+        assert_eq!(drained.len(), 1);
+        assert_eq!(drained[0], engine.account_output(ClientId(3)).unwrap());
+
+        // One row per currency of the account.
+        let eur = Currency("EUR".to_string());
+        engine
+            .process_transaction(Transaction {
+                currency: Some(eur.clone()),
+                ..testkit::deposit(3, 5, "4")
+            })
+            .unwrap();
+        let drained = engine.drain_changed();
+        assert_eq!(drained, engine.account_outputs(ClientId(3)));
+        let currencies: Vec<_> = drained.iter().map(|o| o.currency.clone()).collect();
+        assert_eq!(currencies, vec![None, Some(eur)]);
+        assert_eq!(drained[1].available, Decimal::new(4, 0));
+    }
+
+    #[test]
+    fn test_tx_ids_unique_per_client() {
+        let run = |config| {
+            let mut engine = Engine::with_config(config);
+            engine
+                .process_transaction(testkit::deposit(1, 5, "10"))
+                .unwrap();
+            let result = engine.process_transaction(testkit::deposit(2, 5, "10"));
+            engine.process_transaction(testkit::dispute(2, 5)).unwrap();
+            (engine, result)
+        };
+
+        let globally_unique = EngineConfig {
+            tx_ids_unique_per_client: false,
+            ..EngineConfig::default()
+        };
+        let (engine, result) = run(globally_unique.clone());
+        assert_eq!(
+            result,
+            Err(TransactionError::TxIdReused { owner: ClientId(1) })
+        );
+        assert!(!engine.clients.contains_key(&ClientId(2)));
+        assert_eq!(engine.available(ClientId(1)), Some(Decimal::new(10, 0)));
+
+        // A rejected transaction does not claim its id.
+        let mut engine = Engine::with_config(globally_unique);
+        engine
+            .process_transaction(testkit::deposit(1, 1, "10"))
+            .unwrap();
+        assert!(matches!(
+            engine.process_transaction(testkit::withdrawal(1, 5, "50")),
+            Err(TransactionError::InsufficientFunds { .. })
+        ));
+        engine
+            .process_transaction(testkit::deposit(2, 5, "10"))
+            .unwrap();
+        assert_eq!(engine.available(ClientId(2)), Some(Decimal::new(10, 0)));
+
+        let (engine, result) = run(EngineConfig::default());
+        assert_eq!(result, Ok(()));
+        // The dispute only looks in the history of client 2.
+        assert_eq!(engine.available(ClientId(1)), Some(Decimal::new(10, 0)));
+        let output = engine.account_output(ClientId(2)).unwrap();
+        assert_eq!(output.available, Decimal::ZERO);
+        assert_eq!(output.held, Decimal::new(10, 0));
+    }
+
+    #[test]
+    fn test_lock_seq_in_run() {
+        let engine = engine_from(
+            TxSeq::new()
+                .deposit(1, 1, "10")
+                .deposit(2, 2, "5")
+                .deposit(2, 3, "5")
+                .dispute(1, 1)
+                .chargeback(1, 1),
+        );
+
+        // The account of client 1 only processed 3 of the 5 transactions.
+        let lock = engine.clients[&ClientId(1)].lock_info().unwrap();
+        assert_eq!(lock.tx, TransactionId(1));
+        assert_eq!(lock.seq, 5);
+    }
+
+    #[test]
+    fn test_locked_rejections() {
+        let mut engine = engine_from(
+            TxSeq::new()
+                .deposit(1, 1, "100")
+                .deposit(1, 2, "30")
+                .dispute(1, 2)
+                .chargeback(1, 2),
+        );
+
+        let rejected = TxSeq::new()
+            .deposit(1, 3, "10")
+            .deposit(1, 4, "20.5")
+            .withdrawal(1, 5, "50");
+        for transaction in rejected {
+            assert_eq!(
+                engine.process_transaction(transaction),
+                Err(TransactionError::AccountLocked)
+            );
+        }
+
+        let account = &engine.clients[&ClientId(1)];
+        assert_eq!(account.locked_rejections, 3);
+        assert_eq!(account.locked_rejected_amount, Decimal::new(805, 1));
+        assert_eq!(account.available, Decimal::new(100, 0));
+    }
+
+    #[test]
+    fn test_approx_memory_bytes() {
+        let mut engine = Engine::new();
+        assert_eq!(engine.approx_memory_bytes(), 0);
+
+        let mut previous = 0;
+        for id in 1..=3 {
+            engine
+                .process_transaction(testkit::deposit(1, id, "10"))
+                .unwrap();
+            assert!(engine.approx_memory_bytes() > previous);
+            previous = engine.approx_memory_bytes();
+        }
+        engine.process_transaction(testkit::dispute(1, 1)).unwrap();
+        assert!(engine.approx_memory_bytes() > previous);
+    }
+
+    #[test]
+    fn test_available() {
+        let engine = engine_from(
+            TxSeq::new()
+                .deposit(1, 1, "100")
+                .deposit(1, 2, "40")
+                .dispute(1, 2),
+        );
+
+        assert_eq!(engine.available(ClientId(1)), Some(Decimal::new(100, 0)));
+        assert_eq!(
+            engine.account_output(ClientId(1)).unwrap().held,
+            Decimal::new(40, 0)
+        );
+        assert_eq!(engine.available(ClientId(2)), None);
+    }
+
+    #[test]
+    fn test_rejected_first_deposit() {
+        let strict = TierLimits {
+            max_deposit: Some(Decimal::new(100, 0)),
+            ..TierLimits::default()
+        };
+        let mut engine = Engine::with_config(EngineConfig {
+            tier_limits: BTreeMap::from([("strict".to_string(), strict)]),
+            client_tiers: HashMap::from([(ClientId(1), "strict".to_string())]),
+            ..EngineConfig::default()
+        });
+        assert!(matches!(
+            engine.process_transaction(testkit::deposit(1, 1, "150")),
+            Err(TransactionError::DepositLimitExceeded { .. })
+        ));
+        assert!(!engine.clients.contains_key(&ClientId(1)));
+        assert!(engine.output().is_empty());
+
+        // An existing account is kept.
+        engine
+            .process_transaction(testkit::deposit(1, 2, "50"))
+            .unwrap();
+        assert!(
+            engine
+                .process_transaction(testkit::deposit(1, 3, "150"))
+                .is_err()
+        );
+        assert_eq!(engine.available(ClientId(1)), Some(Decimal::new(50, 0)));
+    }
+
+    #[test]
+    #[cfg(feature = "csv")]
+    fn test_tiers_of_seeded_tenants() {
+        let strict = TierLimits {
+            max_withdrawal: Some(Decimal::new(20, 0)),
+            ..TierLimits::default()
+        };
+        let mut engine = Engine::with_config(EngineConfig {
+            tier_limits: BTreeMap::from([("strict".to_string(), strict)]),
+            ..EngineConfig::default()
+        });
+        engine
+            .load_balances(
+                "client, available, held, locked, tenant\n1, 100, 0, false, acme\n".as_bytes(),
+            )
+            .unwrap();
+        engine
+            .load_tiers("client,tier\n1,strict\n".as_bytes())
+            .unwrap();
+
+        let withdrawal = Transaction {
+            tenant: Some(Tenant("acme".to_string())),
+            ..testkit::withdrawal(1, 1, "30")
+        };
+        assert_eq!(
+            engine.process_transaction(withdrawal),
+            Err(TransactionError::WithdrawalLimitExceeded {
+                limit: Decimal::new(20, 0),
+                tier: Some("strict".to_string()),
+            })
+        );
+    }
+
+    #[test]
+    fn test_tenants() {
+        let transaction = |tenant: Option<&str>, kind, id| Transaction {
+            tenant: tenant.map(|tenant| Tenant(tenant.to_string())),
+            ..testkit::transaction(1, id, kind)
+        };
+        let deposit = |amount| TransactionKind::Deposit {
+            amount: Decimal::new(amount, 0),
+        };
+
+        let mut engine = Engine::new();
+        for tx in [
+            transaction(None, deposit(5), 1),
+            transaction(Some("acme"), deposit(100), 1),
+            transaction(Some("globex"), deposit(30), 2),
+        ] {
+            engine.process_transaction(tx).unwrap();
+        }
+        assert_eq!(
+            engine.process_transaction(transaction(
+                Some("globex"),
+                TransactionKind::Dispute { amount: None },
+                1
+            )),
+            Err(TransactionError::CrossTenantDispute)
+        );
+        assert_eq!(
+            engine.process_transaction(transaction(
+                None,
+                TransactionKind::Dispute { amount: None },
+                2
+            )),
+            Err(TransactionError::CrossTenantDispute)
+        );
+        engine
+            .process_transaction(transaction(
+                Some("acme"),
+                TransactionKind::Dispute { amount: None },
+                1,
+            ))
+            .unwrap();
+        // Ids are only unique per tenant.
+        assert_eq!(
+            engine.process_transaction(transaction(Some("globex"), deposit(1), 1)),
+            Ok(())
+        );
+
+        let balances: Vec<_> = engine
+            .output()
+            .into_iter()
+            .map(|output| (output.tenant.map(|t| t.0), output.available, output.held))
+            .collect();
+        assert_eq!(
+            balances,
+            vec![
+                (None, Decimal::new(5, 0), Decimal::ZERO),
+                (
+                    Some("acme".to_string()),
+                    Decimal::ZERO,
+                    Decimal::new(100, 0)
+                ),
+                (
+                    Some("globex".to_string()),
+                    Decimal::new(31, 0),
+                    Decimal::ZERO
+                ),
+            ]
+        );
+        assert_eq!(engine.available(ClientId(1)), Some(Decimal::new(5, 0)));
+    }
+
+    #[test]
+    #[cfg(feature = "cli")]
+    fn test_emit_deltas_jsonl() {
+        let emit = |engine: &mut Engine| {
+            let mut deltas = Vec::new();
+            engine.emit_deltas_jsonl(&mut deltas).unwrap();
+            String::from_utf8(deltas).unwrap()
+        };
+
+        let mut engine = Engine::new();
+        for (client, id) in [(2, 1), (1, 2)] {
+            engine
+                .process_transaction(testkit::deposit(client, id, "10"))
+                .unwrap();
+        }
+        assert_eq!(
+            emit(&mut engine),
+            "{\"client\":1,\"available\":\"10\",\"held\":\"0\",\"total\":\"10\",\"locked\":false}\n\
+             {\"client\":2,\"available\":\"10\",\"held\":\"0\",\"total\":\"10\",\"locked\":false}\n"
+        );
+        assert_eq!(emit(&mut engine), "");
+
+        engine
+            .process_transaction(testkit::deposit(2, 3, "10"))
+            .unwrap();
+        assert_eq!(
+            emit(&mut engine),
+            "{\"client\":2,\"available\":\"20\",\"held\":\"0\",\"total\":\"20\",\"locked\":false}\n"
+        );
+    }
+
+    #[test]
+    fn test_apply_atomic() {
+        let mut engine = Engine::new();
+        engine
+            .apply_atomic(&TxSeq::new().deposit(1, 1, "50").deposit(2, 2, "20").build())
+            .unwrap();
+        engine.drain_changed();
+        let before = engine.output();
+
+        // The withdrawal of client 2 fails after the first two transactions were applied.
+        let result = engine.apply_atomic(
+            &TxSeq::new()
+                .withdrawal(1, 3, "10")
+                .dispute(1, 1)
+                .deposit(3, 4, "5")
+                .withdrawal(2, 5, "30")
+                .deposit(2, 6, "5")
+                .build(),
+        );
+
+        assert_eq!(
+            result,
+            Err(TransactionError::InsufficientFunds {
+                requested: Decimal::new(30, 0),
+                available: Decimal::new(20, 0),
+            })
+        );
+        assert_eq!(engine.output(), before);
+        assert!(!engine.clients[&ClientId(1)].is_disputed(TransactionId(1)));
+        assert!(!engine.clients.contains_key(&ClientId(3)));
+        assert!(engine.drain_changed().is_empty());
+
+        engine
+            .apply_atomic(&[testkit::withdrawal(2, 5, "10")])
+            .unwrap();
+        assert_eq!(
+            engine.account_output(ClientId(2)).unwrap().available,
+            Decimal::new(10, 0)
+        );
+    }
+
+    #[test]
+    fn test_apply_atomic_timestamps() {
+        let on_day = |day, transaction| Transaction {
+            timestamp: Some(Utc.with_ymd_and_hms(2024, 1, day, 0, 0, 0).unwrap()),
+            ..transaction
+        };
+        let mut engine = Engine::with_config(EngineConfig {
+            interest: Some(InterestPolicy {
+                daily_rate: Decimal::new(1, 2),
+            }),
+            ..EngineConfig::default()
+        });
+        engine
+            .apply_atomic(&[on_day(1, testkit::deposit(1, 1, "1000"))])
+            .unwrap();
+
+        // The rejected batch neither counts its transactions nor moves the run forward.
+        let result = engine.apply_atomic(&[
+            on_day(10, testkit::deposit(2, 2, "10")),
+            on_day(10, testkit::withdrawal(1, 3, "5000")),
+        ]);
+        assert!(result.is_err());
+        assert_eq!(engine.sequence(), 1);
+
+        engine
+            .apply_atomic(&[on_day(2, testkit::deposit(2, 4, "10"))])
+            .unwrap();
+        assert_eq!(engine.sequence(), 2);
+        assert_eq!(
+            engine.clients[&ClientId(2)]
+                .first_activity
+                .unwrap()
+                .sequence,
+            2
+        );
+        // A single day of interest, up to the latest accepted timestamp.
+        engine.accrue_final_interest();
+        assert_eq!(engine.available(ClientId(1)), Some(Decimal::new(1010, 0)));
+    }
+
+    #[test]
+    fn test_all_disputes() {
+        let mut engine = engine_from(
+            TxSeq::new()
+                .deposit(1, 1, "10")
+                .deposit(1, 2, "10")
+                .deposit(2, 3, "1")
+                .dispute(1, 1)
+                .dispute(1, 2)
+                .resolve(1, 2)
+                .dispute(2, 3),
+        );
+
+        let acme = Tenant("acme".to_string());
+        for transaction in TxSeq::new().deposit(1, 4, "5").dispute(1, 4) {
+            engine
+                .process_transaction(Transaction {
+                    tenant: Some(acme.clone()),
+                    ..transaction
+                })
+                .unwrap();
+        }
+
+        let mut disputes: Vec<_> = engine
+            .all_disputes()
+            .map(|(tenant, client, tx, dispute)| {
+                let state = dispute.state().clone();
+                (tenant.cloned(), client.0, tx.0, state, dispute.held())
+            })
+            .collect();
+        disputes.sort_by(|a, b| (&a.0, a.1, a.2).cmp(&(&b.0, b.1, b.2)));
+        assert_eq!(
+            disputes,
+            vec![
+                (None, 1, 1, DisputeState::Disputed, Decimal::TEN),
+                (None, 1, 2, DisputeState::Resolved, Decimal::ZERO),
+                (None, 2, 3, DisputeState::Disputed, Decimal::ONE),
+                (Some(acme), 1, 4, DisputeState::Disputed, Decimal::new(5, 0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_partition_by_locked() {
+        let mut engine = Engine::new();
+        let deposit = TransactionKind::Deposit {
+            amount: Decimal::TEN,
+        };
+        let transactions = [
+            (3, 1, deposit),
+            (1, 2, deposit),
+            (4, 3, deposit),
+            (2, 4, deposit),
+            (3, 1, TransactionKind::Dispute { amount: None }),
+            (3, 1, TransactionKind::Chargeback),
+            (2, 4, TransactionKind::Dispute { amount: None }),
+            (2, 4, TransactionKind::Chargeback),
+        ];
+        for (client, id, kind) in transactions {
+            engine
+                .process_transaction(testkit::transaction(client, id, kind))
+                .unwrap();
+        }
+
+        let (unlocked, locked) = engine.partition_by_locked();
+        let clients =
+            |rows: &[EngineOutput]| rows.iter().map(|row| row.client.0).collect::<Vec<_>>();
+        assert_eq!(clients(&unlocked), vec![1, 4]);
+        assert_eq!(clients(&locked), vec![2, 3]);
+        assert!(unlocked.iter().all(|row| !row.locked));
+        assert!(locked.iter().all(|row| row.locked && row.total.is_zero()));
+    }
+
+    #[test]
+    fn test_gross_totals() {
+        let mut engine = Engine::new();
+        let amount = |units| Decimal::new(units, 0);
+        let transactions = [
+            (1, 1, TransactionKind::Deposit { amount: amount(10) }),
+            (1, 2, TransactionKind::Deposit { amount: amount(5) }),
+            (1, 3, TransactionKind::Withdrawal { amount: amount(3) }),
+            // Rejected for insufficient funds.
+            (
+                1,
+                4,
+                TransactionKind::Withdrawal {
+                    amount: amount(100),
+                },
+            ),
+            (1, 1, TransactionKind::Dispute { amount: None }),
+            (1, 1, TransactionKind::Resolve { amount: None }),
+            (1, 2, TransactionKind::Dispute { amount: None }),
+            // Leaves the dispute open, so only the next resolve closes it.
+            (
+                1,
+                2,
+                TransactionKind::Resolve {
+                    amount: Some(amount(2)),
+                },
+            ),
+            (1, 2, TransactionKind::Resolve { amount: None }),
+            (2, 5, TransactionKind::Deposit { amount: amount(20) }),
+            (2, 6, TransactionKind::Withdrawal { amount: amount(5) }),
+            (2, 5, TransactionKind::Dispute { amount: None }),
+            (2, 5, TransactionKind::Chargeback),
+            // Ignored, as the transaction is unknown.
+            (2, 9, TransactionKind::Dispute { amount: None }),
+        ];
+        for (client, id, kind) in transactions {
+            let _ = engine.process_transaction(testkit::transaction(client, id, kind));
+        }
+
+        let first = GrossTotals {
+            deposited: amount(15),
+            withdrawn: amount(3),
+            deposits: 2,
+            withdrawals: 1,
+            disputes_opened: 2,
+            disputes_resolved: 2,
+            chargebacks: 0,
+        };
+        let second = GrossTotals {
+            deposited: amount(20),
+            withdrawn: amount(5),
+            deposits: 1,
+            withdrawals: 1,
+            disputes_opened: 1,
+            disputes_resolved: 0,
+            chargebacks: 1,
+        };
+        assert_eq!(engine.clients[&ClientId(1)].gross_totals(), first);
+        assert_eq!(engine.clients[&ClientId(2)].gross_totals(), second);
+        assert_eq!(
+            engine.gross_totals(),
+            GrossTotals {
+                deposited: amount(35),
+                withdrawn: amount(8),
+                deposits: 3,
+                withdrawals: 2,
+                disputes_opened: 3,
+                disputes_resolved: 2,
+                chargebacks: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_totals_overflow() {
+        let output = EngineOutput {
+            tenant: None,
+            client: ClientId(1),
+            currency: None,
+            available: Decimal::MAX,
+            held: Decimal::ZERO,
+            total: Decimal::MAX,
+            locked: false,
+        };
+        let totals = Totals::default().checked_add(&output).unwrap();
+        assert_eq!(totals.accounts, 1);
+        assert_eq!(totals.checked_add(&output), None);
+    }
+
+    #[test]
+    fn test_replay_client() {
+        let mut engine = Engine::with_config(EngineConfig {
+            allow_admin_ops: true,
+            ..EngineConfig::default()
+        });
+        let deposit = |amount| TransactionKind::Deposit {
+            amount: Decimal::new(amount, 0),
+        };
+        let transactions = [
+            (1, deposit(100)),
+            (2, deposit(50)),
+            (
+                3,
+                TransactionKind::Withdrawal {
+                    amount: Decimal::new(30, 0),
+                },
+            ),
+            (2, TransactionKind::Dispute { amount: None }),
+            (
+                2,
+                TransactionKind::Resolve {
+                    amount: Some(Decimal::new(20, 0)),
+                },
+            ),
+            (4, deposit(10)),
+            (4, TransactionKind::Dispute { amount: None }),
+            (4, TransactionKind::Chargeback),
+            (5, TransactionKind::Unlock),
+            (6, deposit(5)),
+            (6, TransactionKind::Refund),
+        ];
+        for (id, kind) in transactions {
+            engine
+                .process_transaction(testkit::transaction(1, id, kind))
+                .unwrap();
+        }
+
+        let mut live = engine.clients[&ClientId(1)].clone();
+        // The activity is recorded by the engine.
+        live.first_activity = None;
+        live.last_activity = None;
+        let replayed = engine.replay_client(ClientId(1)).unwrap();
+        assert_eq!(replayed, live);
+        assert_eq!(replayed.available, Decimal::new(90, 0));
+        assert_eq!(replayed.held, Decimal::new(30, 0));
+        assert_eq!(engine.replay_client(ClientId(2)), None);
+    }
+
+    #[test]
+    fn test_dangling_disputes() {
+        let mut engine = engine_from(
+            TxSeq::new()
+                .deposit(2, 1, "10")
+                .deposit(2, 2, "10")
+                .deposit(1, 3, "10")
+                .dispute(2, 1)
+                .dispute(2, 2)
+                .resolve(2, 2)
+                .dispute(1, 3),
+        );
+        assert!(engine.dangling_disputes().is_empty());
+
+        // Pruning the history of the open and of the resolved dispute of client 2.
+        let account = engine.clients.get_mut(&ClientId(2)).unwrap();
+        account.transactions.shift_remove(&TransactionId(2));
+        account.transactions.shift_remove(&TransactionId(1));
+        assert_eq!(
+            engine.dangling_disputes(),
+            vec![
+                (None, ClientId(2), TransactionId(1)),
+                (None, ClientId(2), TransactionId(2))
+            ]
+        );
+
+        // Disputes of the tenants are checked too.
+        let acme = Tenant("acme".to_string());
+        for transaction in TxSeq::new().deposit(2, 4, "5").dispute(2, 4) {
+            engine
+                .process_transaction(Transaction {
+                    tenant: Some(acme.clone()),
+                    ..transaction
+                })
+                .unwrap();
+        }
+        let tenant = engine.tenants.get_mut(&acme).unwrap();
+        let account = tenant.clients.get_mut(&ClientId(2)).unwrap();
+        account.transactions.shift_remove(&TransactionId(4));
+        assert_eq!(
+            engine.dangling_disputes().last(),
+            Some(&(Some(acme), ClientId(2), TransactionId(4)))
+        );
+    }
+
+    #[test]
+    fn test_deterministic_hashing() {
+        let config = EngineConfig {
+            deterministic_hashing: true,
+            ..EngineConfig::default()
+        };
+        let run = || {
+            let mut engine = Engine::with_config(config.clone());
+            for client in (1..=200).rev() {
+                engine
+                    .process_transaction(testkit::transaction(
+                        client,
+                        client.into(),
+                        TransactionKind::Deposit {
+                            amount: Decimal::new(client.into(), 1),
+                        },
+                    ))
+                    .unwrap();
+            }
+            format!("{:?}", engine.clients)
+        };
+
+        assert_eq!(run(), run());
+    }
+
+    mod create_on_any_transaction {
+        use super::*;
+
+        #[test]
+        fn test_first_withdrawal_has_insufficient_funds() {
+            let mut engine = Engine::with_config(EngineConfig {
+                create_on_any_transaction: true,
+                ..EngineConfig::default()
+            });
+
+            let result = engine.process_transaction(testkit::withdrawal(1, 1, "10"));
+
+            assert_eq!(
+                result,
+                Err(TransactionError::InsufficientFunds {
+                    requested: Decimal::new(10, 0),
+                    available: Decimal::ZERO,
+                })
+            );
+            let account = &engine.clients[&ClientId(1)];
+            assert_eq!(account.available, Decimal::ZERO);
+            assert_eq!(account.total_funds(), Decimal::ZERO);
+            assert!(account.transactions.is_empty());
+        }
+
+        #[test]
+        fn test_disabled_ignores_first_withdrawal() {
+            let mut engine = Engine::new();
+
+            let result = engine.process_transaction(testkit::withdrawal(1, 1, "10"));
+
+            assert_eq!(result, Ok(()));
+            assert!(engine.clients.is_empty());
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "cli")]
+    fn test_config_roundtrip() {
+        let fees = FeeSchedule {
+            flat: Decimal::new(25, 2),
+            percent: Decimal::ONE,
+            min: None,
+            max: Some(Decimal::new(20, 0)),
+        };
+        let config = EngineConfig {
+            deterministic_hashing: true,
+            max_open_disputes: Some(5),
+            on_duplicate_dispute: DuplicateDisputePolicy::Error,
+            dispute_window: Some(Duration::days(90)),
+            min_balance: Some(Decimal::new(1000, 2)),
+            day_utc_offset: FixedOffset::west_opt(5 * 3600),
+            withdrawal_fee: Some(fees),
+            deposit_fees: BTreeMap::from([(DEFAULT_TIER.to_string(), fees)]),
+            tier_limits: BTreeMap::from([(
+                "strict".to_string(),
+                TierLimits {
+                    max_deposit: Some(Decimal::new(500, 0)),
+                    ..TierLimits::default()
+                },
+            )]),
+            interest: Some(InterestPolicy {
+                daily_rate: Decimal::new(1, 4),
+            }),
+            ..EngineConfig::default()
+        };
+
+        let json = serde_json::to_string(&config).unwrap();
+        assert!(json.contains(r#""dispute_window":"90d""#), "{json}");
+        assert!(json.contains(r#""day_utc_offset":"-05:00""#), "{json}");
+        assert_eq!(serde_json::from_str::<EngineConfig>(&json).unwrap(), config);
+
+        // Unset windows and offsets are left out, as `null` is not a valid value for them.
+        let json = serde_json::to_string(&EngineConfig::default()).unwrap();
+        assert!(!json.contains("dispute_window"), "{json}");
+        assert_eq!(
+            serde_json::from_str::<EngineConfig>(&json).unwrap(),
+            EngineConfig::default()
+        );
+    }
+}
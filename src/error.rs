@@ -0,0 +1,212 @@
+use std::fmt;
+
+use rust_decimal::Decimal;
+
+use crate::transaction::{ClientId, DisputeState};
+
+/// Reason why a transaction was rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransactionError {
+    /// The client does not have enough available funds for a withdrawal.
+    InsufficientFunds {
+        /// Amount of the withdrawal, including its fee.
+        requested: Decimal,
+        available: Decimal,
+    },
+    /// The withdrawal, fee included, would take exactly the available funds, which it must
+    /// leave above zero unless it was clamped to them.
+    WholeBalanceWithdrawal { available: Decimal },
+    /// Holding the disputed funds would exceed the maximum held per account.
+    HeldLimitReached,
+    /// The transaction already has an open dispute, and duplicates are configured to be
+    /// rejected. Also the refund of a deposit or the reversal of a withdrawal that was disputed.
+    AlreadyDisputed,
+    /// The dispute is of a withdrawal, and withdrawal disputes are not allowed.
+    NotDisputable,
+    /// The amount of a partial dispute is more than the amount of the disputed transaction.
+    DisputeAmountTooLarge {
+        requested: Decimal,
+        /// Amount of the disputed transaction.
+        disputed: Decimal,
+    },
+    /// The dispute or refund is of a deposit that was refunded.
+    Refunded,
+    /// The dispute is of a transaction that was already charged back, whether or not the
+    /// chargeback was reversed.
+    AlreadyChargedBack,
+    /// The withdrawal reversal is of a transaction that is not a withdrawal of the client.
+    UnknownWithdrawal,
+    /// The withdrawal reversal came after the reversal window of the withdrawal had closed.
+    ReversalWindowExpired,
+    /// The withdrawal reversal came without a reversal window configured, which disables
+    /// reversals.
+    NoReversalWindow,
+    /// The withdrawal was already reversed.
+    AlreadyReversed,
+    /// The resolve or chargeback is of a dispute the engine already auto-resolved.
+    DisputeAutoResolved,
+    /// The chargeback reversal is of a transaction whose dispute is not charged back.
+    NotChargedBack,
+    /// The dispute cannot move from its current stage to the requested one, such as a
+    /// resolve in arbitration.
+    InvalidDisputeTransition {
+        from: DisputeState,
+        to: DisputeState,
+    },
+    /// The account already has the maximum number of open disputes.
+    TooManyOpenDisputes {
+        /// Tier of the client setting the maximum, if it is not the global one.
+        tier: Option<String>,
+    },
+    /// The dispute came after the dispute window of the deposit had closed.
+    DisputeWindowExpired,
+    /// The withdrawal would leave less available funds than the minimum balance.
+    MinimumBalance {
+        min: Decimal,
+        /// Available funds after the withdrawal.
+        would_be: Decimal,
+    },
+    /// The deposit is above the maximum amount of a single deposit.
+    DepositLimitExceeded {
+        limit: Decimal,
+        /// Tier of the client setting the limit, if it is not the global one.
+        tier: Option<String>,
+    },
+    /// The withdrawal is above the maximum amount of a single withdrawal.
+    WithdrawalLimitExceeded {
+        limit: Decimal,
+        /// Tier of the client setting the limit, if it is not the global one.
+        tier: Option<String>,
+    },
+    /// The withdrawal would take the funds withdrawn on its day over the daily limit.
+    DailyLimitExceeded {
+        /// Funds already withdrawn on the day of the withdrawal.
+        used: Decimal,
+        limit: Decimal,
+        /// Tier of the client setting the limit, if it is not the global one.
+        tier: Option<String>,
+    },
+    /// The dispute, resolve or chargeback is in another currency than the disputed deposit.
+    CurrencyMismatch,
+    /// The account is locked by a chargeback and cannot process transactions.
+    AccountLocked,
+    /// The account is frozen by an administrative operation and cannot process deposits
+    /// and withdrawals.
+    AccountFrozen,
+    /// The transaction is an administrative operation, and they are not allowed.
+    AdminOpsDisabled,
+    /// The administrative operation reuses the id of a transaction in the history of the
+    /// account, which it would replace.
+    TxIdInUse,
+    /// The deposit or withdrawal reuses the id of a transaction of another client, while
+    /// ids are configured to be globally unique.
+    TxIdReused {
+        /// Client that used the id first.
+        owner: ClientId,
+    },
+    /// The dispute, resolve or chargeback references a transaction of the same client in
+    /// another tenant.
+    CrossTenantDispute,
+    /// A policy depending on time is configured, but the transaction, or the deposit it
+    /// disputes, has no timestamp.
+    MissingTimestamp,
+}
+
+impl fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransactionError::InsufficientFunds {
+                requested,
+                available,
+            } => write!(
+                f,
+                "insufficient available funds, requested {requested} with {available} available"
+            ),
+            TransactionError::WholeBalanceWithdrawal { available } => write!(
+                f,
+                "withdrawal would leave no available funds, withdraw less than {available}"
+            ),
+            TransactionError::HeldLimitReached => write!(f, "held funds limit reached"),
+            TransactionError::AlreadyDisputed => write!(f, "transaction is already disputed"),
+            TransactionError::NotDisputable => write!(f, "transaction cannot be disputed"),
+            TransactionError::DisputeAmountTooLarge {
+                requested,
+                disputed,
+            } => write!(
+                f,
+                "dispute amount {requested} exceeds the disputed amount of {disputed}"
+            ),
+            TransactionError::Refunded => write!(f, "deposit was refunded"),
+            TransactionError::AlreadyChargedBack => {
+                write!(f, "transaction was already charged back")
+            }
+            TransactionError::UnknownWithdrawal => write!(f, "no such withdrawal"),
+            TransactionError::ReversalWindowExpired => {
+                write!(f, "withdrawal reversal window expired")
+            }
+            TransactionError::NoReversalWindow => {
+                write!(f, "withdrawal reversals require a reversal window")
+            }
+            TransactionError::AlreadyReversed => write!(f, "withdrawal was already reversed"),
+            TransactionError::DisputeAutoResolved => {
+                write!(f, "dispute was already auto-resolved")
+            }
+            TransactionError::NotChargedBack => write!(f, "transaction is not charged back"),
+            TransactionError::InvalidDisputeTransition { from, to } => {
+                write!(f, "dispute cannot go from {from} to {to}")
+            }
+            TransactionError::TooManyOpenDisputes { tier } => {
+                write!(f, "too many open disputes")?;
+                write_tier(f, tier.as_deref())
+            }
+            TransactionError::DisputeWindowExpired => write!(f, "dispute window expired"),
+            TransactionError::MinimumBalance { min, would_be } => write!(
+                f,
+                "withdrawal would leave {would_be} available, below the minimum balance of {min}"
+            ),
+            TransactionError::DepositLimitExceeded { limit, tier } => {
+                write!(f, "deposit above the limit of {limit}")?;
+                write_tier(f, tier.as_deref())
+            }
+            TransactionError::WithdrawalLimitExceeded { limit, tier } => {
+                write!(f, "withdrawal above the limit of {limit}")?;
+                write_tier(f, tier.as_deref())
+            }
+            TransactionError::DailyLimitExceeded { used, limit, tier } => {
+                write!(
+                    f,
+                    "daily withdrawal limit exceeded, {used} of {limit} already withdrawn"
+                )?;
+                write_tier(f, tier.as_deref())
+            }
+            TransactionError::CurrencyMismatch => {
+                write!(f, "currency does not match the disputed deposit")
+            }
+            TransactionError::AccountLocked => write!(f, "account is locked"),
+            TransactionError::AccountFrozen => write!(f, "account is frozen"),
+            TransactionError::TxIdReused { owner } => {
+                write!(f, "tx id already used by client {}", owner.0)
+            }
+            TransactionError::AdminOpsDisabled => {
+                write!(f, "administrative operations are not allowed")
+            }
+            TransactionError::TxIdInUse => {
+                write!(f, "tx id already used by a transaction of the account")
+            }
+            TransactionError::CrossTenantDispute => {
+                write!(f, "disputed transaction belongs to another tenant")
+            }
+            TransactionError::MissingTimestamp => write!(f, "missing timestamp"),
+        }
+    }
+}
+
+impl std::error::Error for TransactionError {}
+
+/// Names the tier whose limit rejected a transaction, if it is not a global limit.
+fn write_tier(f: &mut fmt::Formatter<'_>, tier: Option<&str>) -> fmt::Result {
+    match tier {
+        Some(tier) => write!(f, " of tier {tier:?}"),
+        None => Ok(()),
+    }
+}
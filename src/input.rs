@@ -0,0 +1,266 @@
+#[cfg(feature = "cli")]
+use std::io;
+use std::{fs::File, io::Read, path::Path};
+
+#[cfg(feature = "cli")]
+use memmap2::Mmap;
+
+use crate::transaction::Transaction;
+
+/// Columns of a transaction record, for input that comes without a header.
+#[cfg(feature = "cli")]
+const TRANSACTION_HEADER: [&str; 4] = ["type", "client", "tx", "amount"];
+
+/// CSV reader configuration shared by every input, trimming whitespace around fields.
+/// Records can have fewer fields than the header, so blank rows can be told apart from
+/// malformed ones.
+fn builder() -> csv::ReaderBuilder {
+    let mut builder = csv::ReaderBuilder::new();
+    builder.trim(csv::Trim::All).flexible(true);
+    builder
+}
+
+/// Wraps `rdr` in a CSV reader.
+pub fn reader<R: Read>(rdr: R) -> csv::Reader<R> {
+    builder().from_reader(rdr)
+}
+
+/// Opens a CSV file.
+pub fn reader_from_path<P: AsRef<Path>>(path: P) -> csv::Result<csv::Reader<File>> {
+    builder().from_path(path)
+}
+
+/// File read by a CSV reader, either with buffered reads or mapped in memory.
+#[cfg(feature = "cli")]
+pub enum FileSource {
+    Buffered(File),
+    Mapped(io::Cursor<Mmap>),
+}
+
+#[cfg(feature = "cli")]
+impl Read for FileSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            FileSource::Buffered(file) => file.read(buf),
+            FileSource::Mapped(map) => map.read(buf),
+        }
+    }
+}
+
+/// Opens a CSV file, mapping it in memory with `mmap` to save the read system calls on
+/// large files. Files that cannot be mapped, such as pipes, are read as usual.
+#[cfg(feature = "cli")]
+pub fn reader_from_path_mmap<P: AsRef<Path>>(
+    path: P,
+    mmap: bool,
+) -> csv::Result<csv::Reader<FileSource>> {
+    let file = File::open(path)?;
+    let source = if mmap {
+        // SAFETY: the map is only read, and input files are not expected to change during a
+        // run. Truncating one while it is mapped would crash the process with SIGBUS.
+        match unsafe { Mmap::map(&file) } {
+            Ok(map) => FileSource::Mapped(io::Cursor::new(map)),
+            Err(_) => FileSource::Buffered(file),
+        }
+    } else {
+        FileSource::Buffered(file)
+    };
+    Ok(builder().from_reader(source))
+}
+
+/// Reader for records that come without header, and which can omit trailing fields.
+pub fn headerless_reader<R: Read>(rdr: R) -> csv::Reader<R> {
+    builder().has_headers(false).from_reader(rdr)
+}
+
+/// Deserializes the transactions of `rdr`, skipping blank rows, whose fields are all
+/// empty once trimmed, such as a trailing blank line.
+pub fn transactions<R: Read>(
+    rdr: csv::Reader<R>,
+) -> csv::Result<impl Iterator<Item = csv::Result<Transaction>>> {
+    Ok(transactions_with_lines(rdr)?.map(|result| result.map(|(_, transaction)| transaction)))
+}
+
+/// Like [`transactions`], along with the line every transaction starts at.
+pub fn transactions_with_lines<R: Read>(
+    mut rdr: csv::Reader<R>,
+) -> csv::Result<impl Iterator<Item = csv::Result<(u64, Transaction)>>> {
+    let header = rdr.headers()?.clone();
+    Ok(rdr.into_records().filter_map(move |record| match record {
+        Ok(record) if is_blank(&record) => None,
+        Ok(record) => {
+            let line = record.position().map_or(0, |position| position.line());
+            Some(deserialize_record(record, &header).map(|transaction| (line, transaction)))
+        }
+        Err(err) => Some(Err(err)),
+    }))
+}
+
+/// If every field of the record is empty once trimmed.
+pub fn is_blank(record: &csv::StringRecord) -> bool {
+    record.iter().all(str::is_empty)
+}
+
+/// Deserializes a transaction from a record read without header. Records of the dispute
+/// family can omit the trailing amount.
+pub fn deserialize_record(
+    mut record: csv::StringRecord,
+    header: &csv::StringRecord,
+) -> csv::Result<Transaction> {
+    while record.len() < header.len() {
+        record.push_field("");
+    }
+    record.deserialize(Some(header))
+}
+
+/// Parses a single transaction, either as a JSON object or as a CSV record without header,
+/// such as `deposit, 1, 1, 5.0`.
+#[cfg(feature = "cli")]
+pub fn parse_line(line: &str) -> anyhow::Result<Transaction> {
+    let line = line.trim();
+    if line.starts_with('{') {
+        return Ok(serde_json::from_str(line)?);
+    }
+
+    let record = headerless_reader(line.as_bytes())
+        .records()
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("empty transaction"))??;
+    let header = csv::StringRecord::from(&TRANSACTION_HEADER[..]);
+    Ok(deserialize_record(record, &header)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+    use rust_decimal::Decimal;
+
+    use crate::transaction::TransactionKind;
+
+    use super::*;
+
+    #[cfg(feature = "cli")]
+    fn parse_error(line: &str) -> String {
+        parse_line(line).unwrap_err().to_string()
+    }
+
+    #[test]
+    #[cfg(feature = "cli")]
+    fn test_mismatched_amounts() {
+        assert!(parse_error("deposit, 1, 1,").ends_with("deposit requires an amount"));
+        assert!(parse_error("withdrawal, 1, 2").ends_with("withdrawal requires an amount"));
+        // Like resolves, disputes may have an amount.
+        assert_eq!(
+            parse_line("dispute, 1, 1, 5.0").unwrap().kind,
+            TransactionKind::Dispute {
+                amount: Some(Decimal::new(50, 1))
+            }
+        );
+        assert!(
+            parse_error("chargeback, 1, 1, 5.0").ends_with("chargeback must not have an amount")
+        );
+        assert!(
+            parse_error(r#"{"type": "deposit", "client": 1, "tx": 1}"#)
+                .starts_with("deposit requires an amount")
+        );
+
+        let mut rdr = reader("type,client,tx\ndeposit,1,1\n".as_bytes());
+        let err = rdr
+            .deserialize::<Transaction>()
+            .next()
+            .unwrap()
+            .unwrap_err();
+        assert!(err.to_string().ends_with("deposit requires an amount"));
+    }
+
+    #[test]
+    fn test_blank_rows() {
+        let input = "type, client, tx, amount\n\
+                     deposit, 1, 1, 5.0\n\
+                     \x20 \t \n\
+                     deposit, 1, 2, 2.5\n\
+                     \n";
+        let transactions: Vec<Transaction> = transactions(reader(input.as_bytes()))
+            .unwrap()
+            .collect::<csv::Result<_>>()
+            .unwrap();
+        assert_eq!(transactions.len(), 2);
+        assert_eq!(
+            transactions[1].kind,
+            TransactionKind::Deposit {
+                amount: Decimal::new(25, 1)
+            }
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "cli")]
+    fn test_matching_amounts() {
+        let resolve = parse_line("resolve, 1, 1, 2.5").unwrap();
+        assert_eq!(
+            resolve.kind,
+            TransactionKind::Resolve {
+                amount: Some(Decimal::new(25, 1))
+            }
+        );
+        assert_eq!(
+            parse_line("resolve, 1, 1").unwrap().kind,
+            TransactionKind::Resolve { amount: None }
+        );
+        assert_eq!(
+            parse_line("dispute, 1, 1,").unwrap().kind,
+            TransactionKind::Dispute { amount: None }
+        );
+    }
+
+    #[test]
+    fn test_timestamps() {
+        let input = "type, client, tx, amount, timestamp\n\
+                     deposit, 1, 1, 5.0, 2024-06-01T12:00:00+02:00\n\
+                     deposit, 1, 2, 5.0, 1717236000\n\
+                     deposit, 1, 3, 5.0,\n";
+        let timestamps: Vec<_> = transactions(reader(input.as_bytes()))
+            .unwrap()
+            .map(|transaction| transaction.unwrap().timestamp)
+            .collect();
+        let expected = DateTime::parse_from_rfc3339("2024-06-01T10:00:00Z")
+            .unwrap()
+            .to_utc();
+        assert_eq!(timestamps, vec![Some(expected), Some(expected), None]);
+
+        let input = "type, client, tx, amount, timestamp\ndeposit, 1, 1, 5.0, yesterday\n";
+        let err = transactions(reader(input.as_bytes()))
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("invalid timestamp \"yesterday\""),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_ids_out_of_range() {
+        let parse_error = |input: &str| {
+            let input = format!("type, client, tx, amount\n{input}\n");
+            transactions(reader(input.as_bytes()))
+                .unwrap()
+                .next()
+                .unwrap()
+                .unwrap_err()
+                .to_string()
+        };
+
+        let err = parse_error("deposit, 70000, 1, 5.0");
+        assert!(
+            err.ends_with("client id out of range (max 65535): 70000"),
+            "{err}"
+        );
+        let err = parse_error("deposit, 1, 5000000000, 5.0");
+        assert!(
+            err.ends_with("tx id out of range (max 4294967295): 5000000000"),
+            "{err}"
+        );
+    }
+}
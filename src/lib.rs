@@ -0,0 +1,42 @@
+//! Payments engine processing deposits, withdrawals and disputes into client accounts.
+//!
+//! The core, [`transaction`], [`account`] and [`engine`], only depends on `rust_decimal`,
+//! `chrono` and `indexmap`. The `serde` feature derives `Serialize` and `Deserialize` for
+//! its types, the `csv` feature adds the readers and writers of [`input`] and [`output`],
+//! and the `cli` feature, enabled by default, adds the modules of the `payments` binary.
+//! The `testutil` feature exposes the transaction generators of the `testutil` module to
+//! benchmarks, and the `testkit` feature the fixture builders and the [`assert_balances!`]
+//! macro of the `testkit` module to tests.
+
+pub mod account;
+#[cfg(feature = "cli")]
+pub mod config;
+#[cfg(all(unix, feature = "cli"))]
+pub mod daemon;
+pub mod engine;
+pub mod error;
+#[cfg(feature = "csv")]
+pub mod input;
+#[cfg(feature = "cli")]
+pub mod logging;
+#[cfg(feature = "csv")]
+pub mod output;
+#[cfg(feature = "postgres")]
+pub mod pg;
+#[cfg(feature = "cli")]
+pub mod repl;
+#[cfg(feature = "csv")]
+pub mod risk;
+#[cfg(feature = "cli")]
+pub mod shard;
+#[cfg(feature = "cli")]
+pub mod tcp;
+#[cfg(any(test, feature = "testkit"))]
+pub mod testkit;
+#[cfg(any(test, feature = "testutil"))]
+pub mod testutil;
+pub mod transaction;
+#[cfg(feature = "cli")]
+pub mod verify;
+#[cfg(feature = "cli")]
+pub mod watch;
@@ -0,0 +1,239 @@
+use std::io::IsTerminal;
+
+use clap::ValueEnum;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use tracing_subscriber::EnvFilter;
+
+use crate::{engine::Engine, output::format_decimal};
+
+/// Target of the events of [`log_summary`], enabled by [`init`] whatever the filter.
+const SUMMARY_TARGET: &str = "payments::summary";
+
+/// Format of the log lines written to stderr.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human readable lines.
+    #[default]
+    Text,
+    /// One JSON object per line, with the event fields at the top level.
+    Json,
+}
+
+/// Installs the global subscriber writing logs to stderr. `level` is a filter directive
+/// such as `info`, taking precedence over `RUST_LOG`. Without either, only warnings and
+/// errors are logged. With `summary`, the events of [`log_summary`] are logged as well.
+pub fn init(format: LogFormat, level: Option<&str>, summary: bool) -> anyhow::Result<()> {
+    let mut filter = match level {
+        Some(level) => EnvFilter::try_new(level)?,
+        None => EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("warn")),
+    };
+    if summary {
+        filter = filter.add_directive(format!("{SUMMARY_TARGET}=info").parse()?);
+    }
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::stderr().is_terminal());
+
+    match format {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Json => builder.json().flatten_event(true).try_init(),
+    }
+    .map_err(|err| anyhow::anyhow!(err))
+}
+
+/// Logs the error a run failed with, with the input line if it is a parse error.
+pub fn log_failure(err: &anyhow::Error) {
+    let line = err
+        .downcast_ref::<csv::Error>()
+        .and_then(csv::Error::position)
+        .map(csv::Position::line);
+    tracing::error!(line, reason = %format!("{err:#}"), "run failed");
+}
+
+/// Logs the final state of every account, one event per client, ordered by client, then a
+/// `run summary` event with the number of accounts, of those with a negative balance, and
+/// the sum of their [`Engine::gross_totals`].
+pub fn log_summary(engine: &Engine) {
+    let outputs = engine.output();
+    let negative_accounts = outputs.iter().filter(|output| output.is_negative()).count();
+    for output in &outputs {
+        let account = engine
+            .tenant_engine(output.tenant.as_ref())
+            .and_then(|owner| owner.clients.get(&output.client));
+        let disputes = account.map_or(0, |account| account.disputes.len());
+        let locked_rejections = account.map_or(0, |account| account.locked_rejections);
+        let locked_rejected_amount =
+            account.map_or(Decimal::ZERO, |account| account.locked_rejected_amount);
+        tracing::info!(
+            target: SUMMARY_TARGET,
+            tenant = output.tenant.as_ref().map(|tenant| tenant.0.as_str()),
+            client = output.client.0,
+            currency = output.currency.as_ref().map(|currency| currency.0.as_str()),
+            available = %format_decimal(output.available),
+            held = %format_decimal(output.held),
+            locked = output.locked,
+            disputes,
+            locked_rejections,
+            locked_rejected_amount = %format_decimal(locked_rejected_amount),
+            "account summary"
+        );
+    }
+    let gross = engine.gross_totals();
+    tracing::info!(
+        target: SUMMARY_TARGET,
+        accounts = outputs.len(),
+        negative_accounts,
+        deposited = %format_decimal(gross.deposited),
+        withdrawn = %format_decimal(gross.withdrawn),
+        deposits = gross.deposits,
+        withdrawals = gross.withdrawals,
+        disputes_opened = gross.disputes_opened,
+        disputes_resolved = gross.disputes_resolved,
+        chargebacks = gross.chargebacks,
+        "run summary"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io,
+        sync::{Arc, Mutex},
+    };
+
+    use rust_decimal::Decimal;
+    use tracing_subscriber::fmt::MakeWriter;
+
+    use crate::{
+        engine::EngineConfig,
+        testkit::{self, TxSeq},
+        transaction::TransactionKind,
+    };
+
+    use super::*;
+
+    /// Writer appending the log lines to a shared buffer.
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for Capture {
+        type Writer = Self;
+
+        fn make_writer(&'a self) -> Self {
+            self.clone()
+        }
+    }
+
+    #[test]
+    fn test_log_summary() {
+        let mut engine = Engine::new();
+        let transactions = [
+            (
+                TransactionKind::Deposit {
+                    amount: Decimal::new(105, 1),
+                },
+                2,
+                1,
+            ),
+            (
+                TransactionKind::Deposit {
+                    amount: Decimal::new(3, 0),
+                },
+                1,
+                2,
+            ),
+            (TransactionKind::Dispute { amount: None }, 2, 1),
+        ];
+        for (kind, client, id) in transactions {
+            engine
+                .process_transaction(testkit::transaction(client, id, kind))
+                .unwrap();
+        }
+
+        let capture = Capture::default();
+        let subscriber = tracing_subscriber::fmt()
+            .json()
+            .flatten_event(true)
+            .with_writer(capture.clone())
+            .finish();
+        tracing::subscriber::with_default(subscriber, || log_summary(&engine));
+
+        let logs = String::from_utf8(capture.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<serde_json::Value> = logs
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["client"], 1);
+        assert_eq!(lines[0]["available"], "3.0000");
+        assert_eq!(lines[0]["disputes"], 0);
+        assert_eq!(lines[1]["client"], 2);
+        assert_eq!(lines[1]["available"], "0.0000");
+        assert_eq!(lines[1]["held"], "10.5000");
+        assert_eq!(lines[1]["locked"], false);
+        assert_eq!(lines[1]["disputes"], 1);
+        assert_eq!(lines[1]["message"], "account summary");
+        assert_eq!(lines[2]["message"], "run summary");
+        assert_eq!(lines[2]["accounts"], 2);
+        assert_eq!(lines[2]["negative_accounts"], 0);
+        assert_eq!(lines[2]["deposited"], "13.5000");
+        assert_eq!(lines[2]["deposits"], 2);
+        assert_eq!(lines[2]["disputes_opened"], 1);
+    }
+
+    #[test]
+    fn test_deterministic_debug_logs() {
+        let config = EngineConfig {
+            deterministic_hashing: true,
+            ..EngineConfig::default()
+        };
+        let run = || {
+            let mut seq = TxSeq::new();
+            for tx in 1..=64 {
+                seq = seq.deposit(tx % 4 + 1, tx.into(), "1.0");
+            }
+            for tx in (1..=64).step_by(3) {
+                seq = seq.dispute(tx % 4 + 1, tx.into());
+            }
+            for tx in (1..=64).step_by(6) {
+                seq = seq.resolve(tx % 4 + 1, tx.into());
+            }
+
+            let capture = Capture::default();
+            let subscriber = tracing_subscriber::fmt()
+                .json()
+                .flatten_event(true)
+                .without_time()
+                .with_max_level(tracing::Level::DEBUG)
+                .with_writer(capture.clone())
+                .finish();
+            tracing::subscriber::with_default(subscriber, || {
+                let mut engine = Engine::with_config(config.clone());
+                for transaction in seq {
+                    engine.process_transaction(transaction).unwrap();
+                }
+                tracing::debug!(clients = ?engine.clients, "engine state");
+                log_summary(&engine);
+            });
+            String::from_utf8(capture.0.lock().unwrap().clone()).unwrap()
+        };
+
+        // The debug log has the disputes and risk counters of every account.
+        let logs = run();
+        assert!(logs.contains("disputes_per_tx"), "{logs}");
+        assert_eq!(logs, run());
+    }
+}
@@ -1,73 +1,438 @@
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::Write,
+    net::{SocketAddr, TcpListener},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    thread,
+    time::Duration,
+};
 
-use crate::{
-    account::Account,
-    transaction::{ClientId, Transaction, TransactionKind},
+use anyhow::{Context, bail};
+use clap::{Parser, Subcommand};
+#[cfg(unix)]
+use payments::daemon;
+#[cfg(feature = "postgres")]
+use payments::pg;
+use payments::{
+    config::{Config, ConfigFlags},
+    engine::{Engine, EngineConfig},
+    input::{self, reader_from_path, reader_from_path_mmap},
+    logging,
+    output::{Rates, write_dispute_report, write_negative_report, write_report},
+    repl,
+    risk::write_risk_report,
+    shard, tcp,
+    transaction::Transaction,
+    verify, watch,
 };
-use rust_decimal::Decimal;
+use rustyline::error::ReadlineError;
+use sha2::{Digest, Sha256};
+
+/// Processes transactions and outputs the state of every client account as CSV.
+#[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Transaction files, processed as if they were concatenated in order.
+    #[arg(required_unless_present = "dump_config")]
+    files: Vec<PathBuf>,
+    /// Balances snapshot seeding the accounts before the files are processed.
+    #[arg(long)]
+    seed: Option<PathBuf>,
+    /// CSV of `client,tier` rows assigning the clients to the deposit fee tiers of the
+    /// config.
+    #[arg(long)]
+    tiers: Option<PathBuf>,
+    /// Maps the transaction files in memory instead of reading them, which is faster for
+    /// very large files.
+    #[arg(long)]
+    mmap: bool,
+    /// Also writes the accounts with risky dispute and chargeback patterns to this file, as
+    /// CSV. The thresholds are in the `[risk]` section of the config.
+    #[arg(long)]
+    risk_report: Option<PathBuf>,
+    /// Also writes every dispute with its state and the stages it went through to this
+    /// file, as CSV.
+    #[arg(long)]
+    dispute_report: Option<PathBuf>,
+    /// Also writes the accounts with a negative available or total balance, and the amount
+    /// each client would have to pay back, to this file, as CSV.
+    #[arg(long)]
+    negative_report: Option<PathBuf>,
+    /// Also prints the SHA-256 of the report written to stdout to stderr, as a `sha256`
+    /// line followed by the hex digest.
+    #[arg(long)]
+    checksum: bool,
+    /// Prints the engine settings resolved from the config file, the environment and the
+    /// flags as JSON, with the client tiers of `--tiers`, then exits without processing
+    /// anything.
+    #[arg(long)]
+    dump_config: bool,
+    /// TOML config file. `PAYMENTS_<SECTION>_<KEY>` variables override its values, and
+    /// flags override both.
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    #[command(flatten)]
+    flags: ConfigFlags,
+    /// Upserts the final balances into the PostgreSQL database at this connection string.
+    #[cfg(feature = "postgres")]
+    #[arg(long, global = true)]
+    pg_dsn: Option<String>,
+    /// Table the final balances are upserted into.
+    #[cfg(feature = "postgres")]
+    #[arg(long, global = true, default_value = "balances")]
+    pg_table: String,
+    /// Identifier stored with every balance row, the current time by default.
+    #[cfg(feature = "postgres")]
+    #[arg(long, global = true)]
+    pg_run_id: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Accepts transactions over TCP, one per line, until a client sends `SHUTDOWN`.
+    Listen {
+        /// Address to listen on.
+        #[arg(long)]
+        tcp: SocketAddr,
+        /// Answers every line with whether its transaction was accepted.
+        #[arg(long)]
+        ack: bool,
+    },
+    /// Answers newline-delimited JSON commands on a Unix socket until asked to shut down.
+    #[cfg(unix)]
+    Daemon {
+        /// Path of the Unix socket.
+        #[arg(long)]
+        socket: PathBuf,
+        /// Balances snapshot loaded at startup, if it exists, and written on shutdown.
+        #[arg(long)]
+        snapshot: Option<PathBuf>,
+    },
+    /// Manages config files.
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// Reads transactions and commands typed one at a time, to inspect the engine state.
+    Repl,
+    /// Processes a file, then processes a dump of the transactions read from it and checks
+    /// that both runs produce the same report.
+    Verify { file: PathBuf },
+    /// Follows a growing transactions file, processing what is appended to it.
+    Watch {
+        file: PathBuf,
+        /// How often the file is checked for new transactions.
+        #[arg(long, default_value = "2s", value_parser = humantime::parse_duration)]
+        interval: Duration,
+        /// File replaced with the current report whenever new transactions are processed.
+        #[arg(long)]
+        output: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Checks that a config file is valid, without applying the environment.
+    Check { file: PathBuf },
+}
 
-pub mod account;
-pub mod transaction;
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let config =
+        Config::load(cli.config.as_deref(), std::env::vars(), &cli.flags).and_then(|config| {
+            logging::init(
+                config.log.format,
+                config.log.level.as_deref(),
+                config.log.summary,
+            )?;
+            Ok(config)
+        });
+    let config = match config {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("Error: {err:#}");
+            return ExitCode::FAILURE;
+        }
+    };
 
-fn format_decimal(value: Decimal) -> String {
-    format!("{:.4}", value)
+    match run(cli, config) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            logging::log_failure(&err);
+            ExitCode::FAILURE
+        }
+    }
 }
 
-fn main() -> std::io::Result<()> {
-    let file = std::env::args()
-        .nth(1)
-        .expect("Missing the filename as the first argument");
+fn run(cli: Cli, config: Config) -> anyhow::Result<()> {
+    // Before the report options, which read the rates file.
+    if cli.dump_config {
+        return dump_config(config.engine, cli.tiers.as_deref());
+    }
+    let report_options = config.report_options()?;
+    let log_summary = config.log.summary;
+    let risk_thresholds = config.risk;
+    let config = config.engine;
 
-    let mut reader = csv::ReaderBuilder::new()
-        .trim(csv::Trim::All)
-        .from_path(&file)?;
+    let engine = match cli.command {
+        Some(Command::Listen { tcp, ack }) => {
+            tcp::serve(TcpListener::bind(tcp)?, Engine::with_config(config), ack)?
+        }
+        #[cfg(unix)]
+        Some(Command::Daemon { socket, snapshot }) => {
+            let mut daemon = daemon::Daemon::new(Engine::with_config(config), snapshot)?;
+            daemon.run(std::os::unix::net::UnixListener::bind(socket)?)?;
+            return Ok(());
+        }
+        Some(Command::Watch {
+            file,
+            interval,
+            output,
+        }) => {
+            let watcher = watch::Watcher::new(file, Engine::with_config(config));
+            return watch::run(watcher, interval, &output, &report_options);
+        }
+        Some(Command::Config(ConfigCommand::Check { file })) => {
+            Config::load(Some(&file), [], &ConfigFlags::default())?;
+            println!("{} is valid", file.display());
+            return Ok(());
+        }
+        Some(Command::Repl) => return run_repl(repl::Repl::new(config)),
+        Some(Command::Verify { file }) => {
+            let verified = verify::verify(&file, &config)?;
+            println!("verified {verified} transactions of {}", file.display());
+            return Ok(());
+        }
+        None => process_files(
+            &cli.files,
+            config,
+            cli.seed.as_deref(),
+            cli.tiers.as_deref(),
+            report_options.rates.as_ref(),
+            cli.mmap,
+        )?,
+    };
 
-    let mut clients: HashMap<ClientId, Account> = HashMap::new();
+    if log_summary {
+        logging::log_summary(&engine);
+    }
+    if cli.checksum {
+        let mut report = Vec::new();
+        write_report(&engine, &mut report, &report_options)?;
+        std::io::stdout().write_all(&report)?;
+        let digest: String = Sha256::digest(&report)
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        eprintln!("sha256 {digest}");
+    } else {
+        write_report(&engine, std::io::stdout(), &report_options)?;
+    }
+    if let Some(path) = &cli.risk_report {
+        let file =
+            File::create(path).with_context(|| format!("failed to create {}", path.display()))?;
+        write_risk_report(&engine, &risk_thresholds, file)?;
+    }
+    if let Some(path) = &cli.dispute_report {
+        let file =
+            File::create(path).with_context(|| format!("failed to create {}", path.display()))?;
+        write_dispute_report(&engine, file)?;
+    }
+    if let Some(path) = &cli.negative_report {
+        let file =
+            File::create(path).with_context(|| format!("failed to create {}", path.display()))?;
+        write_negative_report(&engine, file)?;
+    }
 
-    for transaction in reader.deserialize() {
-        let transaction: Transaction = transaction?;
+    #[cfg(feature = "postgres")]
+    if let Some(dsn) = &cli.pg_dsn {
+        let run_id = cli
+            .pg_run_id
+            .clone()
+            .unwrap_or_else(|| chrono::Utc::now().to_rfc3339());
+        let mut sink = pg::PgSink::connect(dsn, &cli.pg_table)?;
+        pg::export(&engine, &run_id, &mut sink)?;
+    }
 
-        let client_id = transaction.client;
+    Ok(())
+}
 
-        if let Some(client) = clients.get_mut(&client_id) {
-            client.process_transaction(transaction);
-        } else {
-            if let TransactionKind::Deposit { amount } = transaction.kind {
-                let new_account = Account::new(amount);
-                clients.insert(client_id, new_account);
+/// Processes `files` on top of the `seed` balances, with the client `tiers`. Fails if a file
+/// is given twice. With `rates`, fails on the first transaction in a currency without a
+/// rate. With `mmap`, the files are mapped in memory.
+fn process_files(
+    files: &[PathBuf],
+    config: EngineConfig,
+    seed: Option<&Path>,
+    tiers: Option<&Path>,
+    rates: Option<&Rates>,
+    mmap: bool,
+) -> anyhow::Result<Engine> {
+    check_distinct_files(files)?;
+    if config.dispute_window.is_some() {
+        check_timestamp_columns(files, "engine.dispute_window")?;
+    }
+    if config.daily_withdrawal_limit.is_some()
+        || config
+            .tier_limits
+            .values()
+            .any(|limits| limits.daily_withdrawal_limit.is_some())
+    {
+        check_timestamp_columns(files, "engine.daily_withdrawal_limit")?;
+    }
+    if config.interest.is_some() {
+        check_timestamp_columns(files, "engine.interest")?;
+    }
+    let mut engine = Engine::with_config(config);
+    if let Some(seed) = seed {
+        File::open(seed)
+            .map_err(csv::Error::from)
+            .and_then(|file| engine.load_balances(file))
+            .with_context(|| format!("failed to seed balances from {}", seed.display()))?;
+    }
+    if let Some(tiers) = tiers {
+        load_tiers(&mut engine, tiers)?;
+    }
+
+    if let [file] = files {
+        let transactions = input::transactions_with_lines(reader_from_path_mmap(file, mmap)?)?;
+        for transaction in transactions {
+            let (line, transaction) =
+                transaction.with_context(|| format!("invalid input in {}", file.display()))?;
+            if let Some(rates) = rates {
+                check_rate(rates, &transaction, file, line)?;
+            }
+            let (client, tx) = (transaction.client, transaction.id);
+            // Rejected transactions are only logged.
+            if let Err(reason) = engine.process_transaction(transaction) {
+                tracing::info!(
+                    client = client.0,
+                    tx = tx.0,
+                    %reason,
+                    file = %file.display(),
+                    line,
+                    "transaction rejected"
+                );
+            }
+        }
+    } else {
+        if let Some(rates) = rates {
+            for file in files {
+                for transaction in input::transactions_with_lines(reader_from_path(file)?)? {
+                    let (line, transaction) = transaction
+                        .with_context(|| format!("invalid input in {}", file.display()))?;
+                    check_rate(rates, &transaction, file, line)?;
+                }
             }
         }
+        let shards = thread::available_parallelism().map_or(1, |n| n.get());
+        engine = shard::process_files(files, shards, engine, mmap)?;
     }
+    engine.accrue_final_interest();
+    Ok(engine)
+}
 
-    let mut wtr = csv::Writer::from_writer(std::io::stdout());
-    wtr.write_record(&["client", "available", "held", "total", "locked"])?;
+/// Assigns the client tiers of the `tiers` file, see [`Engine::load_tiers`].
+fn load_tiers(engine: &mut Engine, tiers: &Path) -> anyhow::Result<()> {
+    File::open(tiers)
+        .map_err(csv::Error::from)
+        .and_then(|file| engine.load_tiers(file))
+        .with_context(|| format!("failed to read tiers from {}", tiers.display()))
+}
 
-    for (client_id, account) in clients {
-        wtr.write_record(&[
-            client_id.0.to_string(),
-            format_decimal(account.available),
-            format_decimal(account.held),
-            format_decimal(account.total_funds()),
-            account.locked.to_string(),
-        ])?;
+/// Prints the engine settings as JSON, along with the client tiers of the `tiers` file, if
+/// any, as a `client_tiers` object ordered by client.
+fn dump_config(config: EngineConfig, tiers: Option<&Path>) -> anyhow::Result<()> {
+    let mut engine = Engine::with_config(config);
+    if let Some(tiers) = tiers {
+        load_tiers(&mut engine, tiers)?;
     }
+    let mut dump = serde_json::to_value(&engine.config)?;
+    let client_tiers: BTreeMap<u16, &String> = engine
+        .config
+        .client_tiers
+        .iter()
+        .map(|(client, tier)| (client.0, tier))
+        .collect();
+    dump["client_tiers"] = serde_json::to_value(client_tiers)?;
 
+    let mut stdout = std::io::stdout();
+    serde_json::to_writer_pretty(&mut stdout, &dump)?;
+    writeln!(stdout)?;
     Ok(())
 }
 
-pub fn process_transactions(inputs: Vec<Transaction>) {
-    let mut clients: HashMap<ClientId, Account> = HashMap::new();
+/// Fails if `transaction` is in a currency `rates` cannot convert.
+fn check_rate(
+    rates: &Rates,
+    transaction: &Transaction,
+    file: &Path,
+    line: u64,
+) -> anyhow::Result<()> {
+    if let Some(currency) = transaction
+        .currency
+        .as_ref()
+        .filter(|currency| !rates.has_rate(Some(currency)))
+    {
+        bail!(
+            "no rate from {currency} to {} for line {line} of {}",
+            rates.currency,
+            file.display()
+        );
+    }
+    Ok(())
+}
 
-    for transaction in inputs {
-        let client_id = transaction.client;
+/// Fails if two of `files` are the same file, even through different paths, since its
+/// transaction ids would collide with themselves.
+fn check_distinct_files(files: &[PathBuf]) -> anyhow::Result<()> {
+    let mut seen = HashMap::new();
+    for file in files {
+        let canonical = file
+            .canonicalize()
+            .with_context(|| format!("failed to open {}", file.display()))?;
+        if let Some(first) = seen.insert(canonical, file) {
+            bail!(
+                "{} and {} are the same input file",
+                first.display(),
+                file.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Fails if a file has no timestamp column, as `policy` would reject all of its transactions
+/// it applies to.
+fn check_timestamp_columns(files: &[PathBuf], policy: &str) -> anyhow::Result<()> {
+    for file in files {
+        let mut rdr = reader_from_path(file)?;
+        if !rdr.headers()?.iter().any(|column| column == "timestamp") {
+            bail!(
+                "invalid config: {policy} requires a timestamp column, which {} does not have",
+                file.display()
+            );
+        }
+    }
+    Ok(())
+}
 
-        if let Some(client) = clients.get_mut(&client_id) {
-            client.process_transaction(transaction);
-        } else {
-            if let TransactionKind::Deposit { amount } = transaction.kind {
-                let new_account = Account::new(amount);
-                clients.insert(client_id, new_account);
+fn run_repl(mut repl: repl::Repl) -> anyhow::Result<()> {
+    let mut editor = rustyline::DefaultEditor::new()?;
+    println!("type `help` for the list of commands");
+    loop {
+        match editor.readline("> ") {
+            Ok(line) => {
+                let _ = editor.add_history_entry(&line);
+                let response = repl.eval(&line);
+                if !response.is_empty() {
+                    println!("{response}");
+                }
             }
+            Err(ReadlineError::Interrupted | ReadlineError::Eof) => return Ok(()),
+            Err(err) => return Err(err.into()),
         }
     }
 }
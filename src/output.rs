@@ -0,0 +1,986 @@
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+};
+
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use crate::{
+    account::{LedgerEntry, round_amount},
+    engine::{Engine, Totals},
+    input,
+    transaction::{ClientId, Currency, DisputeState, Tenant, Transaction},
+};
+
+/// Number of minor units in one unit of currency, i.e. the output precision.
+const MINOR_UNITS: i64 = 10_000;
+
+/// Settings changing how the report is rendered.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReportOptions {
+    /// Writes amounts as integer minor units (ten-thousandths) instead of decimals.
+    pub minor_units: bool,
+    /// Writes `locked` as `1` or `0` instead of `true` or `false`.
+    pub bool_as_int: bool,
+    /// Also writes the accounts seeded from a balances snapshot that had no transaction.
+    pub include_inactive: bool,
+    /// Adds the deposit and withdrawal fees paid by every client, on the row of its implicit currency.
+    pub include_fees: bool,
+    /// Adds the total of every row converted with these rates.
+    pub rates: Option<Rates>,
+    /// Adds the anti-money laundering thresholds every client exceeded, see
+    /// [`crate::engine::EngineConfig::aml_flag`].
+    pub aml_columns: bool,
+    /// Adds the sequence number and timestamp of the first and last transaction every
+    /// client had accepted, see [`crate::account::Account::first_activity`], and what was
+    /// rejected because the account was locked, see
+    /// [`crate::account::Account::locked_rejections`], and the chargeback that locked it, see
+    /// [`crate::account::Account::lock_info`].
+    pub extended_columns: bool,
+    /// Appends a row with the sums of the rows written, see [`crate::engine::Totals`].
+    pub summary_row: bool,
+}
+
+/// Exchange rates to the currency of a consolidated report.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rates {
+    /// Currency amounts are converted to. The implicit currency is taken to be this one.
+    pub currency: Currency,
+    rates: HashMap<Currency, Decimal>,
+}
+
+/// Row of a rates file.
+#[derive(Deserialize)]
+struct Rate {
+    currency: Currency,
+    rate: Decimal,
+}
+
+impl Rates {
+    /// Reads rates to `currency` as CSV with the columns `currency` and `rate`, the value
+    /// of one unit of the currency in `currency`. Rates must be positive.
+    pub fn read<R: Read>(currency: Currency, r: R) -> csv::Result<Self> {
+        let mut rates = HashMap::new();
+        for rate in input::reader(r).deserialize() {
+            let Rate {
+                currency: from,
+                rate,
+            } = rate?;
+            if rate <= Decimal::ZERO {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("rate of {from} must be positive, got {rate}"),
+                )
+                .into());
+            }
+            rates.insert(from, rate);
+        }
+        Ok(Self { currency, rates })
+    }
+
+    /// If amounts in `currency`, `None` being the implicit currency, can be converted.
+    pub fn has_rate(&self, currency: Option<&Currency>) -> bool {
+        self.rate(currency).is_some()
+    }
+
+    /// Converts `amount` of `currency` to the report currency, rounded with
+    /// [`round_amount`].
+    pub fn convert(&self, currency: Option<&Currency>, amount: Decimal) -> Option<Decimal> {
+        self.rate(currency).map(|rate| round_amount(amount * rate))
+    }
+
+    fn rate(&self, currency: Option<&Currency>) -> Option<Decimal> {
+        match currency {
+            Some(currency) if *currency != self.currency => self.rates.get(currency).copied(),
+            _ => Some(Decimal::ONE),
+        }
+    }
+}
+
+pub fn format_decimal(value: Decimal) -> String {
+    format!("{:.4}", value)
+}
+
+/// Converts `value` to an integer amount of minor units, failing if it has more precision
+/// than the output.
+fn format_minor_units(value: Decimal) -> io::Result<String> {
+    let minor_units = value * Decimal::from(MINOR_UNITS);
+    if !minor_units.fract().is_zero() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{value} cannot be written as minor units, it has more than 4 decimal places"),
+        ));
+    }
+    Ok(minor_units.trunc().to_string())
+}
+
+/// Writes the state of every account as CSV, ordered by client. Accounts using explicit
+/// currencies have one row per currency, and the report then gets a `currency` column.
+/// Accounts of tenants come after the ones without a tenant, ordered by tenant, and the
+/// report then starts with a `tenant` column.
+/// With rates, a `converted_total` column has the total of each row in the report currency,
+/// with [`ReportOptions::include_fees`] a `fees` column has the fees paid, and with
+/// [`ReportOptions::aml_columns`] an `aml_flag` column has the flag of the client on each of
+/// its rows. [`ReportOptions::extended_columns`] adds the `first_seen`, `first_seen_at`,
+/// `last_activity` and `last_activity_at` columns, empty for clients without accepted
+/// transactions, then the `locked_rejections` and `locked_rejected_amount` columns, the
+/// `locked_by_tx`, `locked_at_seq` and `locked_at` columns, empty unless a chargeback locked
+/// the account, and the `chargeback_locked` and `admin_frozen` columns, which `locked`
+/// combines.
+/// With [`ReportOptions::summary_row`], the last rows have `total` in the `client` column,
+/// the sums of the rows in the balance columns and the number of locked rows in `locked`,
+/// one per currency in the `currency` column, the other columns being empty.
+pub fn write_report<W: Write>(
+    engine: &Engine,
+    writer: W,
+    options: &ReportOptions,
+) -> csv::Result<()> {
+    let format = |value| {
+        if options.minor_units {
+            format_minor_units(value)
+        } else {
+            Ok(format_decimal(value))
+        }
+    };
+    let format_bool = |value: bool| {
+        if options.bool_as_int {
+            u8::from(value).to_string()
+        } else {
+            value.to_string()
+        }
+    };
+
+    let outputs = engine.output();
+    let with_currency = outputs.iter().any(|output| output.currency.is_some());
+    let with_tenant = outputs.iter().any(|output| output.tenant.is_some());
+
+    let mut wtr = csv::Writer::from_writer(writer);
+    let mut header = vec!["client", "available", "held", "total", "locked"];
+    if with_currency {
+        header.insert(1, "currency");
+    }
+    if with_tenant {
+        header.insert(0, "tenant");
+    }
+    if options.rates.is_some() {
+        header.push("converted_total");
+    }
+    if options.include_fees {
+        header.push("fees");
+    }
+    if options.aml_columns {
+        header.push("aml_flag");
+    }
+    if options.extended_columns {
+        header.extend([
+            "first_seen",
+            "first_seen_at",
+            "last_activity",
+            "last_activity_at",
+            "locked_rejections",
+            "locked_rejected_amount",
+            "locked_by_tx",
+            "locked_at_seq",
+            "locked_at",
+            "chargeback_locked",
+            "admin_frozen",
+            "negative",
+            "deposited",
+            "withdrawn",
+            "deposits",
+            "withdrawals",
+            "disputes_opened",
+            "disputes_resolved",
+            "chargebacks",
+        ]);
+    }
+    let columns = header.len();
+    wtr.write_record(header)?;
+
+    let mut written = Vec::new();
+    for output in outputs {
+        // Every output is of the engine or of one of its tenants.
+        let owner = engine
+            .tenant_engine(output.tenant.as_ref())
+            .unwrap_or(engine);
+        if !options.include_inactive && !owner.is_active(output.client) {
+            continue;
+        }
+        if options.summary_row {
+            written.push(output.clone());
+        }
+        let account = &owner.clients[&output.client];
+        let mut record = vec![
+            output.client.0.to_string(),
+            format(output.available)?,
+            format(output.held)?,
+            format(output.total)?,
+            format_bool(output.locked),
+        ];
+        if let Some(rates) = &options.rates {
+            let converted = rates
+                .convert(output.currency.as_ref(), output.total)
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "no rate for {} to {}",
+                            output.currency.as_ref().map_or("", |c| c.0.as_str()),
+                            rates.currency
+                        ),
+                    )
+                })?;
+            record.push(format(converted)?);
+        }
+        if options.include_fees {
+            let fees = match output.currency {
+                Some(_) => Decimal::ZERO,
+                None => account.fees_paid,
+            };
+            record.push(format(fees)?);
+        }
+        if options.aml_columns {
+            let flag = engine.config.aml_flag(account);
+            record.push(flag.name().to_string());
+        }
+        if options.extended_columns {
+            for activity in [account.first_activity, account.last_activity] {
+                record.push(activity.map_or(String::new(), |a| a.sequence.to_string()));
+                record.push(
+                    activity
+                        .and_then(|a| a.timestamp)
+                        .map_or(String::new(), |t| t.to_rfc3339()),
+                );
+            }
+            record.push(account.locked_rejections.to_string());
+            record.push(format(account.locked_rejected_amount)?);
+            let lock = account.lock_info();
+            record.push(lock.map_or(String::new(), |lock| lock.tx.0.to_string()));
+            record.push(lock.map_or(String::new(), |lock| lock.seq.to_string()));
+            record.push(
+                lock.and_then(|lock| lock.timestamp)
+                    .map_or(String::new(), |t| t.to_rfc3339()),
+            );
+            record.push(format_bool(account.locked));
+            record.push(format_bool(account.admin_frozen));
+            record.push(format_bool(output.is_negative()));
+            // Like the fees, the gross amounts are in the implicit currency.
+            let gross = account.gross_totals();
+            for amount in [gross.deposited, gross.withdrawn] {
+                record.push(format(match output.currency {
+                    Some(_) => Decimal::ZERO,
+                    None => amount,
+                })?);
+            }
+            for count in [
+                gross.deposits,
+                gross.withdrawals,
+                gross.disputes_opened,
+                gross.disputes_resolved,
+                gross.chargebacks,
+            ] {
+                record.push(count.to_string());
+            }
+        }
+        if with_currency {
+            record.insert(1, output.currency.map(|c| c.0).unwrap_or_default());
+        }
+        if with_tenant {
+            record.insert(0, output.tenant.map(|t| t.0).unwrap_or_default());
+        }
+        wtr.write_record(record)?;
+    }
+
+    if options.summary_row {
+        let totals = Totals::by_currency(&written).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the totals of the report overflow",
+            )
+        })?;
+        for totals in totals {
+            let mut record = vec![
+                "total".to_string(),
+                format(totals.available)?,
+                format(totals.held)?,
+                format(totals.total)?,
+                totals.locked.to_string(),
+            ];
+            if with_currency {
+                record.insert(1, totals.currency.map(|c| c.0).unwrap_or_default());
+            }
+            if with_tenant {
+                record.insert(0, String::new());
+            }
+            record.resize(columns, String::new());
+            wtr.write_record(record)?;
+        }
+    }
+
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Columns written by [`write_transactions`] and [`write_ledger`].
+const TRANSACTION_COLUMNS: [&str; 8] = [
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "timestamp",
+    "memo",
+    "currency",
+    "tenant",
+];
+
+/// Writes transactions as CSV, in the same format they are read from.
+pub fn write_transactions<'a, W: Write>(
+    transactions: impl IntoIterator<Item = &'a Transaction>,
+    writer: W,
+) -> csv::Result<()> {
+    write_ledger(
+        transactions
+            .into_iter()
+            .map(|transaction| (transaction.client, LedgerEntry::Transaction(transaction))),
+        writer,
+    )
+}
+
+/// Writes ledger entries of clients like [`write_transactions`]. Interest credits are
+/// written as `interest` rows without a transaction id, timestamped at the end of their
+/// period, which cannot be read back as transactions.
+pub fn write_ledger<'a, W: Write>(
+    entries: impl IntoIterator<Item = (ClientId, LedgerEntry<'a>)>,
+    writer: W,
+) -> csv::Result<()> {
+    let mut wtr = csv::Writer::from_writer(writer);
+    wtr.write_record(TRANSACTION_COLUMNS)?;
+
+    for (client, entry) in entries {
+        let record = match entry {
+            LedgerEntry::Transaction(transaction) => [
+                transaction.kind.name().to_string(),
+                transaction.client.0.to_string(),
+                transaction.id.0.to_string(),
+                transaction
+                    .kind
+                    .amount()
+                    .map_or_else(String::new, |amount| amount.to_string()),
+                transaction
+                    .timestamp
+                    .map_or_else(String::new, |timestamp| timestamp.to_rfc3339()),
+                transaction.memo.clone().unwrap_or_default(),
+                transaction
+                    .currency
+                    .as_ref()
+                    .map_or_else(String::new, Currency::to_string),
+                transaction
+                    .tenant
+                    .as_ref()
+                    .map_or_else(String::new, Tenant::to_string),
+            ],
+            LedgerEntry::Interest(interest) => [
+                "interest".to_string(),
+                client.0.to_string(),
+                String::new(),
+                interest.amount.to_string(),
+                interest.to.to_rfc3339(),
+                String::new(),
+                String::new(),
+                String::new(),
+            ],
+        };
+        wtr.write_record(&record)?;
+    }
+
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Writes every dispute as CSV with the columns `client`, `tx`, `state`, `held` and
+/// `history`, the states the dispute went through separated by `>`, ordered by client and
+/// transaction. Like [`write_report`], disputes of tenants come after the others, and the
+/// report then starts with a `tenant` column.
+pub fn write_dispute_report<W: Write>(engine: &Engine, writer: W) -> csv::Result<()> {
+    let with_tenant = engine.tenant_engines().next().is_some();
+    let mut wtr = csv::Writer::from_writer(writer);
+    let mut header = vec!["client", "tx", "state", "held", "history"];
+    if with_tenant {
+        header.insert(0, "tenant");
+    }
+    wtr.write_record(header)?;
+    let mut disputes: Vec<_> = engine.all_disputes().collect();
+    disputes.sort_by_key(|(tenant, client, tx, _)| (*tenant, *client, *tx));
+    for (tenant, client, tx, dispute) in disputes {
+        let history: Vec<_> = dispute.history().iter().map(DisputeState::name).collect();
+        let mut record = vec![
+            client.0.to_string(),
+            tx.0.to_string(),
+            dispute.state().to_string(),
+            format_decimal(dispute.held()),
+            history.join(">"),
+        ];
+        if with_tenant {
+            record.insert(0, tenant.map_or_else(String::new, Tenant::to_string));
+        }
+        wtr.write_record(record)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Writes the accounts with a negative balance as CSV with the columns `client`,
+/// `available`, `total` and `shortfall`, the amount the client would have to pay back, in
+/// the order of [`write_report`] and with its `tenant` and `currency` columns when needed.
+pub fn write_negative_report<W: Write>(engine: &Engine, writer: W) -> csv::Result<()> {
+    let outputs = engine.negative_accounts();
+    let with_currency = outputs.iter().any(|output| output.currency.is_some());
+    let with_tenant = outputs.iter().any(|output| output.tenant.is_some());
+
+    let mut wtr = csv::Writer::from_writer(writer);
+    let mut header = vec!["client", "available", "total", "shortfall"];
+    if with_currency {
+        header.insert(1, "currency");
+    }
+    if with_tenant {
+        header.insert(0, "tenant");
+    }
+    wtr.write_record(header)?;
+    for output in outputs {
+        let mut record = vec![
+            output.client.0.to_string(),
+            format_decimal(output.available),
+            format_decimal(output.total),
+            format_decimal(output.shortfall()),
+        ];
+        if with_currency {
+            record.insert(1, output.currency.map(|c| c.0).unwrap_or_default());
+        }
+        if with_tenant {
+            record.insert(0, output.tenant.map(|t| t.0).unwrap_or_default());
+        }
+        wtr.write_record(record)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        engine::{EngineConfig, FeeSchedule},
+        input,
+        testkit::{self, TxSeq, engine_from},
+        transaction::TransactionKind,
+    };
+
+    use super::*;
+
+    fn report(engine: &Engine, options: &ReportOptions) -> csv::Result<String> {
+        let mut report = Vec::new();
+        write_report(engine, &mut report, options)?;
+        Ok(String::from_utf8(report).unwrap())
+    }
+
+    fn deposit(engine: &mut Engine, client: u16, amount: Decimal) {
+        engine
+            .process_transaction(testkit::transaction(
+                client,
+                client.into(),
+                TransactionKind::Deposit { amount },
+            ))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_minor_units() {
+        let mut engine = Engine::new();
+        deposit(&mut engine, 1, Decimal::new(1005, 1));
+        let options = ReportOptions {
+            minor_units: true,
+            ..ReportOptions::default()
+        };
+
+        assert_eq!(
+            report(&engine, &options).unwrap(),
+            "client,available,held,total,locked\n1,1005000,0,1005000,false\n"
+        );
+        assert_eq!(
+            report(&engine, &ReportOptions::default()).unwrap(),
+            "client,available,held,total,locked\n1,100.5000,0.0000,100.5000,false\n"
+        );
+
+        // Amounts below a minor unit cannot be written as an integer.
+        deposit(&mut engine, 2, Decimal::new(100_005, 5));
+        assert!(report(&engine, &options).is_err());
+    }
+
+    #[test]
+    fn test_bool_as_int() {
+        let mut engine = Engine::new();
+        let balances = "client,available,held,locked\n1,10.0,0,false\n2,20.0,0,true\n";
+        engine.load_balances(balances.as_bytes()).unwrap();
+        let options = ReportOptions {
+            include_inactive: true,
+            ..ReportOptions::default()
+        };
+
+        assert_eq!(
+            report(&engine, &options).unwrap(),
+            "client,available,held,total,locked\n\
+             1,10.0000,0.0000,10.0000,false\n\
+             2,20.0000,0.0000,20.0000,true\n"
+        );
+        let options = ReportOptions {
+            bool_as_int: true,
+            ..options
+        };
+        assert_eq!(
+            report(&engine, &options).unwrap(),
+            "client,available,held,total,locked\n\
+             1,10.0000,0.0000,10.0000,0\n\
+             2,20.0000,0.0000,20.0000,1\n"
+        );
+    }
+
+    #[test]
+    fn test_include_inactive() {
+        let mut engine = Engine::new();
+        let balances = "client,available,held,locked\n1,10.0,0,false\n2,20.0,0,false\n";
+        engine.load_balances(balances.as_bytes()).unwrap();
+        deposit(&mut engine, 1, Decimal::new(5, 0));
+
+        assert_eq!(
+            report(&engine, &ReportOptions::default()).unwrap(),
+            "client,available,held,total,locked\n1,15.0000,0.0000,15.0000,false\n"
+        );
+        let options = ReportOptions {
+            include_inactive: true,
+            ..ReportOptions::default()
+        };
+        assert_eq!(
+            report(&engine, &options).unwrap(),
+            "client,available,held,total,locked
+1,15.0000,0.0000,15.0000,false
+2,20.0000,0.0000,20.0000,false
+"
+        );
+    }
+
+    #[test]
+    fn test_currencies() {
+        let input = "\
+type,client,tx,amount,currency
+deposit,1,1,100.0,USD
+deposit,1,2,50.0,EUR
+dispute,1,2,,
+deposit,2,3,10.0,
+";
+        let mut engine = Engine::new();
+        for transaction in input::reader(input.as_bytes()).deserialize() {
+            engine.process_transaction(transaction.unwrap()).unwrap();
+        }
+
+        assert_eq!(
+            report(&engine, &ReportOptions::default()).unwrap(),
+            "\
+client,currency,available,held,total,locked
+1,EUR,0.0000,50.0000,50.0000,false
+1,USD,100.0000,0.0000,100.0000,false
+2,,10.0000,0.0000,10.0000,false
+"
+        );
+    }
+
+    #[test]
+    fn test_without_currencies() {
+        let mut engine = Engine::new();
+        deposit(&mut engine, 1, Decimal::new(5, 0));
+        assert_eq!(
+            report(&engine, &ReportOptions::default()).unwrap(),
+            "client,available,held,total,locked\n1,5.0000,0.0000,5.0000,false\n"
+        );
+    }
+
+    #[test]
+    fn test_rates() {
+        let input = "\
+type,client,tx,amount,currency
+deposit,1,1,100.0,USD
+deposit,1,2,50.0,EUR
+dispute,1,2,,
+deposit,2,3,10.0,GBP
+deposit,2,4,3.0,
+";
+        let mut engine = Engine::new();
+        for transaction in input::reader(input.as_bytes()).deserialize() {
+            engine.process_transaction(transaction.unwrap()).unwrap();
+        }
+        let rates = "currency,rate\nEUR,1.0825\nGBP,1.267155\n";
+        let options = ReportOptions {
+            rates: Some(Rates::read(Currency("USD".to_string()), rates.as_bytes()).unwrap()),
+            ..ReportOptions::default()
+        };
+
+        // 10 GBP are 12.67155 USD, rounded half to even, and the implicit currency is taken to
+        // be USD.
+        assert_eq!(
+            report(&engine, &options).unwrap(),
+            "\
+client,currency,available,held,total,locked,converted_total
+1,EUR,0.0000,50.0000,50.0000,false,54.1250
+1,USD,100.0000,0.0000,100.0000,false,100.0000
+2,,3.0000,0.0000,3.0000,false,3.0000
+2,GBP,10.0000,0.0000,10.0000,false,12.6716
+"
+        );
+
+        let options = ReportOptions {
+            rates: Some(
+                Rates::read(Currency("USD".to_string()), "currency,rate\n".as_bytes()).unwrap(),
+            ),
+            ..ReportOptions::default()
+        };
+        let err = report(&engine, &options).unwrap_err();
+        assert!(err.to_string().contains("no rate for EUR to USD"), "{err}");
+
+        let err = Rates::read(
+            Currency("USD".to_string()),
+            "currency,rate\nEUR,0\n".as_bytes(),
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string().contains("rate of EUR must be positive"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_include_fees() {
+        let mut engine = Engine::with_config(EngineConfig {
+            withdrawal_fee: Some(FeeSchedule {
+                flat: Decimal::ONE,
+                ..FeeSchedule::default()
+            }),
+            ..EngineConfig::default()
+        });
+        deposit(&mut engine, 1, Decimal::new(10, 0));
+        engine
+            .process_transaction(testkit::withdrawal(1, 2, "4"))
+            .unwrap();
+        let options = ReportOptions {
+            include_fees: true,
+            ..ReportOptions::default()
+        };
+        assert_eq!(
+            report(&engine, &options).unwrap(),
+            "client,available,held,total,locked,fees\n1,5.0000,0.0000,5.0000,false,1.0000\n"
+        );
+    }
+
+    #[test]
+    fn test_aml_columns() {
+        let mut engine = Engine::with_config(EngineConfig {
+            aml_cumulative_threshold: Some(Decimal::new(10_000, 0)),
+            aml_single_threshold: Some(Decimal::new(5_000, 0)),
+            ..EngineConfig::default()
+        });
+        let transactions = [
+            (1, 1, Some(Decimal::new(5_000, 0))),
+            (1, 2, Some(Decimal::new(5_000, 0))),
+            (2, 3, Some(Decimal::new(5_000, 0))),
+            (2, 4, Some(Decimal::new(500_001, 2))),
+            (3, 5, Some(Decimal::new(4_000, 0))),
+            (3, 6, Some(Decimal::new(4_000, 0))),
+            (3, 7, Some(Decimal::new(4_000, 0))),
+            // Charging back keeps the deposit in the cumulative figure.
+            (3, 7, None),
+            (4, 8, Some(Decimal::new(5_001, 0))),
+        ];
+        for (client, id, amount) in transactions {
+            let kinds = match amount {
+                Some(amount) => vec![TransactionKind::Deposit { amount }],
+                None => vec![
+                    TransactionKind::Dispute { amount: None },
+                    TransactionKind::Chargeback,
+                ],
+            };
+            for kind in kinds {
+                engine
+                    .process_transaction(testkit::transaction(client, id, kind))
+                    .unwrap();
+            }
+        }
+        let options = ReportOptions {
+            aml_columns: true,
+            ..ReportOptions::default()
+        };
+        assert_eq!(
+            report(&engine, &options).unwrap(),
+            "client,available,held,total,locked,aml_flag\n\
+             1,10000.0000,0.0000,10000.0000,false,none\n\
+             2,10000.0100,0.0000,10000.0100,false,both\n\
+             3,8000.0000,0.0000,8000.0000,true,cumulative\n\
+             4,5001.0000,0.0000,5001.0000,false,single\n"
+        );
+        assert!(
+            !report(&engine, &ReportOptions::default())
+                .unwrap()
+                .contains("aml_flag")
+        );
+    }
+
+    #[test]
+    fn test_extended_columns() {
+        let timestamp = |hour| {
+            chrono::DateTime::parse_from_rfc3339(&format!("2024-03-01T{hour:02}:00:00Z"))
+                .unwrap()
+                .to_utc()
+        };
+        let transactions = [
+            (1, 1, Decimal::new(10, 0), true, Some(timestamp(8))),
+            (2, 2, Decimal::new(5, 0), true, None),
+            (1, 3, Decimal::new(4, 0), false, Some(timestamp(9))),
+            // Rejected for insufficient funds.
+            (1, 4, Decimal::new(50, 0), false, Some(timestamp(10))),
+        ];
+        let mut engine = Engine::new();
+        for (client, id, amount, is_deposit, timestamp) in transactions {
+            let kind = if is_deposit {
+                TransactionKind::Deposit { amount }
+            } else {
+                TransactionKind::Withdrawal { amount }
+            };
+            let _ = engine.process_transaction(Transaction {
+                timestamp,
+                ..testkit::transaction(client, id, kind)
+            });
+        }
+        // Ignored, as the disputed transaction is unknown.
+        engine
+            .process_transaction(Transaction {
+                timestamp: Some(timestamp(11)),
+                ..testkit::dispute(1, 9)
+            })
+            .unwrap();
+
+        let options = ReportOptions {
+            extended_columns: true,
+            ..ReportOptions::default()
+        };
+        assert_eq!(
+            report(&engine, &options).unwrap(),
+            "client,available,held,total,locked,first_seen,first_seen_at,last_activity,\
+             last_activity_at,locked_rejections,locked_rejected_amount,locked_by_tx,\
+             locked_at_seq,locked_at,chargeback_locked,admin_frozen,negative,deposited,withdrawn,\
+             deposits,withdrawals,disputes_opened,disputes_resolved,chargebacks\n\
+             1,6.0000,0.0000,6.0000,false,1,2024-03-01T08:00:00+00:00,3,\
+             2024-03-01T09:00:00+00:00,0,0.0000,,,,false,false,false,10.0000,4.0000,\
+             1,1,0,0,0\n\
+             2,5.0000,0.0000,5.0000,false,2,,2,,0,0.0000,,,,false,false,false,5.0000,0.0000,\
+             1,0,0,0,0\n"
+        );
+        assert_eq!(
+            report(&engine, &ReportOptions::default()).unwrap(),
+            "client,available,held,total,locked\n\
+             1,6.0000,0.0000,6.0000,false\n\
+             2,5.0000,0.0000,5.0000,false\n"
+        );
+    }
+
+    #[test]
+    fn test_dispute_report() {
+        let transactions = [
+            (
+                2,
+                1,
+                TransactionKind::Deposit {
+                    amount: Decimal::TEN,
+                },
+            ),
+            (
+                1,
+                2,
+                TransactionKind::Deposit {
+                    amount: Decimal::ONE,
+                },
+            ),
+            (2, 1, TransactionKind::Dispute { amount: None }),
+            (2, 1, TransactionKind::RequestEvidence),
+            (2, 1, TransactionKind::PreArbitration),
+            (1, 2, TransactionKind::Dispute { amount: None }),
+            (1, 2, TransactionKind::Resolve { amount: None }),
+        ];
+        let mut engine = Engine::new();
+        for (client, id, kind) in transactions {
+            engine
+                .process_transaction(testkit::transaction(client, id, kind))
+                .unwrap();
+        }
+
+        let mut report = Vec::new();
+        write_dispute_report(&engine, &mut report).unwrap();
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "client,tx,state,held,history\n\
+             1,2,resolved,0.0000,disputed>resolved\n\
+             2,1,pre_arbitration,10.0000,disputed>evidence_requested>pre_arbitration\n"
+        );
+    }
+
+    #[test]
+    fn test_negative_report() {
+        let transactions = [
+            (
+                1,
+                1,
+                TransactionKind::Deposit {
+                    amount: Decimal::TEN,
+                },
+            ),
+            (
+                2,
+                2,
+                TransactionKind::Deposit {
+                    amount: Decimal::new(5, 0),
+                },
+            ),
+            (
+                1,
+                3,
+                TransactionKind::Withdrawal {
+                    amount: Decimal::new(7, 0),
+                },
+            ),
+            (1, 1, TransactionKind::Dispute { amount: None }),
+            (1, 1, TransactionKind::Chargeback),
+        ];
+        let mut engine = Engine::new();
+        for (client, id, kind) in transactions {
+            engine
+                .process_transaction(testkit::transaction(client, id, kind))
+                .unwrap();
+        }
+
+        let mut negative = Vec::new();
+        write_negative_report(&engine, &mut negative).unwrap();
+        assert_eq!(
+            String::from_utf8(negative).unwrap(),
+            "client,available,total,shortfall\n1,-7.0000,-7.0000,7.0000\n"
+        );
+        let options = ReportOptions {
+            extended_columns: true,
+            ..ReportOptions::default()
+        };
+        let report = report(&engine, &options).unwrap();
+        let column = report
+            .lines()
+            .next()
+            .unwrap()
+            .split(',')
+            .position(|name| name == "negative")
+            .unwrap();
+        let negative: Vec<_> = report
+            .lines()
+            .map(|line| line.split(',').nth(column).unwrap())
+            .collect();
+        assert_eq!(negative, ["negative", "true", "false"]);
+    }
+
+    #[test]
+    fn test_summary_row() {
+        let amount = |units| Decimal::new(units, 1);
+        let transactions = [
+            (
+                1,
+                1,
+                TransactionKind::Deposit {
+                    amount: amount(105),
+                },
+            ),
+            (2, 2, TransactionKind::Deposit { amount: amount(70) }),
+            (3, 3, TransactionKind::Deposit { amount: amount(50) }),
+            (2, 4, TransactionKind::Withdrawal { amount: amount(25) }),
+            (1, 1, TransactionKind::Dispute { amount: None }),
+            (3, 3, TransactionKind::Dispute { amount: None }),
+            (3, 3, TransactionKind::Chargeback),
+        ];
+        let mut engine = Engine::new();
+        for (client, id, kind) in transactions {
+            engine
+                .process_transaction(testkit::transaction(client, id, kind))
+                .unwrap();
+        }
+
+        let options = ReportOptions {
+            summary_row: true,
+            ..ReportOptions::default()
+        };
+        let report = report(&engine, &options).unwrap();
+        assert_eq!(
+            report,
+            "client,available,held,total,locked\n\
+             1,0.0000,10.5000,10.5000,false\n\
+             2,4.5000,0.0000,4.5000,false\n\
+             3,0.0000,0.0000,0.0000,true\n\
+             total,4.5000,10.5000,15.0000,1\n"
+        );
+
+        // The totals are the sums of the columns of the account rows.
+        let rows: Vec<Vec<&str>> = report
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').collect())
+            .collect();
+        let (total_row, rows) = rows.split_last().unwrap();
+        let sum = |column: usize| -> Decimal {
+            rows.iter()
+                .map(|row| row[column].parse::<Decimal>().unwrap())
+                .sum()
+        };
+        let totals = engine.totals().unwrap();
+        let [totals] = &totals[..] else {
+            panic!("expected a single currency");
+        };
+        assert_eq!(totals.accounts, 3);
+        assert_eq!(totals.locked, 1);
+        assert_eq!(totals.available, sum(1));
+        assert_eq!(totals.held, sum(2));
+        assert_eq!(totals.total, sum(3));
+        assert_eq!(total_row[3].parse::<Decimal>().unwrap(), totals.total);
+    }
+
+    #[test]
+    fn test_summary_rows_per_currency() {
+        let eur = |transaction| Transaction {
+            currency: Some(Currency("EUR".to_string())),
+            ..transaction
+        };
+        let engine = engine_from(
+            TxSeq::new()
+                .deposit(1, 1, "10")
+                .push(eur(testkit::deposit(1, 2, "3")))
+                .push(eur(testkit::deposit(2, 3, "4"))),
+        );
+        let options = ReportOptions {
+            summary_row: true,
+            ..ReportOptions::default()
+        };
+
+        // Amounts of different currencies are summed separately.
+        assert_eq!(
+            report(&engine, &options).unwrap(),
+            "client,currency,available,held,total,locked\n\
+             1,,10.0000,0.0000,10.0000,false\n\
+             1,EUR,3.0000,0.0000,3.0000,false\n\
+             2,EUR,4.0000,0.0000,4.0000,false\n\
+             total,,10.0000,0.0000,10.0000,0\n\
+             total,EUR,7.0000,0.0000,7.0000,0\n"
+        );
+        let totals = engine.totals().unwrap();
+        let currencies: Vec<_> = totals.iter().map(|t| t.currency.clone()).collect();
+        assert_eq!(currencies, [None, Some(Currency("EUR".to_string()))]);
+        assert_eq!(totals[1].accounts, 2);
+    }
+}
@@ -0,0 +1,190 @@
+use anyhow::{Context, bail};
+use postgres::{Client, NoTls};
+
+use crate::engine::{Engine, EngineOutput};
+
+/// Destination of the final balances.
+pub trait BalanceSink {
+    /// Upserts `balances`, tagged with `run_id`. Either every row is written or none is.
+    fn upsert(&mut self, run_id: &str, balances: &[EngineOutput]) -> anyhow::Result<()>;
+}
+
+/// Upserts the state of every account into `sink`. Returns the number of accounts written.
+/// Fails if an account uses explicit currencies or has a tenant, since the table has one row
+/// per client.
+pub fn export(engine: &Engine, run_id: &str, sink: &mut impl BalanceSink) -> anyhow::Result<usize> {
+    let balances = engine.output();
+    if let Some(output) = balances.iter().find(|output| output.currency.is_some()) {
+        bail!(
+            "client {} has balances in currencies, which cannot be exported to PostgreSQL",
+            output.client.0
+        );
+    }
+    if let Some(output) = balances.iter().find(|output| output.tenant.is_some()) {
+        bail!(
+            "client {} has a tenant, which cannot be exported to PostgreSQL",
+            output.client.0
+        );
+    }
+    sink.upsert(run_id, &balances)?;
+    Ok(balances.len())
+}
+
+/// Writes the balances to a PostgreSQL table with the columns `client integer primary key`,
+/// `available`, `held` and `total numeric(20,4)`, `locked bool` and `run_id text`.
+pub struct PgSink {
+    client: Client,
+    table: String,
+}
+
+impl PgSink {
+    /// Connects to `dsn` without TLS. `table` may be schema-qualified.
+    pub fn connect(dsn: &str, table: &str) -> anyhow::Result<Self> {
+        // The table name can't be a query parameter, so only plain identifiers are accepted.
+        let is_identifier = |part: &str| {
+            !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        };
+        if !table.split('.').all(is_identifier) {
+            bail!("invalid table name {table:?}");
+        }
+
+        let client = Client::connect(dsn, NoTls).context("failed to connect to PostgreSQL")?;
+        Ok(Self {
+            client,
+            table: table.to_string(),
+        })
+    }
+}
+
+impl BalanceSink for PgSink {
+    fn upsert(&mut self, run_id: &str, balances: &[EngineOutput]) -> anyhow::Result<()> {
+        // Dropping the transaction on error rolls it back, leaving the table untouched.
+        let mut transaction = self.client.transaction()?;
+        let statement = transaction.prepare(&format!(
+            "INSERT INTO {} (client, available, held, total, locked, run_id)
+             VALUES ($1, $2, $3, $4, $5, $6)
+             ON CONFLICT (client) DO UPDATE SET
+                 available = EXCLUDED.available,
+                 held = EXCLUDED.held,
+                 total = EXCLUDED.total,
+                 locked = EXCLUDED.locked,
+                 run_id = EXCLUDED.run_id",
+            self.table
+        ))?;
+
+        for balance in balances {
+            transaction.execute(
+                &statement,
+                &[
+                    &i32::from(balance.client.0),
+                    &balance.available,
+                    &balance.held,
+                    &balance.total,
+                    &balance.locked,
+                    &run_id,
+                ],
+            )?;
+        }
+
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use crate::testkit;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct MockSink {
+        runs: Vec<(String, Vec<EngineOutput>)>,
+        fail: bool,
+    }
+
+    impl BalanceSink for MockSink {
+        fn upsert(&mut self, run_id: &str, balances: &[EngineOutput]) -> anyhow::Result<()> {
+            if self.fail {
+                bail!("connection lost");
+            }
+            self.runs.push((run_id.to_string(), balances.to_vec()));
+            Ok(())
+        }
+    }
+
+    fn engine() -> Engine {
+        let mut engine = Engine::new();
+        for (client, id) in [(2, 1), (1, 2)] {
+            engine
+                .process_transaction(testkit::deposit(client, id, "1.5"))
+                .unwrap();
+        }
+        engine
+    }
+
+    #[test]
+    fn test_export() {
+        let engine = engine();
+        let mut sink = MockSink::default();
+
+        assert_eq!(export(&engine, "run-1", &mut sink).unwrap(), 2);
+        assert_eq!(sink.runs, vec![("run-1".to_string(), engine.output())]);
+
+        sink.fail = true;
+        assert!(export(&engine, "run-2", &mut sink).is_err());
+        assert_eq!(sink.runs.len(), 1);
+    }
+
+    #[test]
+    fn test_invalid_table_name() {
+        let err = PgSink::connect("host=localhost", "balances; DROP TABLE balances").err();
+        assert!(err.unwrap().to_string().starts_with("invalid table name"));
+    }
+
+    /// Runs against the database in `PG_TEST_DSN`.
+    #[test]
+    #[ignore]
+    fn test_postgres() {
+        let dsn = std::env::var("PG_TEST_DSN").expect("PG_TEST_DSN is not set");
+        let table = "payments_test_balances";
+        let mut client = Client::connect(&dsn, NoTls).unwrap();
+        client
+            .batch_execute(&format!(
+                "DROP TABLE IF EXISTS {table};
+                 CREATE TABLE {table} (
+                     client integer PRIMARY KEY,
+                     available numeric(20,4) NOT NULL,
+                     held numeric(20,4) NOT NULL,
+                     total numeric(20,4) NOT NULL,
+                     locked bool NOT NULL,
+                     run_id text NOT NULL
+                 );"
+            ))
+            .unwrap();
+
+        let mut sink = PgSink::connect(&dsn, table).unwrap();
+        export(&engine(), "run-1", &mut sink).unwrap();
+        export(&engine(), "run-2", &mut sink).unwrap();
+
+        let rows = client
+            .query(
+                &format!("SELECT client, total, run_id FROM {table} ORDER BY client"),
+                &[],
+            )
+            .unwrap();
+        let rows: Vec<(i32, Decimal, String)> = rows
+            .iter()
+            .map(|row| (row.get(0), row.get(1), row.get(2)))
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                (1, Decimal::new(15, 1), "run-2".to_string()),
+                (2, Decimal::new(15, 1), "run-2".to_string()),
+            ]
+        );
+    }
+}
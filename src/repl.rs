@@ -0,0 +1,246 @@
+use std::fmt::Write;
+
+use crate::{
+    engine::{Engine, EngineConfig},
+    input::{self, parse_line, reader_from_path},
+    output::{ReportOptions, format_decimal, write_report},
+    transaction::{ClientId, Transaction},
+};
+
+const HELP: &str = "\
+commands:
+  deposit <client> <tx> <amount>
+  withdrawal <client> <tx> <amount>
+  dispute <client> <tx> [amount]
+  resolve <client> <tx> [amount]
+  chargeback <client> <tx>
+  chargeback_reversal <client> <tx>
+  refund <client> <tx>
+  withdrawal_reversal <client> <tx>
+  request_evidence <client> <tx>
+  pre_arbitration <client> <tx>
+  arbitration <client> <tx>
+  unlock <client> <tx>    needs --allow-admin-ops
+  freeze <client> <tx>    needs --allow-admin-ops
+  unfreeze <client> <tx>  needs --allow-admin-ops
+  show <client>      state of a client account
+  report             state of every account, as CSV
+  load <file>        processes a transactions file
+  undo               reverts the last transaction or load
+  help";
+
+/// Interactive session applying one command per line to an engine.
+pub struct Repl {
+    engine: Engine,
+    /// Description and transactions of every command that changed the engine. Undoing a
+    /// command replays all the previous ones on a new engine.
+    history: Vec<(String, Vec<Transaction>)>,
+}
+
+impl Repl {
+    pub fn new(config: EngineConfig) -> Self {
+        Self {
+            engine: Engine::with_config(config),
+            history: Vec::new(),
+        }
+    }
+
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    /// Evaluates a line, returning the text to print back.
+    pub fn eval(&mut self, line: &str) -> String {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            [] => String::new(),
+            ["help"] => HELP.to_string(),
+            ["show", client] => self.show(client),
+            ["report"] => self.report(),
+            ["undo"] => self.undo(),
+            ["load", path] => self.load(path),
+            [kind, ..] if is_transaction_kind(kind) => self.process(&words.join(", ")),
+            [command, ..] => format!("unknown command {command:?}, see `help`"),
+        }
+    }
+
+    fn process(&mut self, line: &str) -> String {
+        let transaction = match parse_line(line) {
+            Ok(transaction) => transaction,
+            Err(err) => return format!("invalid: {err}"),
+        };
+        match self.engine.process_transaction(transaction.clone()) {
+            Ok(()) => {
+                let description = format!(
+                    "{} {} {}",
+                    transaction.kind.name(),
+                    transaction.client.0,
+                    transaction.id.0
+                );
+                self.history.push((description, vec![transaction]));
+                "ok".to_string()
+            }
+            Err(reason) => format!("rejected: {reason}"),
+        }
+    }
+
+    fn show(&self, client: &str) -> String {
+        let Ok(client) = client.parse() else {
+            return format!("invalid client {client:?}");
+        };
+        match self.engine.account_output(ClientId(client)) {
+            Some(account) => format!(
+                "client {client}: available {}, held {}, total {}, locked {}",
+                format_decimal(account.available),
+                format_decimal(account.held),
+                format_decimal(account.total),
+                account.locked
+            ),
+            None => format!("client {client} has no account"),
+        }
+    }
+
+    fn report(&self) -> String {
+        let mut report = Vec::new();
+        match write_report(&self.engine, &mut report, &ReportOptions::default()) {
+            Ok(()) => String::from_utf8_lossy(&report).trim_end().to_string(),
+            Err(err) => format!("error: {err}"),
+        }
+    }
+
+    fn load(&mut self, path: &str) -> String {
+        let transactions = reader_from_path(path)
+            .and_then(input::transactions)
+            .and_then(|transactions| transactions.collect::<csv::Result<Vec<_>>>());
+        let transactions = match transactions {
+            Ok(transactions) => transactions,
+            Err(err) => return format!("error: {err}"),
+        };
+
+        let mut rejected = 0;
+        for transaction in &transactions {
+            if self
+                .engine
+                .process_transaction(transaction.clone())
+                .is_err()
+            {
+                rejected += 1;
+            }
+        }
+        let mut response = format!("loaded {} transactions from {path}", transactions.len());
+        if rejected > 0 {
+            let _ = write!(response, ", {rejected} rejected");
+        }
+        self.history.push((format!("load of {path}"), transactions));
+        response
+    }
+
+    fn undo(&mut self) -> String {
+        let Some((undone, _)) = self.history.pop() else {
+            return "nothing to undo".to_string();
+        };
+
+        let mut engine = Engine::with_config(self.engine.config.clone());
+        for transaction in self
+            .history
+            .iter()
+            .flat_map(|(_, transactions)| transactions)
+        {
+            // Rejected transactions are ignored, as when they were first processed.
+            let _ = engine.process_transaction(transaction.clone());
+        }
+        self.engine = engine;
+        format!("undid {undone}")
+    }
+}
+
+fn is_transaction_kind(word: &str) -> bool {
+    matches!(
+        word,
+        "deposit"
+            | "withdrawal"
+            | "dispute"
+            | "resolve"
+            | "chargeback"
+            | "chargeback_reversal"
+            | "refund"
+            | "withdrawal_reversal"
+            | "request_evidence"
+            | "pre_arbitration"
+            | "arbitration"
+            | "unlock"
+            | "freeze"
+            | "unfreeze"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use rust_decimal::Decimal;
+
+    use super::*;
+
+    #[test]
+    fn test_scripted_session() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write!(
+            file,
+            "type,client,tx,amount\ndeposit,2,10,5.0\nwithdrawal,2,11,50.0\n"
+        )
+        .unwrap();
+        let load = format!("load {}", file.path().display());
+
+        let mut repl = Repl::new(EngineConfig::default());
+        let script = [
+            ("deposit 1 100 25.50", "ok".to_string()),
+            (
+                "withdrawal 1 101 30",
+                "rejected: insufficient available funds, requested 30 with 25.5 available"
+                    .to_string(),
+            ),
+            ("dispute 1 100", "ok".to_string()),
+            (
+                "show 1",
+                "client 1: available 0.0000, held 25.5000, total 25.5000, locked false".to_string(),
+            ),
+            ("show 7", "client 7 has no account".to_string()),
+            ("undo", "undid dispute 1 100".to_string()),
+            (
+                &load,
+                format!(
+                    "loaded 2 transactions from {}, 1 rejected",
+                    file.path().display()
+                ),
+            ),
+            (
+                "report",
+                "client,available,held,total,locked\n\
+                 1,25.5000,0.0000,25.5000,false\n\
+                 2,5.0000,0.0000,5.0000,false"
+                    .to_string(),
+            ),
+            ("undo", format!("undid load of {}", file.path().display())),
+            ("", String::new()),
+            (
+                "transfer 1 1",
+                "unknown command \"transfer\", see `help`".to_string(),
+            ),
+        ];
+        for (line, response) in script {
+            assert_eq!(repl.eval(line), response, "evaluating {line:?}");
+        }
+
+        assert!(repl.eval("deposit 1").starts_with("invalid: "));
+        assert!(repl.eval("load missing.csv").starts_with("error: "));
+
+        let output = repl.engine().output();
+        assert_eq!(output.len(), 1);
+        assert_eq!(output[0].available, Decimal::new(2550, 2));
+
+        assert_eq!(repl.eval("undo"), "undid deposit 1 100");
+        assert_eq!(repl.eval("undo"), "nothing to undo");
+        assert!(repl.engine().output().is_empty());
+    }
+}
@@ -0,0 +1,251 @@
+//! Post-run report of the accounts with risky dispute and chargeback patterns.
+//!
+//! The report only reads the [`RiskCounters`] of the accounts, so it never changes how
+//! transactions are processed.
+
+use std::io::Write;
+
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use crate::{
+    account::RiskCounters,
+    engine::Engine,
+    transaction::{ClientId, Tenant},
+};
+
+/// Thresholds of the risk report, read from the `[risk]` section of the config.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RiskThresholds {
+    /// Flags the accounts with more disputes than this.
+    pub max_disputes: u64,
+    /// Flags the accounts whose chargebacks are more than this percentage of their
+    /// deposits, e.g. `10` for 10%.
+    pub max_chargeback_percent: Decimal,
+    /// Flags the disputes of a deposit within this many transactions of the client after
+    /// it, e.g. `1` for the transaction right after the deposit.
+    pub immediate_dispute_rows: u64,
+    /// Flags the transactions disputed more times than this, resolving them in between.
+    pub max_disputes_per_tx: u32,
+}
+
+impl Default for RiskThresholds {
+    fn default() -> Self {
+        Self {
+            max_disputes: 5,
+            max_chargeback_percent: Decimal::TEN,
+            immediate_dispute_rows: 1,
+            max_disputes_per_tx: 2,
+        }
+    }
+}
+
+/// Risky pattern of an account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RiskPattern {
+    /// More disputes than [`RiskThresholds::max_disputes`].
+    ManyDisputes,
+    /// More chargebacks than [`RiskThresholds::max_chargeback_percent`] of the deposits.
+    HighChargebackRate,
+    /// A deposit disputed within [`RiskThresholds::immediate_dispute_rows`].
+    ImmediateDispute,
+    /// A transaction disputed more than [`RiskThresholds::max_disputes_per_tx`] times.
+    DisputeCycling,
+}
+
+impl RiskPattern {
+    pub fn name(self) -> &'static str {
+        match self {
+            RiskPattern::ManyDisputes => "many_disputes",
+            RiskPattern::HighChargebackRate => "high_chargeback_rate",
+            RiskPattern::ImmediateDispute => "immediate_dispute",
+            RiskPattern::DisputeCycling => "dispute_cycling",
+        }
+    }
+}
+
+/// Pattern found on the account of a client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RiskFinding {
+    /// Tenant of the client, if any.
+    pub tenant: Option<Tenant>,
+    pub client: ClientId,
+    pub pattern: RiskPattern,
+    /// What triggered the pattern, such as `tx 3 disputed 1 row after its deposit`.
+    pub detail: String,
+}
+
+/// Patterns of every account exceeding `thresholds`, ordered by tenant, those without a
+/// tenant first, then by client, by pattern and by transaction.
+pub fn find_risks(engine: &Engine, thresholds: &RiskThresholds) -> Vec<RiskFinding> {
+    let mut findings = Vec::new();
+    for engine in std::iter::once(engine).chain(engine.tenant_engines()) {
+        let mut clients: Vec<_> = engine.clients.iter().collect();
+        clients.sort_by_key(|(client, _)| **client);
+        for (client, account) in clients {
+            findings.extend(account_risks(&account.risk, thresholds).into_iter().map(
+                |(pattern, detail)| RiskFinding {
+                    tenant: engine.tenant().cloned(),
+                    client: *client,
+                    pattern,
+                    detail,
+                },
+            ));
+        }
+    }
+    findings
+}
+
+/// Patterns of an account with `counters`, in the order of [`find_risks`].
+fn account_risks(
+    counters: &RiskCounters,
+    thresholds: &RiskThresholds,
+) -> Vec<(RiskPattern, String)> {
+    let mut risks = Vec::new();
+    if counters.disputes > thresholds.max_disputes {
+        risks.push((
+            RiskPattern::ManyDisputes,
+            format!(
+                "{} disputes over the maximum of {}",
+                counters.disputes, thresholds.max_disputes
+            ),
+        ));
+    }
+    if counters.deposits > 0 {
+        let percent = Decimal::from(counters.chargebacks) * Decimal::ONE_HUNDRED
+            / Decimal::from(counters.deposits);
+        if percent > thresholds.max_chargeback_percent {
+            risks.push((
+                RiskPattern::HighChargebackRate,
+                format!(
+                    "{} of {} deposits charged back",
+                    counters.chargebacks, counters.deposits
+                ),
+            ));
+        }
+    }
+
+    let mut immediate: Vec<_> = counters
+        .dispute_delays
+        .iter()
+        .filter(|(_, delay)| *delay <= thresholds.immediate_dispute_rows)
+        .collect();
+    immediate.sort();
+    for (tx_id, delay) in immediate {
+        let rows = if *delay == 1 { "row" } else { "rows" };
+        risks.push((
+            RiskPattern::ImmediateDispute,
+            format!("tx {} disputed {delay} {rows} after its deposit", tx_id.0),
+        ));
+    }
+
+    let mut cycling: Vec<_> = counters
+        .disputes_per_tx
+        .iter()
+        .filter(|(_, disputes)| **disputes > thresholds.max_disputes_per_tx)
+        .collect();
+    cycling.sort();
+    for (tx_id, disputes) in cycling {
+        risks.push((
+            RiskPattern::DisputeCycling,
+            format!("tx {} disputed {disputes} times", tx_id.0),
+        ));
+    }
+    risks
+}
+
+/// Writes the patterns found by [`find_risks`] as CSV with the columns `client`, `pattern`
+/// and `detail`, and first a `tenant` column if any transaction had a tenant.
+pub fn write_risk_report<W: Write>(
+    engine: &Engine,
+    thresholds: &RiskThresholds,
+    writer: W,
+) -> csv::Result<()> {
+    let with_tenant = engine.tenant_engines().next().is_some();
+    let mut wtr = csv::Writer::from_writer(writer);
+    let mut header = vec!["client", "pattern", "detail"];
+    if with_tenant {
+        header.insert(0, "tenant");
+    }
+    wtr.write_record(header)?;
+    for finding in find_risks(engine, thresholds) {
+        let mut record = vec![
+            finding.client.0.to_string(),
+            finding.pattern.name().to_string(),
+            finding.detail,
+        ];
+        if with_tenant {
+            record.insert(0, finding.tenant.map(|t| t.0).unwrap_or_default());
+        }
+        wtr.write_record(record)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{testkit, transaction::TransactionKind};
+
+    #[test]
+    fn test_risk_report() {
+        let deposit = TransactionKind::Deposit {
+            amount: Decimal::new(10, 0),
+        };
+        let resolve = TransactionKind::Resolve { amount: None };
+        let dispute = TransactionKind::Dispute { amount: None };
+        let transactions = [
+            // Too many disputes.
+            (1, 1, deposit),
+            (1, 2, deposit),
+            (1, 3, deposit),
+            (1, 1, dispute),
+            (1, 1, resolve),
+            (1, 2, dispute),
+            (1, 2, resolve),
+            (1, 3, dispute),
+            (1, 3, resolve),
+            // Half of the deposits charged back.
+            (2, 10, deposit),
+            (2, 11, deposit),
+            (2, 10, dispute),
+            (2, 10, TransactionKind::Chargeback),
+            // Disputed right after the deposit.
+            (3, 20, deposit),
+            (3, 20, dispute),
+            (3, 20, resolve),
+            // Disputed twice.
+            (4, 30, deposit),
+            (4, 31, deposit),
+            (4, 30, dispute),
+            (4, 30, resolve),
+            (4, 30, dispute),
+            (4, 30, resolve),
+        ];
+        let mut engine = Engine::new();
+        for (client, id, kind) in transactions {
+            engine
+                .process_transaction(testkit::transaction(client, id, kind))
+                .unwrap();
+        }
+        let thresholds = RiskThresholds {
+            max_disputes: 2,
+            max_chargeback_percent: Decimal::new(25, 0),
+            immediate_dispute_rows: 1,
+            max_disputes_per_tx: 1,
+        };
+
+        let mut report = Vec::new();
+        write_risk_report(&engine, &thresholds, &mut report).unwrap();
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "client,pattern,detail\n\
+             1,many_disputes,3 disputes over the maximum of 2\n\
+             2,high_chargeback_rate,1 of 2 deposits charged back\n\
+             3,immediate_dispute,tx 20 disputed 1 row after its deposit\n\
+             4,dispute_cycling,tx 30 disputed 2 times\n"
+        );
+    }
+}
@@ -0,0 +1,224 @@
+use std::{path::Path, thread};
+
+use crate::{
+    engine::Engine,
+    error::TransactionError,
+    input::{self, reader_from_path_mmap},
+    transaction::{ClientId, Transaction, TransactionId},
+};
+
+/// Index of the shard owning every transaction of `client`, regardless of the file it
+/// comes from.
+pub fn shard_of(client: ClientId, shards: usize) -> usize {
+    usize::from(client.0) % shards
+}
+
+/// Processes `paths` as if they were a single file concatenated in the given order.
+///
+/// Files are parsed in parallel and their transactions are routed by client into `shards`
+/// partitions. Each shard is then processed by its own engine, consuming its partitions in
+/// file order, so a client's transactions are always applied sequentially and in the same
+/// order as in a single-threaded run. Since shards own disjoint sets of clients, their
+/// engines are merged by simply joining their accounts.
+///
+/// The accounts already in `engine`, e.g. seeded from a balances snapshot, are moved to the
+/// shards owning them before processing. Unless ids are only unique per client, a single
+/// shard is used, since an id is only claimed by an accepted transaction and a shard only
+/// sees the transactions of its own clients.
+///
+/// With `mmap`, the files are mapped in memory, see [`input::reader_from_path_mmap`].
+pub fn process_files<P: AsRef<Path> + Sync>(
+    paths: &[P],
+    shards: usize,
+    mut engine: Engine,
+    mmap: bool,
+) -> csv::Result<Engine> {
+    let shards = if engine.config.tx_ids_unique_per_client {
+        shards.max(1)
+    } else {
+        1
+    };
+
+    let partitions = thread::scope(|scope| {
+        let readers: Vec<_> = paths
+            .iter()
+            .enumerate()
+            .map(|(file, path)| {
+                scope.spawn(move || partition_file(file, path.as_ref(), shards, mmap))
+            })
+            .collect();
+        readers
+            .into_iter()
+            .map(|reader| reader.join().expect("file reader thread panicked"))
+            .collect::<csv::Result<Vec<_>>>()
+    })?;
+
+    // Sequence numbers continue from the transactions `engine` already processed.
+    let mut file_start = engine.sequence();
+    let mut shard_inputs: Vec<Vec<Entry>> = vec![Vec::new(); shards];
+    for file_partitions in partitions {
+        let file_len: usize = file_partitions.iter().map(Vec::len).sum();
+        for (shard, transactions) in file_partitions.into_iter().enumerate() {
+            shard_inputs[shard].extend(transactions.into_iter().map(
+                |(file, line, position, transaction)| {
+                    (file, line, file_start + position, transaction)
+                },
+            ));
+        }
+        file_start += file_len as u64;
+    }
+
+    let shard_engines: Vec<Engine> = (0..shards)
+        .map(|shard| engine.split_off(|client| shard_of(client, shards) == shard))
+        .collect();
+
+    let engines = thread::scope(|scope| {
+        let workers: Vec<_> = shard_inputs
+            .into_iter()
+            .zip(shard_engines)
+            .map(|(transactions, mut engine)| {
+                scope.spawn(move || {
+                    for (file, line, sequence, transaction) in transactions {
+                        let (client, tx) = (transaction.client, transaction.id);
+                        // Rejected transactions are only logged, as in a sequential run.
+                        if let Err(reason) = engine.process_transaction_at(sequence, transaction) {
+                            log_rejection(client, tx, &reason, paths[file].as_ref(), line);
+                        }
+                    }
+                    engine
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .map(|worker| worker.join().expect("shard thread panicked"))
+            .collect::<Vec<_>>()
+    });
+
+    for shard_engine in engines {
+        engine.merge(shard_engine);
+    }
+    Ok(engine)
+}
+
+/// Transaction along with the index of the file and the line it was read from, and its
+/// sequence number in the run.
+type Entry = (usize, u64, u64, Transaction);
+
+/// Logs a transaction rejected with `reason`, as in a sequential run.
+fn log_rejection(
+    client: ClientId,
+    tx: TransactionId,
+    reason: &TransactionError,
+    path: &Path,
+    line: u64,
+) {
+    tracing::info!(
+        client = client.0,
+        tx = tx.0,
+        %reason,
+        file = %path.display(),
+        line,
+        "transaction rejected"
+    );
+}
+
+/// Reads a whole file, splitting its transactions into one partition per shard. They are
+/// numbered from 1 in the file.
+fn partition_file(
+    file: usize,
+    path: &Path,
+    shards: usize,
+    mmap: bool,
+) -> csv::Result<Vec<Vec<Entry>>> {
+    let mut partitions = vec![Vec::new(); shards];
+    let transactions = input::transactions_with_lines(reader_from_path_mmap(path, mmap)?)?;
+    for (position, transaction) in (1..).zip(transactions) {
+        let (line, transaction) = transaction?;
+        partitions[shard_of(transaction.client, shards)].push((file, line, position, transaction));
+    }
+    Ok(partitions)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use rust_decimal::Decimal;
+    use tempfile::NamedTempFile;
+
+    use super::*;
+    use crate::engine::EngineConfig;
+
+    const FIRST_FILE: &str = "type, client, tx, amount
+deposit, 1, 1, 10.0
+deposit, 2, 2, 20.0
+deposit, 3, 3, 30.0
+withdrawal, 1, 4, 2.5
+dispute, 2, 2,
+";
+
+    const SECOND_FILE: &str = "type, client, tx, amount
+deposit, 4, 5, 40.0
+withdrawal, 3, 6, 35.0
+chargeback, 2, 2,
+deposit, 1, 7, 1.5
+dispute, 1, 1,
+resolve, 1, 1,
+deposit, 2, 8, 5.0
+";
+
+    fn write_file(contents: &str) -> NamedTempFile {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+        file
+    }
+
+    #[test]
+    fn test_sharded_matches_sequential() {
+        let first = write_file(FIRST_FILE);
+        let second = write_file(SECOND_FILE);
+        let concatenated = write_file(&format!(
+            "{FIRST_FILE}{}",
+            SECOND_FILE.split_once('\n').unwrap().1
+        ));
+
+        let mut sequential = Engine::new();
+        for transaction in
+            input::transactions(input::reader_from_path(concatenated.path()).unwrap()).unwrap()
+        {
+            let _ = sequential.process_transaction(transaction.unwrap());
+        }
+
+        for shards in 1..=4 {
+            let sharded =
+                process_files(&[first.path(), second.path()], shards, Engine::new(), false)
+                    .unwrap();
+            assert!(sharded.clients == sequential.clients, "{shards} shards");
+        }
+        assert_eq!(sequential.clients.len(), 4);
+        assert!(sequential.clients[&ClientId(2)].locked);
+    }
+
+    #[test]
+    fn test_reused_ids_rejected_across_shards() {
+        let first =
+            write_file("type, client, tx, amount\ndeposit, 2, 1, 20.0\nwithdrawal, 2, 5, 50.0\n");
+        let second =
+            write_file("type, client, tx, amount\ndeposit, 1, 5, 10.0\ndeposit, 3, 5, 1.0\n");
+        let config = EngineConfig {
+            tx_ids_unique_per_client: false,
+            ..EngineConfig::default()
+        };
+
+        for shards in 1..=2 {
+            let engine = Engine::with_config(config.clone());
+            let sharded =
+                process_files(&[first.path(), second.path()], shards, engine, false).unwrap();
+            // The rejected withdrawal of client 2 does not claim its id.
+            assert_eq!(sharded.available(ClientId(2)), Some(Decimal::new(200, 1)));
+            assert_eq!(sharded.available(ClientId(1)), Some(Decimal::new(100, 1)));
+            assert_eq!(sharded.available(ClientId(3)), None, "{shards} shards");
+        }
+    }
+}
@@ -0,0 +1,262 @@
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Sender},
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::{engine::Engine, error::TransactionError, input::parse_line, transaction::Transaction};
+
+/// Control line that stops the server.
+pub const SHUTDOWN: &str = "SHUTDOWN";
+/// Control line that stops applying transactions until [`RESUME`].
+pub const PAUSE: &str = "PAUSE";
+/// Control line that resumes applying transactions after [`PAUSE`].
+pub const RESUME: &str = "RESUME";
+
+/// How often a paused engine checks whether it was resumed.
+const PAUSE_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Messages sent from the connections to the engine thread.
+enum Request {
+    Transaction(Transaction, Sender<Result<(), TransactionError>>),
+    Shutdown,
+}
+
+/// Accepts connections on `listener` and applies the transactions they send, one per line,
+/// until a client sends [`SHUTDOWN`]. Lines are either CSV records without header or JSON
+/// objects.
+///
+/// Every connection is handled by its own thread, but all transactions go through a single
+/// queue to the engine, so they are applied in the global order they arrived at. If `acks` is
+/// set, every line is answered on the same connection with `ok`, `rejected: <reason>` or
+/// `invalid: <error>`.
+///
+/// Returns the engine once the server is shut down, so the report can be written.
+pub fn serve(listener: TcpListener, engine: Engine, acks: bool) -> io::Result<Engine> {
+    serve_pausable(listener, engine, acks, Arc::new(AtomicBool::new(false)))
+}
+
+/// Like [`serve`], but no transaction is applied while `paused` is set. Transactions sent in
+/// the meantime are queued and applied in order once it is cleared, either by the caller or
+/// by a client sending [`RESUME`]. [`PAUSE`] sets it, and [`SHUTDOWN`] clears it so the
+/// queued transactions are applied before stopping.
+pub fn serve_pausable(
+    listener: TcpListener,
+    mut engine: Engine,
+    acks: bool,
+    paused: Arc<AtomicBool>,
+) -> io::Result<Engine> {
+    let addr = listener.local_addr()?;
+    let shutting_down = Arc::new(AtomicBool::new(false));
+    let (sender, receiver) = mpsc::channel();
+
+    let acceptor = {
+        let shutting_down = Arc::clone(&shutting_down);
+        let paused = Arc::clone(&paused);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if shutting_down.load(Ordering::SeqCst) {
+                    break;
+                }
+                let Ok(stream) = stream else {
+                    continue;
+                };
+                let sender = sender.clone();
+                let paused = Arc::clone(&paused);
+                thread::spawn(move || handle_connection(stream, sender, acks, &paused));
+            }
+        })
+    };
+
+    for request in receiver {
+        // The engine may have been waiting for this request when it was paused.
+        while paused.load(Ordering::SeqCst) {
+            thread::sleep(PAUSE_POLL_INTERVAL);
+        }
+        match request {
+            Request::Transaction(transaction, reply) => {
+                let (client, tx) = (transaction.client, transaction.id);
+                let result = engine.process_transaction(transaction);
+                if let Err(reason) = &result {
+                    tracing::info!(client = client.0, tx = tx.0, %reason, "transaction rejected");
+                }
+                let _ = reply.send(result);
+            }
+            Request::Shutdown => break,
+        }
+    }
+
+    // The acceptor is blocked waiting for a connection, so it is woken up with one.
+    shutting_down.store(true, Ordering::SeqCst);
+    let _ = TcpStream::connect(addr);
+    let _ = acceptor.join();
+
+    Ok(engine)
+}
+
+fn handle_connection(
+    stream: TcpStream,
+    requests: Sender<Request>,
+    acks: bool,
+    paused: &AtomicBool,
+) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match line.trim() {
+            SHUTDOWN => {
+                let _ = requests.send(Request::Shutdown);
+                paused.store(false, Ordering::SeqCst);
+                break;
+            }
+            PAUSE | RESUME => {
+                paused.store(line.trim() == PAUSE, Ordering::SeqCst);
+                continue;
+            }
+            _ => {}
+        }
+
+        let ack = match parse_line(&line) {
+            Ok(transaction) => {
+                let (reply, result) = mpsc::channel();
+                if requests
+                    .send(Request::Transaction(transaction, reply))
+                    .is_err()
+                {
+                    break;
+                }
+                match result.recv() {
+                    Ok(Ok(())) => "ok".to_string(),
+                    Ok(Err(reason)) => format!("rejected: {reason}"),
+                    // The engine stopped before processing this transaction.
+                    Err(_) => break,
+                }
+            }
+            Err(err) => format!("invalid: {err}"),
+        };
+
+        if acks {
+            writeln!(writer, "{ack}")?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use crate::{
+        output::{ReportOptions, write_report},
+        transaction::ClientId,
+    };
+
+    use super::*;
+
+    fn send(stream: &mut BufReader<TcpStream>, line: &str) -> String {
+        writeln!(stream.get_mut(), "{line}").unwrap();
+        let mut ack = String::new();
+        stream.read_line(&mut ack).unwrap();
+        ack.trim_end().to_string()
+    }
+
+    #[test]
+    fn test_interleaved_connections() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || serve(listener, Engine::new(), true).unwrap());
+
+        let mut first = BufReader::new(TcpStream::connect(addr).unwrap());
+        let mut second = BufReader::new(TcpStream::connect(addr).unwrap());
+
+        assert_eq!(send(&mut first, "deposit, 1, 1, 10.0"), "ok");
+        assert_eq!(
+            send(
+                &mut second,
+                r#"{"type": "deposit", "client": 2, "tx": 2, "amount": "5.5"}"#
+            ),
+            "ok"
+        );
+        assert_eq!(send(&mut second, "dispute, 1, 1"), "ok");
+        assert_eq!(
+            send(&mut first, "withdrawal, 2, 3, 6.0"),
+            "rejected: insufficient available funds, requested 6 with 5.5 available"
+        );
+        assert!(send(&mut first, "refund, 1, 4, 1.0").starts_with("invalid: "));
+        assert_eq!(send(&mut second, "withdrawal, 2, 5, 0.5"), "ok");
+        writeln!(first.get_mut(), "{SHUTDOWN}").unwrap();
+
+        let engine = server.join().unwrap();
+        assert_eq!(engine.clients[&ClientId(1)].held, Decimal::new(10, 0));
+
+        let mut report = Vec::new();
+        write_report(&engine, &mut report, &ReportOptions::default()).unwrap();
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "client,available,held,total,locked
+1,0.0000,10.0000,10.0000,false
+2,5.0000,0.0000,5.0000,false
+"
+        );
+    }
+
+    #[test]
+    fn test_pause() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let paused = Arc::new(AtomicBool::new(true));
+        let server = {
+            let paused = Arc::clone(&paused);
+            thread::spawn(move || serve_pausable(listener, Engine::new(), true, paused).unwrap())
+        };
+
+        // While paused, transactions are queued but not acknowledged.
+        let mut first = BufReader::new(TcpStream::connect(addr).unwrap());
+        let mut second = TcpStream::connect(addr).unwrap();
+        writeln!(second, "deposit, 2, 2, 1.0").unwrap();
+        first
+            .get_ref()
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        writeln!(first.get_mut(), "deposit, 1, 1, 10.0").unwrap();
+        let mut ack = String::new();
+        assert!(first.read_line(&mut ack).is_err());
+
+        paused.store(false, Ordering::SeqCst);
+        first.get_ref().set_read_timeout(None).unwrap();
+        first.read_line(&mut ack).unwrap();
+        assert_eq!(ack, "ok\n");
+
+        writeln!(first.get_mut(), "{PAUSE}").unwrap();
+        writeln!(second, "withdrawal, 2, 3, 0.5").unwrap();
+        writeln!(first.get_mut(), "{RESUME}").unwrap();
+        assert_eq!(send(&mut first, "withdrawal, 1, 4, 2.5"), "ok");
+        writeln!(first.get_mut(), "{PAUSE}").unwrap();
+        writeln!(first.get_mut(), "deposit, 3, 5, 4.0").unwrap();
+        thread::sleep(Duration::from_millis(50));
+        writeln!(second, "{SHUTDOWN}").unwrap();
+
+        // Nothing queued while paused is lost.
+        let engine = server.join().unwrap();
+        let available: Vec<_> = engine
+            .output()
+            .iter()
+            .map(|output| output.available)
+            .collect();
+        assert_eq!(
+            available,
+            [Decimal::new(75, 1), Decimal::new(5, 1), Decimal::new(4, 0)]
+        );
+    }
+}
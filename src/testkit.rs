@@ -0,0 +1,328 @@
+//! Builders of transactions and assertions on balances, for the tests of the crate and of
+//! its users.
+//!
+//! ```
+//! use payments::{assert_balances, testkit::{TxSeq, engine_from}};
+//!
+//! let engine = engine_from(
+//!     TxSeq::new()
+//!         .deposit(1, 1, "25.0")
+//!         .dispute(1, 1)
+//!         .chargeback(1, 1),
+//! );
+//! assert_balances!(engine, client: 1, available: "0", held: "0", locked: true);
+//! ```
+
+use std::fmt;
+
+use rust_decimal::Decimal;
+
+use crate::{
+    account::Account,
+    engine::{Engine, EngineConfig, EngineOutput},
+    transaction::{ClientId, Transaction, TransactionId, TransactionKind},
+};
+
+/// Parses an amount such as `"25.0"`, panicking if it is not a decimal.
+pub fn amount(value: &str) -> Decimal {
+    value
+        .parse()
+        .unwrap_or_else(|err| panic!("invalid amount {value:?}: {err}"))
+}
+
+/// Transaction `tx` of `client`, without timestamp, memo, currency nor tenant. Fields can
+/// be set with the struct update syntax, e.g.
+/// `Transaction { timestamp: Some(at), ..deposit(1, 1, "10") }`.
+pub fn transaction(client: u16, tx: u32, kind: TransactionKind) -> Transaction {
+    Transaction {
+        client: ClientId(client),
+        kind,
+        id: TransactionId(tx),
+        timestamp: None,
+        memo: None,
+        currency: None,
+        tenant: None,
+    }
+}
+
+pub fn deposit(client: u16, tx: u32, value: &str) -> Transaction {
+    let amount = amount(value);
+    transaction(client, tx, TransactionKind::Deposit { amount })
+}
+
+pub fn withdrawal(client: u16, tx: u32, value: &str) -> Transaction {
+    let amount = amount(value);
+    transaction(client, tx, TransactionKind::Withdrawal { amount })
+}
+
+/// Dispute of the whole transaction `tx`.
+pub fn dispute(client: u16, tx: u32) -> Transaction {
+    transaction(client, tx, TransactionKind::Dispute { amount: None })
+}
+
+/// Dispute of `value` of the transaction `tx`.
+pub fn partial_dispute(client: u16, tx: u32, value: &str) -> Transaction {
+    let amount = Some(amount(value));
+    transaction(client, tx, TransactionKind::Dispute { amount })
+}
+
+/// Resolve releasing everything the dispute of `tx` holds.
+pub fn resolve(client: u16, tx: u32) -> Transaction {
+    transaction(client, tx, TransactionKind::Resolve { amount: None })
+}
+
+/// Resolve releasing `value` of what the dispute of `tx` holds.
+pub fn partial_resolve(client: u16, tx: u32, value: &str) -> Transaction {
+    let amount = Some(amount(value));
+    transaction(client, tx, TransactionKind::Resolve { amount })
+}
+
+pub fn chargeback(client: u16, tx: u32) -> Transaction {
+    transaction(client, tx, TransactionKind::Chargeback)
+}
+
+/// Sequence of transactions built fluently, e.g.
+/// `TxSeq::new().deposit(1, 1, "25.0").dispute(1, 1).chargeback(1, 1)`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TxSeq {
+    transactions: Vec<Transaction>,
+}
+
+impl TxSeq {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends any transaction, such as one built with [`transaction`].
+    pub fn push(mut self, transaction: Transaction) -> Self {
+        self.transactions.push(transaction);
+        self
+    }
+
+    pub fn deposit(self, client: u16, tx: u32, value: &str) -> Self {
+        self.push(deposit(client, tx, value))
+    }
+
+    pub fn withdrawal(self, client: u16, tx: u32, value: &str) -> Self {
+        self.push(withdrawal(client, tx, value))
+    }
+
+    pub fn dispute(self, client: u16, tx: u32) -> Self {
+        self.push(dispute(client, tx))
+    }
+
+    pub fn partial_dispute(self, client: u16, tx: u32, value: &str) -> Self {
+        self.push(partial_dispute(client, tx, value))
+    }
+
+    pub fn resolve(self, client: u16, tx: u32) -> Self {
+        self.push(resolve(client, tx))
+    }
+
+    pub fn partial_resolve(self, client: u16, tx: u32, value: &str) -> Self {
+        self.push(partial_resolve(client, tx, value))
+    }
+
+    pub fn chargeback(self, client: u16, tx: u32) -> Self {
+        self.push(chargeback(client, tx))
+    }
+
+    /// Transactions of the sequence, in order.
+    pub fn build(self) -> Vec<Transaction> {
+        self.transactions
+    }
+}
+
+impl IntoIterator for TxSeq {
+    type Item = Transaction;
+    type IntoIter = std::vec::IntoIter<Transaction>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.transactions.into_iter()
+    }
+}
+
+/// Engine with the default config that processed `seq`, panicking if a transaction is
+/// rejected.
+pub fn engine_from(seq: TxSeq) -> Engine {
+    let mut engine = Engine::new();
+    for transaction in seq {
+        let description = format!("{transaction:?}");
+        if let Err(err) = engine.process_transaction(transaction) {
+            panic!("{description} was rejected: {err}");
+        }
+    }
+    engine
+}
+
+/// Account of a single client that processed `seq` with the default config, panicking if a
+/// transaction is rejected. Like [`Account::process_transaction`], transactions of a locked
+/// account are ignored.
+pub fn account_from(seq: TxSeq) -> Account {
+    account_from_with(seq, &EngineConfig::default())
+}
+
+/// Like [`account_from`], with `config`.
+pub fn account_from_with(seq: TxSeq, config: &EngineConfig) -> Account {
+    let mut account = Account::new(Decimal::ZERO);
+    for transaction in seq {
+        let description = format!("{transaction:?}");
+        if let Err(err) = account.process_transaction_with(transaction, config) {
+            panic!("{description} was rejected: {err}");
+        }
+    }
+    account
+}
+
+/// Balances compared by [`assert_balances!`], in the implicit currency.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Balances {
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    /// If the account is locked by a chargeback or frozen.
+    pub locked: bool,
+}
+
+impl Balances {
+    pub fn of_account(account: &Account) -> Self {
+        Self::from(EngineOutput::new(ClientId(0), account))
+    }
+
+    /// Balances of `client` in `engine`, panicking if it has no account.
+    pub fn of_client(engine: &Engine, client: u16) -> Self {
+        let output = engine
+            .account_output(ClientId(client))
+            .unwrap_or_else(|| panic!("client {client} has no account"));
+        Self::from(output)
+    }
+}
+
+impl From<EngineOutput> for Balances {
+    fn from(output: EngineOutput) -> Self {
+        Self {
+            available: output.available,
+            held: output.held,
+            total: output.total,
+            locked: output.locked,
+        }
+    }
+}
+
+/// Expected value of a field of [`Balances`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expected {
+    Amount(Decimal),
+    Flag(bool),
+}
+
+impl From<&str> for Expected {
+    fn from(value: &str) -> Self {
+        Expected::Amount(amount(value))
+    }
+}
+
+impl From<Decimal> for Expected {
+    fn from(value: Decimal) -> Self {
+        Expected::Amount(value)
+    }
+}
+
+impl From<bool> for Expected {
+    fn from(value: bool) -> Self {
+        Expected::Flag(value)
+    }
+}
+
+impl fmt::Display for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expected::Amount(amount) => write!(f, "{amount}"),
+            Expected::Flag(flag) => write!(f, "{flag}"),
+        }
+    }
+}
+
+/// Compares the named fields of `actual` to the expected values, panicking with every
+/// field that differs. Used by [`assert_balances!`].
+#[track_caller]
+pub fn check_balances(subject: &str, actual: Balances, expected: &[(&str, Expected)]) {
+    let mut differences = String::new();
+    for (field, expected) in expected {
+        let value = match *field {
+            "available" => Expected::Amount(actual.available),
+            "held" => Expected::Amount(actual.held),
+            "total" => Expected::Amount(actual.total),
+            "locked" => Expected::Flag(actual.locked),
+            _ => panic!("unknown balance {field:?}, expected available, held, total or locked"),
+        };
+        if value != *expected {
+            differences.push_str(&format!("\n  {field}: expected {expected}, got {value}"));
+        }
+    }
+    if !differences.is_empty() {
+        panic!("balances of {subject} differ:{differences}\nactual: {actual:?}");
+    }
+}
+
+/// Asserts the balances of an account, or of a client of an engine, comparing only the
+/// fields given among `available`, `held`, `total` and `locked`. Amounts can be strings or
+/// decimals. On failure, every differing field is listed with its expected and actual value.
+///
+/// ```
+/// # use payments::{assert_balances, testkit::{TxSeq, account_from, engine_from}};
+/// let seq = TxSeq::new().deposit(1, 1, "10").dispute(1, 1);
+/// assert_balances!(engine_from(seq.clone()), client: 1, available: "0", held: "10");
+/// assert_balances!(account_from(seq), total: "10", locked: false);
+/// ```
+#[macro_export]
+macro_rules! assert_balances {
+    ($engine:expr, client: $client:expr $(, $field:ident: $value:expr)+ $(,)?) => {
+        $crate::testkit::check_balances(
+            &format!("client {}", $client),
+            $crate::testkit::Balances::of_client(&$engine, $client),
+            &[$((stringify!($field), $crate::testkit::Expected::from($value))),+],
+        )
+    };
+    ($account:expr $(, $field:ident: $value:expr)+ $(,)?) => {
+        $crate::testkit::check_balances(
+            "account",
+            $crate::testkit::Balances::of_account(&$account),
+            &[$((stringify!($field), $crate::testkit::Expected::from($value))),+],
+        )
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tx_seq() {
+        let seq = TxSeq::new()
+            .deposit(1, 1, "25.0")
+            .deposit(2, 2, "5")
+            .withdrawal(2, 3, "1.5")
+            .dispute(1, 1)
+            .chargeback(1, 1);
+        assert_eq!(seq.clone().build().len(), 5);
+        assert_eq!(seq.clone().build()[0], deposit(1, 1, "25"));
+
+        let engine = engine_from(seq);
+        assert_balances!(engine, client: 1, available: "0", held: "0", total: "0", locked: true);
+        assert_balances!(engine, client: 2, available: "3.5", locked: false);
+    }
+
+    #[test]
+    #[should_panic(expected = "balances of client 1 differ:\n  held: expected 1, got 10")]
+    fn test_assert_balances_diff() {
+        let engine = engine_from(TxSeq::new().deposit(1, 1, "10").dispute(1, 1));
+        assert_balances!(engine, client: 1, available: "0", held: "1");
+    }
+
+    #[test]
+    #[should_panic(expected = "was rejected: insufficient available funds")]
+    fn test_rejected_fixture() {
+        account_from(TxSeq::new().withdrawal(1, 1, "10"));
+    }
+}
@@ -0,0 +1,115 @@
+//! Generation of transaction streams for tests and benchmarks.
+
+use rust_decimal::Decimal;
+
+use crate::transaction::{ClientId, Transaction, TransactionId, TransactionKind};
+
+/// Generates `n` transactions of clients `1..=clients`, the same ones for the same `seed`.
+///
+/// Most are deposits and withdrawals of up to 1000 with 4 decimal places, some of which
+/// fail for insufficient funds. About `dispute_ratio` of them dispute an earlier deposit of
+/// the client, and as many close an open dispute, mostly by a resolve and otherwise by a
+/// chargeback. Disputes still open at the end stay open.
+pub fn gen_transactions(seed: u64, n: usize, clients: u16, dispute_ratio: f64) -> Vec<Transaction> {
+    assert!(clients > 0, "at least one client is needed");
+
+    let mut rng = Rng(seed);
+    let mut transactions = Vec::with_capacity(n);
+    let mut deposits: Vec<(ClientId, TransactionId)> = Vec::new();
+    let mut disputed: Vec<(ClientId, TransactionId)> = Vec::new();
+    let mut next_id = 1;
+
+    while transactions.len() < n {
+        let roll = rng.next_f64();
+        let (client, id, kind) = if roll < dispute_ratio && !deposits.is_empty() {
+            let (client, id) = deposits.swap_remove(rng.below(deposits.len()));
+            disputed.push((client, id));
+            (client, id, TransactionKind::Dispute { amount: None })
+        } else if roll < 2.0 * dispute_ratio && !disputed.is_empty() {
+            let (client, id) = disputed.swap_remove(rng.below(disputed.len()));
+            let kind = if rng.below(3) == 0 {
+                TransactionKind::Chargeback
+            } else {
+                TransactionKind::Resolve { amount: None }
+            };
+            (client, id, kind)
+        } else {
+            let client = ClientId(rng.below(clients.into()) as u16 + 1);
+            let id = TransactionId(next_id);
+            next_id += 1;
+            // Up to 1000.0000, never zero.
+            let amount = Decimal::new(rng.below(10_000_000) as i64 + 1, 4);
+            if rng.below(10) < 7 {
+                deposits.push((client, id));
+                (client, id, TransactionKind::Deposit { amount })
+            } else {
+                (client, id, TransactionKind::Withdrawal { amount })
+            }
+        };
+        transactions.push(Transaction {
+            client,
+            kind,
+            id,
+            timestamp: None,
+            memo: None,
+            currency: None,
+            tenant: None,
+        });
+    }
+    transactions
+}
+
+/// SplitMix64 generator, small and with a stable output for a seed, unlike the generators
+/// of the standard library.
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform value in `[0, 1)`.
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Value in `[0, bound)`, with a negligible bias for the small bounds used here.
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+
+    #[test]
+    fn test_deterministic() {
+        let transactions = gen_transactions(42, 1_000, 20, 0.05);
+        assert_eq!(transactions.len(), 1_000);
+        assert_eq!(transactions, gen_transactions(42, 1_000, 20, 0.05));
+        assert_ne!(transactions, gen_transactions(43, 1_000, 20, 0.05));
+
+        assert!(
+            transactions
+                .iter()
+                .any(|tx| tx.kind == TransactionKind::Dispute { amount: None })
+        );
+        assert!(
+            transactions
+                .iter()
+                .all(|tx| (1..=20).contains(&tx.client.0))
+        );
+        let mut engine = Engine::new();
+        for transaction in transactions {
+            // Rejected transactions, such as withdrawals without funds, are expected.
+            let _ = engine.process_transaction(transaction);
+        }
+        assert!(engine.clients.len() <= 20);
+    }
+}
@@ -1,40 +1,340 @@
+use std::fmt;
+
+use chrono::{DateTime, Utc};
 use rust_decimal::Decimal;
-use serde::{Deserialize, Serialize};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, de::Error};
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
-#[serde(transparent)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize), serde(transparent))]
 pub struct ClientId(pub u16);
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
-#[serde(transparent)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize), serde(transparent))]
 pub struct TransactionId(pub u32);
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
-#[serde(tag = "type", rename_all = "lowercase")]
+/// Currency code of a transaction, such as `USD`.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(transparent))]
+pub struct Currency(pub String);
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Tenant owning a client account, such as a merchant of a payment platform. The same client
+/// id in two tenants are two separate accounts.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(transparent))]
+pub struct Tenant(pub String);
+
+impl fmt::Display for Tenant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for ClientId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_id(deserializer, "client id", u16::MAX.into()).map(ClientId)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for TransactionId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_id(deserializer, "tx id", u32::MAX.into()).map(TransactionId)
+    }
+}
+
+/// Deserializes an id up to `max`, reporting the value if it is out of range instead of the
+/// generic serde error.
+#[cfg(feature = "serde")]
+fn deserialize_id<'de, D, T>(deserializer: D, name: &str, max: u64) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: TryFrom<u64>,
+{
+    let id = u64::deserialize(deserializer)?;
+    T::try_from(id).map_err(|_| D::Error::custom(format!("{name} out of range (max {max}): {id}")))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(
+        tag = "type",
+        rename_all = "lowercase",
+        try_from = "RawTransactionKind"
+    )
+)]
 pub enum TransactionKind {
     /// A credit to a client's asset account from an external source.
     Deposit { amount: Decimal },
     /// A debit from a client's asset account to an external destination.
     Withdrawal { amount: Decimal },
     /// Claim that a previously processed transaction (specifically a deposit) was
-    /// erroneous or fraudulent and should be reversed.
-    Dispute,
+    /// erroneous or fraudulent and should be reversed. If an amount is given, only that part
+    /// of the transaction is disputed and held.
+    Dispute { amount: Option<Decimal> },
     /// A resolution to an ongoing dispute, indicating that the disputed transaction
-    /// was valid.
-    Resolve,
+    /// was valid. If an amount is given, only that part of the held funds is released.
+    Resolve { amount: Option<Decimal> },
     /// The final state of a dispute, representing a reversal of the original transaction.
     Chargeback,
+    /// Reversal of the chargeback of a transaction, e.g. after winning a pre-arbitration
+    /// case. The charged back funds are made available again, and the account is unlocked
+    /// if this chargeback locked it.
+    #[cfg_attr(feature = "serde", serde(rename = "chargeback_reversal"))]
+    ChargebackReversal,
+    /// Recall of a deposit by the depositing counterparty, e.g. a duplicate funding,
+    /// debiting its amount without a dispute nor locking the account. A refunded deposit
+    /// cannot be disputed anymore.
+    Refund,
+    /// Reversal of a withdrawal that bounced, crediting its amount back. Only accepted once
+    /// per withdrawal, within [`crate::engine::EngineConfig::withdrawal_reversal_window`].
+    #[cfg_attr(feature = "serde", serde(rename = "withdrawal_reversal"))]
+    WithdrawalReversal,
+    /// Escalation of an open dispute to the stage where evidence is requested.
+    #[cfg_attr(feature = "serde", serde(rename = "request_evidence"))]
+    RequestEvidence,
+    /// Escalation of an open dispute to pre-arbitration.
+    #[cfg_attr(feature = "serde", serde(rename = "pre_arbitration"))]
+    PreArbitration,
+    /// Escalation of an open dispute to arbitration, after which it can only be charged
+    /// back.
+    Arbitration,
+    /// Administrative operation unfreezing an account locked by a chargeback. Only
+    /// accepted with [`crate::engine::EngineConfig::allow_admin_ops`].
+    Unlock,
+    /// Administrative operation freezing an account pending an investigation, which then
+    /// rejects deposits and withdrawals. Only accepted with
+    /// [`crate::engine::EngineConfig::allow_admin_ops`].
+    Freeze,
+    /// Administrative operation lifting a [`TransactionKind::Freeze`]. It does not unlock an
+    /// account locked by a chargeback.
+    Unfreeze,
+}
+
+impl TransactionKind {
+    /// Name of the kind, as in the `type` column.
+    pub fn name(&self) -> &'static str {
+        match self {
+            TransactionKind::Deposit { .. } => "deposit",
+            TransactionKind::Withdrawal { .. } => "withdrawal",
+            TransactionKind::Dispute { .. } => "dispute",
+            TransactionKind::Resolve { .. } => "resolve",
+            TransactionKind::Chargeback => "chargeback",
+            TransactionKind::ChargebackReversal => "chargeback_reversal",
+            TransactionKind::Refund => "refund",
+            TransactionKind::WithdrawalReversal => "withdrawal_reversal",
+            TransactionKind::RequestEvidence => "request_evidence",
+            TransactionKind::PreArbitration => "pre_arbitration",
+            TransactionKind::Arbitration => "arbitration",
+            TransactionKind::Unlock => "unlock",
+            TransactionKind::Freeze => "freeze",
+            TransactionKind::Unfreeze => "unfreeze",
+        }
+    }
+
+    /// If the kind is an administrative operation rather than a payment.
+    pub fn is_admin_op(&self) -> bool {
+        matches!(
+            self,
+            TransactionKind::Unlock | TransactionKind::Freeze | TransactionKind::Unfreeze
+        )
+    }
+
+    /// Amount carried by the transaction, if any.
+    pub fn amount(&self) -> Option<Decimal> {
+        match *self {
+            TransactionKind::Deposit { amount } | TransactionKind::Withdrawal { amount } => {
+                Some(amount)
+            }
+            TransactionKind::Dispute { amount } | TransactionKind::Resolve { amount } => amount,
+            TransactionKind::Chargeback
+            | TransactionKind::ChargebackReversal
+            | TransactionKind::Refund
+            | TransactionKind::WithdrawalReversal
+            | TransactionKind::RequestEvidence
+            | TransactionKind::PreArbitration
+            | TransactionKind::Arbitration
+            | TransactionKind::Unlock
+            | TransactionKind::Freeze
+            | TransactionKind::Unfreeze => None,
+        }
+    }
+}
+
+/// Type and amount columns as read, before checking that they match.
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct RawTransactionKind {
+    #[serde(rename = "type")]
+    name: KindName,
+    #[serde(default, deserialize_with = "optional_amount")]
+    amount: Option<Decimal>,
+}
+
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum KindName {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    #[serde(rename = "chargeback_reversal")]
+    ChargebackReversal,
+    Refund,
+    #[serde(rename = "withdrawal_reversal")]
+    WithdrawalReversal,
+    #[serde(rename = "request_evidence")]
+    RequestEvidence,
+    #[serde(rename = "pre_arbitration")]
+    PreArbitration,
+    Arbitration,
+    Unlock,
+    Freeze,
+    Unfreeze,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<RawTransactionKind> for TransactionKind {
+    type Error = String;
+
+    fn try_from(raw: RawTransactionKind) -> Result<Self, Self::Error> {
+        let kind = match (raw.name, raw.amount) {
+            (KindName::Deposit, Some(amount)) => TransactionKind::Deposit { amount },
+            (KindName::Withdrawal, Some(amount)) => TransactionKind::Withdrawal { amount },
+            (KindName::Resolve, amount) => TransactionKind::Resolve { amount },
+            (KindName::Dispute, amount) => TransactionKind::Dispute { amount },
+            (KindName::Chargeback, None) => TransactionKind::Chargeback,
+            (KindName::ChargebackReversal, None) => TransactionKind::ChargebackReversal,
+            (KindName::Refund, None) => TransactionKind::Refund,
+            (KindName::WithdrawalReversal, None) => TransactionKind::WithdrawalReversal,
+            (KindName::RequestEvidence, None) => TransactionKind::RequestEvidence,
+            (KindName::PreArbitration, None) => TransactionKind::PreArbitration,
+            (KindName::Arbitration, None) => TransactionKind::Arbitration,
+            (KindName::Unlock, None) => TransactionKind::Unlock,
+            (KindName::Freeze, None) => TransactionKind::Freeze,
+            (KindName::Unfreeze, None) => TransactionKind::Unfreeze,
+            (KindName::Deposit, None) => return Err("deposit requires an amount".to_string()),
+            (KindName::Withdrawal, None) => {
+                return Err("withdrawal requires an amount".to_string());
+            }
+            (KindName::Chargeback, Some(_)) => {
+                return Err("chargeback must not have an amount".to_string());
+            }
+            (KindName::ChargebackReversal, Some(_)) => {
+                return Err("chargeback_reversal must not have an amount".to_string());
+            }
+            (KindName::Refund, Some(_)) => {
+                return Err("refund must not have an amount".to_string());
+            }
+            (KindName::WithdrawalReversal, Some(_)) => {
+                return Err("withdrawal_reversal must not have an amount".to_string());
+            }
+            (KindName::RequestEvidence, Some(_)) => {
+                return Err("request_evidence must not have an amount".to_string());
+            }
+            (KindName::PreArbitration, Some(_)) => {
+                return Err("pre_arbitration must not have an amount".to_string());
+            }
+            (KindName::Arbitration, Some(_)) => {
+                return Err("arbitration must not have an amount".to_string());
+            }
+            (KindName::Unlock, Some(_)) => {
+                return Err("unlock must not have an amount".to_string());
+            }
+            (KindName::Freeze, Some(_)) => {
+                return Err("freeze must not have an amount".to_string());
+            }
+            (KindName::Unfreeze, Some(_)) => {
+                return Err("unfreeze must not have an amount".to_string());
+            }
+        };
+        Ok(kind)
+    }
+}
+
+/// Deserializes an optional amount, where an empty CSV field means no amount.
+#[cfg(feature = "serde")]
+fn optional_amount<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Decimal>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Field {
+        Amount(Decimal),
+        Text(String),
+    }
+
+    match Option::<Field>::deserialize(deserializer)? {
+        Some(Field::Amount(amount)) => Ok(Some(amount)),
+        Some(Field::Text(text)) if !text.is_empty() => {
+            Err(D::Error::custom(format!("invalid amount {text:?}")))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Deserializes an optional timestamp, either RFC 3339 text or a number of seconds since the
+/// Unix epoch. An empty CSV field means no timestamp.
+#[cfg(feature = "serde")]
+fn optional_timestamp<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<DateTime<Utc>>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Field {
+        Epoch(i64),
+        Text(String),
+    }
+
+    match Option::<Field>::deserialize(deserializer)? {
+        Some(Field::Epoch(seconds)) => DateTime::from_timestamp(seconds, 0)
+            .map(Some)
+            .ok_or_else(|| D::Error::custom(format!("timestamp {seconds} is out of range"))),
+        Some(Field::Text(text)) if !text.is_empty() => DateTime::parse_from_rfc3339(&text)
+            .map(|timestamp| Some(timestamp.to_utc()))
+            .map_err(|err| D::Error::custom(format!("invalid timestamp {text:?}: {err}"))),
+        _ => Ok(None),
+    }
 }
 
 /// Record of a financial operation performed on a client's asset account.
 /// A transaction represent immutable historical events.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Transaction {
-    #[serde(flatten)]
+    #[cfg_attr(feature = "serde", serde(flatten))]
     pub kind: TransactionKind,
     pub client: ClientId,
-    #[serde(rename = "tx")]
+    #[cfg_attr(feature = "serde", serde(rename = "tx"))]
     pub id: TransactionId,
+    /// When the transaction happened, if the input provides it.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, deserialize_with = "optional_timestamp")
+    )]
+    pub timestamp: Option<DateTime<Utc>>,
+    /// Free-form note attached by the payment processor, if any.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub memo: Option<String>,
+    /// Currency of the amount, or of the disputed deposit for dispute transactions.
+    /// Transactions without one are in the implicit currency.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub currency: Option<Currency>,
+    /// Tenant of the client account. Transactions without one are processed as if there
+    /// were no tenants.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub tenant: Option<Tenant>,
 }
 
 impl Transaction {
@@ -43,10 +343,26 @@ impl Transaction {
         let kind = self.kind;
         matches!(
             kind,
-            TransactionKind::Dispute | TransactionKind::Resolve | TransactionKind::Chargeback
+            TransactionKind::Dispute { .. }
+                | TransactionKind::Resolve { .. }
+                | TransactionKind::Chargeback
+                | TransactionKind::ChargebackReversal
+                | TransactionKind::RequestEvidence
+                | TransactionKind::PreArbitration
+                | TransactionKind::Arbitration
         )
     }
 
+    /// If this transaction references an earlier transaction by its id, like the dispute
+    /// process, refunds and withdrawal reversals.
+    pub fn references_transaction(&self) -> bool {
+        self.belongs_to_dispute()
+            || matches!(
+                self.kind,
+                TransactionKind::Refund | TransactionKind::WithdrawalReversal
+            )
+    }
+
     /// Amount, if the operation is a deposit.
     pub fn deposit_amount(&self) -> Option<Decimal> {
         let TransactionKind::Deposit { amount } = self.kind else {
@@ -60,50 +376,256 @@ impl Transaction {
         match self.kind {
             TransactionKind::Deposit { amount } => amount > Decimal::ZERO,
             TransactionKind::Withdrawal { amount } => amount > Decimal::ZERO,
+            TransactionKind::Dispute {
+                amount: Some(amount),
+            }
+            | TransactionKind::Resolve {
+                amount: Some(amount),
+            } => amount > Decimal::ZERO,
             _ => true,
         }
     }
 }
 
-#[derive(PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum DisputeState {
     /// Initial state of a dispute.
     Disputed,
+    /// Evidence was requested, the dispute being still open.
+    EvidenceRequested,
+    /// The dispute went to pre-arbitration, still open.
+    PreArbitration,
+    /// The dispute went to arbitration, still open but only to a chargeback.
+    Arbitration,
     /// The dispute was resolved and held funds were made available
     /// again for the client.
     Resolved,
     /// The dispute was finished with a chargeback, withdrawing
     /// from the client.
     ChargedBack,
+    /// The dispute was resolved by the engine because it stayed open for too long, and
+    /// cannot be resolved nor charged back anymore.
+    AutoResolved,
+    /// The chargeback of the dispute was reversed, making the charged back funds available
+    /// again for the client.
+    Reversed,
+}
+
+impl DisputeState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DisputeState::Disputed => "disputed",
+            DisputeState::EvidenceRequested => "evidence_requested",
+            DisputeState::PreArbitration => "pre_arbitration",
+            DisputeState::Arbitration => "arbitration",
+            DisputeState::Resolved => "resolved",
+            DisputeState::ChargedBack => "charged_back",
+            DisputeState::AutoResolved => "auto_resolved",
+            DisputeState::Reversed => "reversed",
+        }
+    }
+
+    /// Position of an open stage in the escalation order, `None` for the final states.
+    fn open_stage(&self) -> Option<u8> {
+        match self {
+            DisputeState::Disputed => Some(0),
+            DisputeState::EvidenceRequested => Some(1),
+            DisputeState::PreArbitration => Some(2),
+            DisputeState::Arbitration => Some(3),
+            DisputeState::Resolved
+            | DisputeState::ChargedBack
+            | DisputeState::AutoResolved
+            | DisputeState::Reversed => None,
+        }
+    }
+}
+
+impl fmt::Display for DisputeState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
 }
 
 /// A dispute is a claim that a previously processed transaction (specifically a deposit)
 /// was erroneous or fraudulent and should be reversed.
 /// A dispute references the original transaction by ID and can be followed by either a
 /// resolve (releasing the held funds back to available) or a chargeback (removing the held
-/// funds and freezing the account).
-#[derive(PartialEq, Eq)]
+/// funds and freezing the account). In between, it can be escalated through the open stages
+/// of [`DisputeState`], which keep the funds held.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Dispute {
     state: DisputeState,
+    /// Every state the dispute went through, the current one last.
+    history: Vec<DisputeState>,
+    /// Amount of the disputed transaction.
+    amount: Decimal,
+    /// Part of the disputed amount still held, which decreases on partial resolves.
+    held: Decimal,
+    /// Part of the disputed amount that could not be charged back because it had
+    /// already been released.
+    shortfall: Decimal,
+    /// Timestamp of the dispute transaction, if known.
+    opened_at: Option<DateTime<Utc>>,
+    /// Number of transactions the account had processed when the dispute was opened.
+    opened_after: u64,
+    /// Currency of the disputed deposit, `None` being the implicit currency.
+    currency: Option<Currency>,
+    /// If the disputed transaction is a withdrawal rather than a deposit.
+    of_withdrawal: bool,
 }
 
 impl Dispute {
-    pub fn new() -> Self {
+    /// Opens a dispute, holding the whole `amount` in `currency`. `opened_after` is the
+    /// number of transactions the account had processed.
+    pub fn new(
+        amount: Decimal,
+        currency: Option<Currency>,
+        opened_at: Option<DateTime<Utc>>,
+        opened_after: u64,
+    ) -> Self {
         Self {
             state: DisputeState::Disputed,
+            history: vec![DisputeState::Disputed],
+            amount,
+            held: amount,
+            shortfall: Decimal::ZERO,
+            opened_at,
+            opened_after,
+            currency,
+            of_withdrawal: false,
         }
     }
 
-    /// If we can finish the dispute, either to a resolve or a chargeback.
+    /// Opens a dispute of a withdrawal, like [`Dispute::new`]. Its held funds are credited
+    /// back to the account on a chargeback, and removed on a resolve.
+    pub fn of_withdrawal(
+        amount: Decimal,
+        currency: Option<Currency>,
+        opened_at: Option<DateTime<Utc>>,
+        opened_after: u64,
+    ) -> Self {
+        Self {
+            of_withdrawal: true,
+            ..Self::new(amount, currency, opened_at, opened_after)
+        }
+    }
+
+    /// If the disputed transaction is a withdrawal rather than a deposit.
+    pub fn is_of_withdrawal(&self) -> bool {
+        self.of_withdrawal
+    }
+
+    pub fn state(&self) -> &DisputeState {
+        &self.state
+    }
+
+    /// Every state the dispute went through, from [`DisputeState::Disputed`] to the current
+    /// one.
+    pub fn history(&self) -> &[DisputeState] {
+        &self.history
+    }
+
+    fn set_state(&mut self, state: DisputeState) {
+        self.history.push(state.clone());
+        self.state = state;
+    }
+
+    /// Currency of the disputed deposit, `None` being the implicit currency.
+    pub fn currency(&self) -> Option<&Currency> {
+        self.currency.as_ref()
+    }
+
+    /// Funds currently held by this dispute.
+    pub fn held(&self) -> Decimal {
+        self.held
+    }
+
+    /// Disputed amount that was not held anymore when the dispute was charged back.
+    pub fn shortfall(&self) -> Decimal {
+        self.shortfall
+    }
+
+    /// Funds removed from the account by the chargeback of this dispute, i.e. the disputed
+    /// amount minus the shortfall. Zero if the dispute was not charged back.
+    pub fn charged_back(&self) -> Decimal {
+        match self.state {
+            DisputeState::ChargedBack => self.amount - self.shortfall,
+            _ => Decimal::ZERO,
+        }
+    }
+
+    /// If the dispute is still open and was opened before `cutoff`.
+    /// Disputes without a timestamp never go stale.
+    pub fn is_stale(&self, cutoff: DateTime<Utc>) -> bool {
+        self.can_finish() && self.opened_at.is_some_and(|opened_at| opened_at < cutoff)
+    }
+
+    /// If the dispute is still open and the account processed at least `horizon`
+    /// transactions since it was opened, out of `processed` in total.
+    pub fn is_past_horizon(&self, processed: u64, horizon: u64) -> bool {
+        self.can_finish() && processed - self.opened_after >= horizon
+    }
+
+    /// If the dispute is still open, in any stage, so it can be charged back.
     pub fn can_finish(&self) -> bool {
-        matches!(self.state, DisputeState::Disputed)
+        self.state.open_stage().is_some()
+    }
+
+    /// If the dispute is open in a stage before arbitration, so it can be resolved.
+    pub fn can_resolve(&self) -> bool {
+        self.can_finish() && self.state != DisputeState::Arbitration
+    }
+
+    /// If the open dispute can be escalated to `stage`, i.e. an open stage after its
+    /// current one.
+    pub fn can_escalate(&self, stage: &DisputeState) -> bool {
+        match (self.state.open_stage(), stage.open_stage()) {
+            (Some(current), Some(next)) => next > current,
+            _ => false,
+        }
+    }
+
+    /// Moves the dispute to the open `stage`, keeping its funds held. Check it with
+    /// [`Dispute::can_escalate`] first.
+    pub fn escalate(&mut self, stage: DisputeState) {
+        self.set_state(stage);
+    }
+
+    /// Releases `amount` of the held funds, or all of them if no amount is given. The
+    /// dispute is resolved once nothing is held anymore. Returns the released funds.
+    pub fn resolve(&mut self, amount: Option<Decimal>) -> Decimal {
+        let released = amount.map_or(self.held, |amount| amount.min(self.held));
+        self.held -= released;
+        if self.held.is_zero() {
+            self.set_state(DisputeState::Resolved);
+        }
+        released
+    }
+
+    /// Releases all the held funds, marking the dispute as auto-resolved. Returns the
+    /// released funds.
+    pub fn auto_resolve(&mut self) -> Decimal {
+        let released = self.held;
+        self.held = Decimal::ZERO;
+        self.set_state(DisputeState::AutoResolved);
+        released
     }
 
-    pub fn resolve(&mut self) {
-        self.state = DisputeState::Resolved
+    /// Charges back the funds still held, recording as shortfall the part of the disputed
+    /// amount that was already released. Returns the charged back funds.
+    pub fn chargeback(&mut self) -> Decimal {
+        let charged_back = self.held;
+        self.shortfall = self.amount - charged_back;
+        self.held = Decimal::ZERO;
+        self.set_state(DisputeState::ChargedBack);
+        charged_back
     }
 
-    pub fn chargeback(&mut self) {
-        self.state = DisputeState::ChargedBack
+    /// Reverses the chargeback of the dispute. Returns the funds to make available again,
+    /// i.e. those that were charged back.
+    pub fn reverse_chargeback(&mut self) -> Decimal {
+        let charged_back = self.charged_back();
+        self.set_state(DisputeState::Reversed);
+        charged_back
     }
 }
@@ -0,0 +1,61 @@
+use std::path::Path;
+
+use anyhow::bail;
+
+use crate::{
+    engine::{Engine, EngineConfig},
+    input::{self, reader, reader_from_path},
+    output::{ReportOptions, write_report, write_transactions},
+    transaction::Transaction,
+};
+
+/// Processes `path`, dumps the transactions read from it, processes the dump and checks that
+/// both runs produce the same report. Returns the number of transactions verified.
+pub fn verify(path: &Path, config: &EngineConfig) -> anyhow::Result<usize> {
+    let transactions =
+        input::transactions(reader_from_path(path)?)?.collect::<csv::Result<Vec<_>>>()?;
+
+    let mut dump = Vec::new();
+    write_transactions(&transactions, &mut dump)?;
+    let reloaded =
+        input::transactions(reader(dump.as_slice()))?.collect::<csv::Result<Vec<_>>>()?;
+    if reloaded != transactions {
+        bail!(
+            "the dump of {} does not reload to the same transactions",
+            path.display()
+        );
+    }
+
+    let verified = transactions.len();
+    let original = process(transactions, config);
+    let replayed = process(reloaded, config);
+
+    let mut original_report = Vec::new();
+    write_report(&original, &mut original_report, &ReportOptions::default())?;
+    let mut replayed_report = Vec::new();
+    write_report(&replayed, &mut replayed_report, &ReportOptions::default())?;
+
+    if original_report != replayed_report {
+        let clients: Vec<String> = original
+            .diff(&replayed)
+            .iter()
+            .map(|diff| diff.client.0.to_string())
+            .collect();
+        bail!(
+            "processing the dump of {} does not match the original run, differing clients: {}",
+            path.display(),
+            clients.join(", ")
+        );
+    }
+
+    Ok(verified)
+}
+
+fn process(transactions: Vec<Transaction>, config: &EngineConfig) -> Engine {
+    let mut engine = Engine::with_config(config.clone());
+    for transaction in transactions {
+        // Rejected transactions are ignored.
+        let _ = engine.process_transaction(transaction);
+    }
+    engine
+}
@@ -0,0 +1,212 @@
+use std::{
+    fs::{self, File},
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use anyhow::{Context, bail};
+
+use crate::{
+    engine::Engine,
+    input::{deserialize_record, headerless_reader, is_blank},
+    output::{ReportOptions, write_report},
+};
+
+/// Follows a transactions file that keeps growing, processing only what was appended since
+/// the last poll.
+pub struct Watcher {
+    path: PathBuf,
+    engine: Engine,
+    /// Byte offset right after the last complete line processed.
+    offset: u64,
+    /// Header of the file, read on the first poll that finds a complete line.
+    header: Option<csv::StringRecord>,
+}
+
+impl Watcher {
+    pub fn new(path: impl Into<PathBuf>, engine: Engine) -> Self {
+        Self {
+            path: path.into(),
+            engine,
+            offset: 0,
+            header: None,
+        }
+    }
+
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    /// Processes the complete lines appended since the last poll. A partially written last
+    /// line is left for a later poll, once its newline is there. Returns the number of
+    /// transactions read. If a line is invalid, none of the appended lines are processed.
+    ///
+    /// Fails if the file got shorter, since it was truncated or rotated and the processed
+    /// offset no longer points to the same data.
+    pub fn poll(&mut self) -> anyhow::Result<usize> {
+        let mut file = File::open(&self.path)
+            .with_context(|| format!("failed to open {}", self.path.display()))?;
+        let len = file.metadata()?.len();
+        if len < self.offset {
+            bail!(
+                "{} was truncated or rotated: it has {len} bytes but {} were already processed",
+                self.path.display(),
+                self.offset
+            );
+        }
+
+        file.seek(SeekFrom::Start(self.offset))?;
+        let mut appended = Vec::new();
+        file.read_to_end(&mut appended)?;
+        let Some(last_newline) = appended.iter().rposition(|byte| *byte == b'\n') else {
+            return Ok(0);
+        };
+        let complete = &appended[..=last_newline];
+
+        // The whole chunk is parsed before applying any of it, so an invalid line leaves the
+        // engine and the offset as they were and the next poll does not re-apply anything.
+        let mut header = self.header.clone();
+        let mut transactions = Vec::new();
+        for record in headerless_reader(complete).into_records() {
+            let record = record?;
+            if is_blank(&record) {
+                continue;
+            }
+            match &header {
+                Some(header) => transactions.push(deserialize_record(record, header)?),
+                None => header = Some(record),
+            }
+        }
+
+        self.header = header;
+        let processed = transactions.len();
+        for transaction in transactions {
+            let (client, tx) = (transaction.client, transaction.id);
+            // Rejected transactions are only logged.
+            if let Err(reason) = self.engine.process_transaction(transaction) {
+                tracing::info!(
+                    client = client.0,
+                    tx = tx.0,
+                    %reason,
+                    file = %self.path.display(),
+                    "transaction rejected"
+                );
+            }
+        }
+        self.offset += complete.len() as u64;
+        Ok(processed)
+    }
+}
+
+/// Polls the watched file every `interval`, replacing `output` with the current report
+/// whenever new transactions were processed. Only returns on error.
+pub fn run(
+    mut watcher: Watcher,
+    interval: Duration,
+    output: &Path,
+    options: &ReportOptions,
+) -> anyhow::Result<()> {
+    let mut first = true;
+    loop {
+        if watcher.poll()? > 0 || first {
+            write_report_atomically(watcher.engine(), output, options)?;
+            first = false;
+        }
+        thread::sleep(interval);
+    }
+}
+
+/// Writes the report next to `output` and renames it, so readers never see a partial report.
+fn write_report_atomically(
+    engine: &Engine,
+    output: &Path,
+    options: &ReportOptions,
+) -> anyhow::Result<()> {
+    let mut partial = output.as_os_str().to_owned();
+    partial.push(".partial");
+    write_report(engine, File::create(&partial)?, options)?;
+    fs::rename(&partial, output)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    fn report(watcher: &Watcher) -> String {
+        let mut report = Vec::new();
+        write_report(watcher.engine(), &mut report, &ReportOptions::default()).unwrap();
+        String::from_utf8(report).unwrap()
+    }
+
+    #[test]
+    fn test_appended_in_stages() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        let mut watcher = Watcher::new(file.path(), Engine::new());
+
+        write!(file, "type, client, tx, amount\ndeposit, 1, 1, 10.0\ndepo").unwrap();
+        assert_eq!(watcher.poll().unwrap(), 1);
+        assert_eq!(
+            report(&watcher),
+            "client,available,held,total,locked\n1,10.0000,0.0000,10.0000,false\n"
+        );
+
+        // The partial line is only processed once it is complete.
+        write!(file, "sit, 2, 2, 5.0").unwrap();
+        assert_eq!(watcher.poll().unwrap(), 0);
+        write!(file, "\ndispute, 1, 1,\n").unwrap();
+        assert_eq!(watcher.poll().unwrap(), 2);
+        assert_eq!(
+            report(&watcher),
+            "client,available,held,total,locked
+1,0.0000,10.0000,10.0000,false
+2,5.0000,0.0000,5.0000,false
+"
+        );
+
+        assert_eq!(watcher.poll().unwrap(), 0);
+        write!(file, "resolve, 1, 1,\nwithdrawal, 2, 3, 1.5\n").unwrap();
+        assert_eq!(watcher.poll().unwrap(), 2);
+        assert_eq!(
+            report(&watcher),
+            "client,available,held,total,locked
+1,10.0000,0.0000,10.0000,false
+2,3.5000,0.0000,3.5000,false
+"
+        );
+    }
+
+    #[test]
+    fn test_invalid_line_applies_nothing() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        let mut watcher = Watcher::new(file.path(), Engine::new());
+
+        write!(file, "type, client, tx, amount\ndeposit, 1, 1, 10.0\n").unwrap();
+        assert_eq!(watcher.poll().unwrap(), 1);
+
+        write!(file, "deposit, 1, 2, 5.0\ndeposit, x, 3, 1.0\n").unwrap();
+        assert!(watcher.poll().is_err());
+        assert!(watcher.poll().is_err());
+        assert_eq!(
+            report(&watcher),
+            "client,available,held,total,locked\n1,10.0000,0.0000,10.0000,false\n"
+        );
+    }
+
+    #[test]
+    fn test_truncated_file() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        let mut watcher = Watcher::new(file.path(), Engine::new());
+
+        write!(file, "type, client, tx, amount\ndeposit, 1, 1, 10.0\n").unwrap();
+        assert_eq!(watcher.poll().unwrap(), 1);
+
+        file.as_file().set_len(0).unwrap();
+        let err = watcher.poll().unwrap_err();
+        assert!(err.to_string().contains("was truncated or rotated"));
+    }
+}
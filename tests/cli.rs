@@ -0,0 +1,315 @@
+#![cfg(feature = "cli")]
+
+use std::io::Write;
+
+use assert_cmd::{Command, cargo::cargo_bin_cmd};
+use predicates::str::contains;
+
+fn payments() -> Command {
+    cargo_bin_cmd!("payments")
+}
+
+#[test]
+fn test_basic_sample() {
+    payments()
+        .arg("samples/basic/input.csv")
+        .assert()
+        .success()
+        .stdout(include_str!("../samples/basic/output.csv"));
+}
+
+#[test]
+fn test_disputes_sample() {
+    payments()
+        .arg("samples/disputes/input.csv")
+        .assert()
+        .success()
+        .stdout(include_str!("../samples/disputes/output.csv"));
+}
+
+#[test]
+fn test_verify() {
+    payments()
+        .args(["verify", "samples/disputes/input.csv"])
+        .assert()
+        .success()
+        .stdout(contains("verified 13 transactions"));
+}
+
+#[test]
+fn test_json_logs() {
+    let mut input = tempfile::NamedTempFile::new().unwrap();
+    writeln!(
+        input,
+        "type, client, tx, amount\n\
+         deposit, 1, 1, 5.0\n\
+         withdrawal, 1, 2, 50.0\n\
+         deposit, 2, 3, 1.0\n\
+         withdrawal, 2, 4, 2.0\n\
+         deposit, 2, 5, oops"
+    )
+    .unwrap();
+
+    let output = payments()
+        .arg(input.path())
+        .args(["--log-format", "json", "--log-level", "info"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+
+    let logs: Vec<serde_json::Value> = String::from_utf8(output.stderr)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(logs.len(), 3);
+    let rejections = [
+        (1, 2, 3, "requested 50 with 5 available"),
+        (2, 4, 5, "requested 2 with 1 available"),
+    ];
+    for (log, (client, tx, line, amounts)) in logs.iter().zip(rejections) {
+        assert_eq!(log["level"], "INFO");
+        assert_eq!(log["message"], "transaction rejected");
+        assert_eq!(log["client"], client);
+        assert_eq!(log["tx"], tx);
+        assert_eq!(
+            log["reason"],
+            format!("insufficient available funds, {amounts}")
+        );
+        assert_eq!(log["file"], input.path().display().to_string());
+        assert_eq!(log["line"], line);
+    }
+    assert_eq!(logs[2]["level"], "ERROR");
+    assert_eq!(logs[2]["line"], 6);
+    assert!(
+        logs[2]["reason"]
+            .as_str()
+            .unwrap()
+            .contains("invalid amount")
+    );
+}
+
+#[test]
+fn test_dispute_window() {
+    let mut input = tempfile::NamedTempFile::new().unwrap();
+    writeln!(
+        input,
+        "type, client, tx, amount, timestamp\n\
+         deposit, 1, 1, 5.0, 2024-01-01T00:00:00Z\n\
+         deposit, 1, 2, 3.0, 1704067200\n\
+         dispute, 1, 1, , 2024-03-01T00:00:00Z\n\
+         dispute, 1, 2, , 2024-06-01T00:00:00Z"
+    )
+    .unwrap();
+    payments()
+        .arg(input.path())
+        .args(["--dispute-window", "90d"])
+        .assert()
+        .success()
+        .stdout("client,available,held,total,locked\n1,3.0000,5.0000,8.0000,false\n");
+
+    payments()
+        .args(["samples/basic/input.csv", "--dispute-window", "90d"])
+        .assert()
+        .failure()
+        .stderr(contains(
+            "invalid config: engine.dispute_window requires a timestamp column",
+        ));
+}
+
+#[test]
+fn test_rates() {
+    let mut input = tempfile::NamedTempFile::new().unwrap();
+    writeln!(
+        input,
+        "type, client, tx, amount, currency\n\
+         deposit, 1, 1, 10.0, EUR\n\
+         deposit, 1, 2, 4.0, JPY\n\
+         deposit, 1, 3, oops, EUR"
+    )
+    .unwrap();
+    let mut rates = tempfile::NamedTempFile::new().unwrap();
+    writeln!(rates, "currency, rate\nEUR, 1.1").unwrap();
+
+    // The run fails on the first currency without a rate, before the invalid amount.
+    payments()
+        .arg(input.path())
+        .arg("--rates")
+        .arg(rates.path())
+        .args(["--report-currency", "USD"])
+        .assert()
+        .failure()
+        .stderr(contains("no rate from JPY to USD for line 3"));
+
+    payments()
+        .args(["samples/basic/input.csv", "--report-currency", "USD"])
+        .assert()
+        .failure()
+        .stderr(contains("--rates"));
+}
+
+#[test]
+fn test_risk_report() {
+    let report = tempfile::NamedTempFile::new().unwrap();
+    payments()
+        .arg("samples/disputes/input.csv")
+        .arg("--risk-report")
+        .arg(report.path())
+        .assert()
+        .success()
+        .stdout(include_str!("../samples/disputes/output.csv"));
+    assert_eq!(
+        std::fs::read_to_string(report.path()).unwrap(),
+        "client,pattern,detail\n\
+         2,immediate_dispute,tx 2 disputed 1 row after its deposit\n\
+         3,high_chargeback_rate,1 of 1 deposits charged back\n"
+    );
+}
+
+#[test]
+fn test_mmap() {
+    // A single file, then several files, which are processed in shards.
+    for files in [
+        &["samples/disputes/input.csv"][..],
+        &["samples/basic/input.csv", "samples/disputes/input.csv"],
+    ] {
+        let buffered = payments().args(files).output().unwrap();
+        assert!(buffered.status.success());
+        payments()
+            .args(files)
+            .arg("--mmap")
+            .assert()
+            .success()
+            .stdout(buffered.stdout);
+    }
+}
+
+#[test]
+fn test_tenants() {
+    let mut first = tempfile::NamedTempFile::new().unwrap();
+    writeln!(
+        first,
+        "type, client, tx, amount, tenant\n\
+         deposit, 1, 1, 10.0, acme\n\
+         deposit, 1, 2, 4.0, globex\n\
+         dispute, 1, 1, , globex"
+    )
+    .unwrap();
+    let mut second = tempfile::NamedTempFile::new().unwrap();
+    writeln!(second, "type, client, tx, amount\ndeposit, 2, 3, 1.5").unwrap();
+
+    let report = "\
+tenant,client,available,held,total,locked
+,2,1.5000,0.0000,1.5000,false
+acme,1,10.0000,0.0000,10.0000,false
+globex,1,4.0000,0.0000,4.0000,false
+";
+    // A single file, then several files, which are processed in shards.
+    payments().arg(first.path()).assert().success().stdout(
+        "tenant,client,available,held,total,locked\n\
+             acme,1,10.0000,0.0000,10.0000,false\n\
+             globex,1,4.0000,0.0000,4.0000,false\n",
+    );
+    payments()
+        .arg(first.path())
+        .arg(second.path())
+        .assert()
+        .success()
+        .stdout(report);
+}
+
+#[test]
+fn test_duplicate_files() {
+    payments()
+        .args([
+            "samples/basic/input.csv",
+            "samples/disputes/input.csv",
+            "samples/../samples/basic/input.csv",
+        ])
+        .assert()
+        .failure()
+        .stderr(contains(
+            "samples/basic/input.csv and samples/../samples/basic/input.csv are the same input file",
+        ));
+}
+
+#[test]
+fn test_checksum() {
+    let run = || {
+        let output = payments()
+            .args(["--checksum", "samples/disputes/input.csv"])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        let digest = stderr
+            .lines()
+            .find_map(|line| line.strip_prefix("sha256 "))
+            .unwrap()
+            .to_string();
+        (output.stdout, digest)
+    };
+
+    let (stdout, digest) = run();
+    assert_eq!(stdout, include_bytes!("../samples/disputes/output.csv"));
+    let expected: String = <sha2::Sha256 as sha2::Digest>::digest(&stdout)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    assert_eq!(digest, expected);
+    // The report is ordered by client, so the same input always has the same checksum.
+    assert_eq!(run().1, digest);
+}
+
+#[test]
+fn test_dump_config() {
+    let output = payments()
+        .args(["--dump-config", "--max-open-disputes", "2"])
+        .env("PAYMENTS_ENGINE_DISPUTE_WINDOW", "30d")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let config: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(config["max_open_disputes"], 2);
+    assert_eq!(config["dispute_window"], "30d");
+    assert_eq!(config["on_duplicate_dispute"], "ignore");
+
+    assert_eq!(config["client_tiers"], serde_json::json!({}));
+
+    // The client tiers are included, and checked against the config.
+    let mut tiers = tempfile::NamedTempFile::new().unwrap();
+    write!(tiers, "client,tier\n2,gold\n1,gold\n").unwrap();
+    let mut config_file = tempfile::NamedTempFile::new().unwrap();
+    write!(
+        config_file,
+        "[engine.tier_limits.gold]\nmax_deposit = \"100\"\n"
+    )
+    .unwrap();
+    let output = payments()
+        .arg("--dump-config")
+        .arg("--tiers")
+        .arg(tiers.path())
+        .arg("--config")
+        .arg(config_file.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let config: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        config["client_tiers"],
+        serde_json::json!({"1": "gold", "2": "gold"})
+    );
+    payments()
+        .arg("--dump-config")
+        .arg("--tiers")
+        .arg(tiers.path())
+        .assert()
+        .failure();
+
+    // The rates file is only read to process transactions.
+    payments()
+        .args(["--dump-config", "--rates", "missing-rates.csv"])
+        .args(["--report-currency", "USD"])
+        .assert()
+        .success();
+}